@@ -0,0 +1,40 @@
+// libFuzzer entry point for the packet decoders. Build with
+//   cmake -DROLLBACK_BUILD_FUZZERS=ON -DCMAKE_CXX_COMPILER=clang++ ...
+// and run ./fuzz-decode [corpus_dir]. ASan/UBSan turn any out-of-bounds read or
+// overflow into a crash; malformed input must only ever be rejected.
+#include "compression.h"
+#include "serialization.h"
+#include <cstddef>
+#include <cstdint>
+#include <span>
+#include <stdexcept>
+
+extern "C" int LLVMFuzzerTestOneInput(const uint8_t *data, size_t size) {
+    std::span<const uint8_t> input(data, size);
+
+    // Raw datagrams as they arrive off the wire
+    std::vector<uint8_t> decompressed;
+    try {
+        decompressed = rollback::decompressPacket(input);
+    } catch (const std::runtime_error &) {
+        decompressed.clear();
+    }
+    try {
+        (void)rollback::decompressPacket(input, 1024, true);
+    } catch (const std::runtime_error &) {
+    }
+
+    for (auto buffer : { input, std::span<const uint8_t>(decompressed) }) {
+        for (size_t inputWidth : { size_t{ 4 }, size_t{ 8 } }) {
+            (void)rollback::parseClientMessage(buffer, inputWidth);
+            (void)rollback::parseClientMessage(buffer, inputWidth, nullptr, rollback::InputEncoding::RunLength);
+            for (int maxPlayers = 1; maxPlayers <= 8; maxPlayers *= 2) {
+                for (uint16_t capabilities : { uint16_t{ 0 }, rollback::CAPABILITY_SESSION_NONCE }) {
+                    (void)rollback::parseServerMessage(buffer, maxPlayers, inputWidth, capabilities);
+                    (void)rollback::parseServerMessages(buffer, maxPlayers, inputWidth, capabilities);
+                }
+            }
+        }
+    }
+    return 0;
+}
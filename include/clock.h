@@ -0,0 +1,49 @@
+#pragma once
+
+#include <atomic>
+#include <chrono>
+
+namespace rollback
+{
+
+    /**
+     * Source of "now" for timing code, so it can be driven by a virtual clock
+     * when replaying a recording instead of by the wall clock.
+     */
+    class Clock
+    {
+    public:
+        using time_point = std::chrono::steady_clock::time_point;
+
+        virtual ~Clock() = default;
+        virtual time_point now() const = 0;
+        // Whether now() moves on its own; a virtual clock only moves when its driver moves it
+        virtual bool realTime() const { return true; }
+    };
+
+    // The real thing: std::chrono::steady_clock
+    class SteadyClock : public Clock
+    {
+    public:
+        time_point now() const override { return std::chrono::steady_clock::now(); }
+    };
+
+    // Stands still until told to move; safe to read while another thread moves it
+    class ManualClock : public Clock
+    {
+    public:
+        explicit ManualClock(time_point start = time_point{}) : now_(start.time_since_epoch().count()) {}
+
+        time_point now() const override { return time_point(time_point::duration(now_.load())); }
+        bool realTime() const override { return false; }
+        void advance(std::chrono::nanoseconds by)
+        {
+            now_ += std::chrono::duration_cast<time_point::duration>(by).count();
+        }
+        void set(time_point to) { now_ = to.time_since_epoch().count(); }
+
+    private:
+        std::atomic<time_point::rep> now_;
+    };
+
+} // namespace rollback
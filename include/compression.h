@@ -8,26 +8,52 @@
 namespace rollback
 {
 
+    constexpr size_t CHECKSUM_SIZE = 4; // trailing CRC32, little endian, over the compressed bytes
+
+    // Thrown by decompressPacket when a packet's trailing CRC32 doesn't match its contents
+    class ChecksumMismatch : public std::runtime_error
+    {
+    public:
+        using std::runtime_error::runtime_error;
+    };
+
     /**
      * Compresses a buffer using an 8-byte zero-suppression bitmask algorithm,
      * writing into a buffer.
      *
      * @param input The data to compress
+     * @param appendChecksum Append a CRC32 of the compressed data (see CHECKSUM_SIZE)
      * @return Vector containing the compressed data
      * @throws std::runtime_error If the compressed output would exceed 1024 bytes
      */
-    std::vector<uint8_t> compressPacket(std::span<const uint8_t> input);
+    std::vector<uint8_t> compressPacket(std::span<const uint8_t> input, bool appendChecksum = false);
+
+    // compressPacket into a caller-owned buffer, which is cleared first; reusing one
+    // buffer avoids an allocation per packet. Output is identical to compressPacket's.
+    void compressPacketInto(std::span<const uint8_t> input, std::vector<uint8_t>& output, bool appendChecksum = false);
 
     /**
      * Decompresses a buffer that was compressed with the zero-suppression bitmask algorithm.
      *
      * @param compressedBuffer The compressed input (mask + non-zero bytes)
-     * @param originalLength The expected length of the decompressed data
-     * @return Vector containing the decompressed data
+     * @param originalLength The most bytes to decompress
+     * @param verifyChecksum The buffer ends in a CRC32 that must match the rest of it
+     * @return Vector containing the decompressed data: as many bytes as the masks
+     *         describe (a multiple of 8 unless cut short), never more than originalLength
+     * @throws ChecksumMismatch If verifyChecksum is set and the CRC32 is missing or wrong
      * @throws std::runtime_error If the compressed data is malformed or the decompressed
      *         output would overflow 1024 bytes
      */
     std::vector<uint8_t> decompressPacket(std::span<const uint8_t> compressedBuffer,
-                                          size_t originalLength = 1024);
+                                          size_t originalLength = 1024,
+                                          bool verifyChecksum = false);
+
+    // decompressPacket into a caller-owned buffer, which is cleared first; its contents
+    // are unspecified if this throws. Output is identical to decompressPacket's.
+    void decompressPacketInto(std::span<const uint8_t> compressedBuffer, std::vector<uint8_t>& output,
+                              size_t originalLength = 1024, bool verifyChecksum = false);
+
+    // True if the buffer ends in a CRC32 of everything before it
+    bool hasValidChecksum(std::span<const uint8_t> packet);
 
 } // namespace rollback
\ No newline at end of file
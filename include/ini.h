@@ -0,0 +1,25 @@
+#pragma once
+
+#include <istream>
+#include <map>
+#include <optional>
+#include <string>
+
+namespace rollback
+{
+
+    // key -> value from an ini file; later duplicates win
+    using IniValues = std::map<std::string, std::string>;
+
+    /**
+     * Reads `key=value` lines. Keys and values are trimmed and a value wrapped in
+     * matching double or single quotes loses them. Blank lines, comments (';' or '#')
+     * and [section] headers are skipped, as is any line without '='; sections don't
+     * namespace keys.
+     */
+    IniValues parseIni(std::istream& in);
+
+    // parseIni on a file, or nullopt if it can't be opened
+    std::optional<IniValues> loadIniFile(const std::string& path);
+
+} // namespace rollback
@@ -0,0 +1,36 @@
+#pragma once
+
+#include <cstdint>
+
+namespace rollback
+{
+
+    constexpr uint32_t DEFAULT_MAX_INPUT_BUFFER_FRAMES = 8;
+
+    // Inter-arrival jitter estimate for one player's input packets
+    struct JitterState
+    {
+        bool initialized = false;
+        float meanIntervalMs = 0.0f; // smoothed gap between consecutive input packets
+        float jitterMs = 0.0f;       // smoothed deviation of the gap from that mean
+    };
+
+    /**
+     * Folds the gap between two consecutive input packets into the estimate.
+     * Pure: no I/O and no shared state.
+     *
+     * @param previous The state returned by the previous call (default-constructed for the first)
+     * @param intervalMs Time between this packet and the previous one
+     * @return The updated state
+     */
+    JitterState updateJitter(const JitterState& previous, float intervalMs);
+
+    /**
+     * Frames of input a player should keep buffered ahead of the server to absorb
+     * its jitter: enough to cover two deviations of `frameTimeMs` frames, clamped to
+     * [0, maxFrames].
+     */
+    uint32_t inputBufferFrames(float jitterMs, uint32_t maxFrames = DEFAULT_MAX_INPUT_BUFFER_FRAMES,
+                               float frameTimeMs = 1000.0f / 60.0f);
+
+} // namespace rollback
@@ -0,0 +1,73 @@
+#pragma once
+
+#include <cstdint>
+#include <optional>
+#include <sstream>
+#include <string>
+#include <utility>
+
+namespace rollback
+{
+
+    enum class LogLevel : uint8_t
+    {
+        Debug,
+        Info,
+        Warn,
+        Error
+    };
+
+    // Fields attached to a log line so output can be filtered per match or player
+    struct LogContext
+    {
+        std::string matchId;
+        std::optional<uint16_t> playerIndex;
+        std::string src; // "ip:port" of the remote peer, if relevant
+    };
+
+    /**
+     * Writes a single logfmt-style line, e.g.
+     *   ts=2025-01-01T12:00:00.123Z level=info match_id=abc player_index=1 msg="Player joined"
+     * Empty context fields are omitted. Warn and Error go to stderr, the rest to stdout.
+     */
+    void logMessage(LogLevel level, const LogContext& ctx, const std::string& message);
+
+    // Lines below this level are dropped before formatting (default: Info)
+    void setLogLevel(LogLevel level);
+    bool isLogEnabled(LogLevel level);
+
+    /**
+     * One-call setup for embedders: sets the level and, if `logFile` is non-empty,
+     * appends every line to that file instead of stdout/stderr. Safe to call again
+     * to change either; an empty `logFile` goes back to the console.
+     *
+     * @return false if the file couldn't be opened (output stays where it was)
+     */
+    bool initLogging(LogLevel level, const std::string& logFile = {});
+
+    // "debug", "info", "warn" or "error"; nullopt for anything else
+    std::optional<LogLevel> parseLogLevel(const std::string& name);
+
+    template <typename... Args>
+    void log(LogLevel level, const LogContext& ctx, Args&&... args)
+    {
+        if (!isLogEnabled(level))
+            return;
+        std::ostringstream oss;
+        (oss << ... << std::forward<Args>(args));
+        logMessage(level, ctx, oss.str());
+    }
+
+    template <typename... Args>
+    void logDebug(const LogContext& ctx, Args&&... args) { log(LogLevel::Debug, ctx, std::forward<Args>(args)...); }
+
+    template <typename... Args>
+    void logInfo(const LogContext& ctx, Args&&... args) { log(LogLevel::Info, ctx, std::forward<Args>(args)...); }
+
+    template <typename... Args>
+    void logWarn(const LogContext& ctx, Args&&... args) { log(LogLevel::Warn, ctx, std::forward<Args>(args)...); }
+
+    template <typename... Args>
+    void logError(const LogContext& ctx, Args&&... args) { log(LogLevel::Error, ctx, std::forward<Args>(args)...); }
+
+} // namespace rollback
@@ -0,0 +1,83 @@
+#pragma once
+
+#include <cstdint>
+#include <optional>
+#include <string>
+#include <vector>
+
+namespace rollback
+{
+
+    // HTTP match configuration structures
+    struct MVSIPlayer {
+        uint16_t player_index;
+        std::string ip;
+        bool is_host;
+        uint16_t team_index = 0; // players sharing a team_index are on the same side
+    };
+
+    // How a match hands out inputs. Rollback forwards whatever has arrived and
+    // predicts the gaps; InputDelay holds each frame back until every player has
+    // sent it, so clients never need to roll back
+    enum class NetcodeMode : uint8_t
+    {
+        Rollback,
+        InputDelay
+    };
+
+    struct MVSIMatchConfig {
+        uint8_t max_players;
+        uint32_t match_duration;
+        std::vector<MVSIPlayer> players;
+        NetcodeMode netcode_mode = NetcodeMode::Rollback;
+        uint8_t input_delay = 0; // frames a complete frame is held before release (InputDelay only)
+    };
+
+    // Whether the clients' MatchResult reports settled who won
+    enum class MatchResultStatus : uint8_t
+    {
+        Unreported, // nobody sent one
+        Decided,    // more than half of the match's slots named the same team
+        Disputed    // reports came in, but no team has a majority (cheating or a desync)
+    };
+
+    struct MatchOutcome {
+        MatchResultStatus status = MatchResultStatus::Unreported;
+        uint8_t winning_team_index = 0; // only meaningful when Decided
+        uint8_t reports = 0;            // players whose MatchResult was counted
+    };
+
+    /**
+     * The service that owns matchmaking. The server asks it for a match's roster
+     * the first time the match id is seen (and again, as registerMatch, while that
+     * roster is empty) and tells it when the match is over.
+     * Both calls block and are made from io_context threads.
+     */
+    class MatchBackend
+    {
+    public:
+        virtual ~MatchBackend() = default;
+
+        // Roster and settings for a match, or nullopt if the backend doesn't know it
+        virtual std::optional<MVSIMatchConfig> registerMatch(const std::string& matchId, const std::string& key) = 0;
+
+        virtual void endMatch(const std::string& matchId, const std::string& key, const MatchOutcome& outcome) = 0;
+    };
+
+    // The MVSI web API: POST {endpoint}/mvsi_register and {endpoint}/mvsi_end_match
+    class HttpMatchBackend : public MatchBackend
+    {
+    public:
+        explicit HttpMatchBackend(std::string endpoint);
+
+        std::optional<MVSIMatchConfig> registerMatch(const std::string& matchId, const std::string& key) override;
+        void endMatch(const std::string& matchId, const std::string& key, const MatchOutcome& outcome) override;
+
+    private:
+        // Response body, or nullopt (already logged) if the request failed. `body` is JSON
+        std::optional<std::string> post(const std::string& path, const std::string& matchId, const std::string& body);
+
+        std::string endpoint_;
+    };
+
+} // namespace rollback
@@ -0,0 +1,22 @@
+#pragma once
+
+#include "match_backend.h"
+#include <cstdint>
+#include <map>
+
+namespace rollback
+{
+
+    /**
+     * Settles the winner from the clients' MatchResult reports. Pure: no I/O and no shared state.
+     *
+     * A team wins only with the votes of more than half of the match's slots, not just
+     * of the reports, so one client can't decide a match the others left without reporting.
+     *
+     * @param reportedWinners Player index -> winning team that player's MatchResult named
+     * @param maxPlayers The match's slot count
+     * @return Unreported without reports, otherwise Decided or Disputed
+     */
+    MatchOutcome tallyMatchResult(const std::map<uint16_t, uint8_t>& reportedWinners, int maxPlayers);
+
+} // namespace rollback
@@ -6,9 +6,42 @@
 #include <array>
 #include <map>
 #include <memory>
+#include <optional>
+#include <ostream>
 
 namespace rollback {
 
+constexpr size_t DEFAULT_INPUT_WIDTH = 4; // bytes of input per frame on the wire (one u32)
+constexpr size_t MAX_INPUT_WIDTH = 16;
+
+// One frame of one player's input as little-endian bytes. Only the first
+// `inputWidth` bytes (see ServerConfig) go on the wire; the rest stay zero.
+struct FrameInput {
+    std::array<uint8_t, MAX_INPUT_WIDTH> bytes{};
+
+    FrameInput() = default;
+    FrameInput(uint32_t value) { // the common 4-byte case
+        for (size_t i = 0; i < 4; ++i) {
+            bytes[i] = static_cast<uint8_t>(value >> (i * 8));
+        }
+    }
+
+    bool operator==(const FrameInput&) const = default;
+};
+
+inline std::ostream& operator<<(std::ostream& os, const FrameInput& input) {
+    static const char digits[] = "0123456789abcdef";
+    size_t top = MAX_INPUT_WIDTH;
+    while (top > 4 && input.bytes[top - 1] == 0) {
+        --top;
+    }
+    os << "0x";
+    for (size_t i = top; i-- > 0;) {
+        os << digits[input.bytes[i] >> 4] << digits[input.bytes[i] & 0xF];
+    }
+    return os;
+}
+
 // Client message types
 enum class ClientMessageType : uint8_t {
     NewConnection = 1,
@@ -18,22 +51,25 @@ enum class ClientMessageType : uint8_t {
     QualityData = 5,
     Disconnecting = 6,
     PlayerDisconnectedAck = 7,
-    ReadyToStartMatch = 8
+    ReadyToStartMatch = 8,
+    SpectatorConnection = 9 // same payload as NewConnection; receives inputs but sends none
 };
 
-// Server message types
+// Server message types. 5 is unused by the client; anything not listed here is
+// rejected by serializeServerMessage/parseServerMessage rather than guessed at.
 enum class ServerMessageType : uint8_t {
-    NewConnectionReply = 1,
-    StartGame = 2,
-    InputAck = 3,
-    PlayerInput = 4,
-    RequestQualityData = 6,
-    PlayersStatus = 7,
-    Kick = 8,
-    ChecksumAck = 9,
-    PlayersConfigurationData = 10,
-    PlayerDisconnected = 11,
-    ChangePort = 12
+    NewConnectionReply = 1,       // NewConnectionReplyPayload
+    StartGame = 2,                // no payload
+    InputAck = 3,                 // InputAckPayload: last frame of the client's own input the server holds
+    PlayerInput = 4,              // PlayerInputPayload
+    RequestQualityData = 6,       // RequestQualityDataPayload, client answers with QualityData
+    PlayersStatus = 7,            // PlayersStatusPayload: average ping of every slot, shown in the HUD
+    Kick = 8,                     // KickPayload
+    ChecksumAck = 9,              // ChecksumAckPayload: last frame whose checksum the server has verified
+    PlayersConfigurationData = 10, // PlayersConfigurationDataPayload
+    PlayerDisconnected = 11,      // PlayerDisconnectedPayload
+    ChangePort = 12,              // ChangePortPayload: client should resend to this port from now on
+    Batch = 0xF0                  // several messages in one datagram (see serializeBatch); no header of its own
 };
 
 // Client message header
@@ -74,10 +110,17 @@ struct InputPayload {
     uint32_t clientFrame;
     uint8_t numFrames;
     uint8_t numChecksums;
-    std::vector<uint32_t> inputPerFrame;
+    std::vector<FrameInput> inputPerFrame;
     std::vector<uint32_t> checksumPerFrame;
 };
 
+// How InputPayload::inputPerFrame is laid out on the wire. Either way numFrames
+// counts frames and parseClientMessage hands back one input per frame.
+enum class InputEncoding : uint8_t {
+    Raw,      // numFrames inputs back to back
+    RunLength // runs of (count:uint8, input) until numFrames frames are covered; a count of 0 is malformed
+};
+
 // Player input ack payload
 struct PlayerInputAckPayload {
     uint8_t numPlayers;
@@ -112,6 +155,12 @@ struct ReadyToStartMatchPayload {
     uint8_t ready;
 };
 
+// Values for NewConnectionReplyPayload::success
+enum class ConnectionResult : uint8_t {
+    Success = 0,
+    NotInMatch = 1 // player index isn't on the backend's roster for this match
+};
+
 // Server message payloads
 struct NewConnectionReplyPayload {
     uint8_t success;
@@ -120,6 +169,7 @@ struct NewConnectionReplyPayload {
     uint32_t matchDurationInFrames;
     uint8_t unknown;
     uint8_t isValidationServerDebugMode;
+    std::optional<uint32_t> sessionNonce; // appended for CAPABILITY_SESSION_NONCE clients only
 };
 
 struct InputAckPayload {
@@ -136,7 +186,7 @@ struct PlayerInputPayload {
     int16_t packetsLossPercent;
     float rift;
     uint32_t checksumAckFrame;
-    std::vector<std::vector<uint32_t>> inputPerFrame;
+    std::vector<std::vector<FrameInput>> inputPerFrame;
 };
 
 struct RequestQualityDataPayload {
@@ -153,6 +203,18 @@ struct PlayersStatusPayload {
     std::vector<PlayerStatusData> status;
 };
 
+// Values for KickPayload::reason
+enum class KickReason : uint16_t {
+    VersionMismatch = 1,     // param1 carries the capabilities the server grants (with CAPABILITIES_VERSION_FLAG)
+    InvalidKey = 2,          // the match key doesn't match the one the match was registered with
+    ReadyTimeout = 3,        // the player (or, when the match is aborted, someone) never readied up
+    MatchUnavailable = 4,    // the backend doesn't know the match, or its config can't be hosted here
+    MatchAlreadyStarted = 5, // a slot not yet in the match connected after the players were configured; param1 carries the server frame
+    MatchFull = 6,           // every slot is taken; param1 carries max_players
+    SlotTaken = 7,           // the slot is held from another address, and this one isn't the roster's for it
+    InputHistoryLost = 8     // the client's acks fell behind inputs the server no longer holds; param1 carries the player index
+};
+
 struct KickPayload {
     uint16_t reason;
     uint32_t param1;
@@ -164,9 +226,19 @@ struct ChecksumAckPayload {
 
 struct PlayersConfigurationDataPayload {
     uint8_t numPlayers;
-    std::vector<uint16_t> configValues; // simplified from the original raw buffer
+    std::vector<uint16_t> configValues; // one word per player slot, see playerConfigValue()
 };
 
+// Config word for a player slot, one per slot in PlayersConfigurationData:
+//   bits 15..8  player index (the slot itself)
+//   bits  7..0  team; slots with the same team fight on the same side
+// So a 2v2 with teams alternating by index is 0, 257, 512, 769, and one with
+// players 0 and 1 against 2 and 3 is 0, 256, 513, 769. A team above 255 is cut
+// to its low byte.
+constexpr uint16_t playerConfigValue(uint16_t playerIndex, uint16_t teamId) {
+    return static_cast<uint16_t>((playerIndex << 8) | (teamId & 0xFF));
+}
+
 struct PlayerDisconnectedPayload {
     uint8_t playerIndex;
     uint8_t shouldAITakeControl;
@@ -193,9 +265,36 @@ struct ServerMessage {
     virtual ~ServerMessage() = default;
 };
 
+// True if `sequence` comes after `last`, treating the u32 space as circular so a
+// long match that wraps past 0xFFFFFFFF keeps being accepted. Anything up to
+// 2^31 ahead counts as newer; anything else is stale.
+constexpr bool isSequenceNewer(uint32_t sequence, uint32_t last) {
+    return static_cast<int32_t>(sequence - last) > 0;
+}
+
 // Constants
+constexpr uint16_t SUPPORTED_PROTOCOL_VERSION = 1; // NewConnectionPayload::messageVersion we expect; others are logged, not refused
+// A messageVersion with this bit set isn't a version number: the bits below it are
+// the CAPABILITY_* extensions the client asks for. They combine freely, and what the
+// connection packet asks for holds both ways for the whole connection.
+constexpr uint16_t CAPABILITIES_VERSION_FLAG = 0x8000;
+constexpr uint16_t CAPABILITY_CHECKSUMS = 0x0001;    // every packet both ways ends in a CRC32 of the codec's output, so not with CAPABILITY_UNCOMPRESSED
+constexpr uint16_t CAPABILITY_UNCOMPRESSED = 0x0002; // sent raw both ways (no zero-suppression codec)
+// NewConnectionReply ends in the match's session nonce (u32), and every later
+// client packet repeats it right after the header, so a packet lifted from one
+// match is dropped by another
+constexpr uint16_t CAPABILITY_SESSION_NONCE = 0x0004;
+constexpr size_t SESSION_NONCE_SIZE = 4;
+// Input messages carry runs instead of one input per frame (see InputEncoding::RunLength)
+constexpr uint16_t CAPABILITY_RLE_INPUTS = 0x0008;
+
+// The CAPABILITY_* bits a connection's messageVersion asks for; none for a plain version number
+constexpr uint16_t protocolCapabilities(uint16_t messageVersion) {
+    return (messageVersion & CAPABILITIES_VERSION_FLAG) ? static_cast<uint16_t>(messageVersion & ~CAPABILITIES_VERSION_FLAG) : 0;
+}
 constexpr uint16_t GAME_SERVER_PORT = 41234;
 constexpr int MAX_PLAYERS = 2;
+constexpr int MAX_MATCH_PLAYERS = 8; // upper bound on the backend's max_players; larger matches are rejected
 constexpr bool EMULATE_P2 = false;
 
 } // namespace rollback
\ No newline at end of file
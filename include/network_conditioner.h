@@ -0,0 +1,43 @@
+#pragma once
+
+#include <chrono>
+#include <cstdint>
+#include <mutex>
+#include <optional>
+#include <random>
+
+namespace rollback
+{
+
+    // Artificial network trouble for local testing; all zero means a clean link
+    struct NetworkConditions
+    {
+        uint32_t latencyMs = 0; // added to every datagram
+        uint32_t jitterMs = 0;  // plus a uniform 0..jitterMs on top
+        double dropRate = 0.0;  // probability in [0, 1] that a datagram is discarded
+
+        bool enabled() const { return latencyMs > 0 || jitterMs > 0 || dropRate > 0.0; }
+    };
+
+    /**
+     * Decides the fate of each datagram passing through the server's socket when
+     * NetworkConditions are configured. Applied once on receive and once on send,
+     * so the conditions hit both directions. Thread-safe.
+     */
+    class NetworkConditioner
+    {
+    public:
+        explicit NetworkConditioner(NetworkConditions conditions, uint32_t seed = std::random_device{}());
+
+        bool enabled() const { return conditions_.enabled(); }
+
+        // How long to hold the datagram back, or nullopt to drop it
+        std::optional<std::chrono::milliseconds> delayFor();
+
+    private:
+        NetworkConditions conditions_;
+        std::mutex mutex_;
+        std::mt19937 rng_;
+    };
+
+} // namespace rollback
@@ -0,0 +1,118 @@
+#pragma once
+
+#include "message_types.h"
+#include <algorithm>
+#include <cstdint>
+#include <span>
+#include <string>
+#include <string_view>
+#include <vector>
+
+namespace rollback {
+
+// Every multi-byte field on the wire is little endian; these are the only places
+// that know it, so serializers never shift bytes by hand.
+
+/**
+ * Appends fields to a growing buffer.
+ */
+class PacketWriter {
+public:
+    void u8(uint8_t value) { buffer_.push_back(value); }
+    void u16le(uint16_t value) { writeLe(value); }
+    void u32le(uint32_t value) { writeLe(value); }
+    void i16le(int16_t value) { writeLe(static_cast<uint16_t>(value)); }
+
+    void bytes(std::span<const uint8_t> data) { buffer_.insert(buffer_.end(), data.begin(), data.end()); }
+
+    // The first `width` bytes of an input frame
+    void input(const FrameInput& input, size_t width) {
+        buffer_.insert(buffer_.end(), input.bytes.begin(), input.bytes.begin() + width);
+    }
+
+    // Exactly `len` bytes: `s` cut to `len` and zero padded. A string of exactly
+    // `len` bytes has no terminator, which PacketReader::stringFixed accepts.
+    void stringFixed(std::string_view s, size_t len) {
+        const size_t n = std::min(s.size(), len);
+        buffer_.insert(buffer_.end(), s.begin(), s.begin() + n);
+        buffer_.insert(buffer_.end(), len - n, 0);
+    }
+
+    size_t size() const { return buffer_.size(); }
+    void reserve(size_t n) { buffer_.reserve(n); }
+    std::vector<uint8_t> take() { return std::move(buffer_); }
+
+private:
+    template<typename T>
+    void writeLe(T value) {
+        for (size_t i = 0; i < sizeof(T); ++i) {
+            buffer_.push_back(static_cast<uint8_t>((value >> (i * 8)) & 0xFF));
+        }
+    }
+
+    std::vector<uint8_t> buffer_;
+};
+
+/**
+ * Reads fields front to back. Reading past the end yields zeros rather than
+ * failing, which the lenient client parser relies on; check has() first where a
+ * short buffer must be rejected.
+ */
+class PacketReader {
+public:
+    explicit PacketReader(std::span<const uint8_t> buffer) : buffer_(buffer) {}
+
+    size_t offset() const { return offset_; }
+    size_t remaining() const { return offset_ < buffer_.size() ? buffer_.size() - offset_ : 0; }
+    bool has(size_t n) const { return n <= remaining(); }
+    void skip(size_t n) { offset_ += n; }
+
+    uint8_t u8() { return readLe<uint8_t>(); }
+    uint16_t u16le() { return readLe<uint16_t>(); }
+    uint32_t u32le() { return readLe<uint32_t>(); }
+    int16_t i16le() { return static_cast<int16_t>(readLe<uint16_t>()); }
+
+    FrameInput input(size_t width) {
+        FrameInput input;
+        for (size_t i = 0; i < width; ++i) {
+            input.bytes[i] = u8();
+        }
+        return input;
+    }
+
+    // A `len`-byte field holding text up to the first zero byte (or all `len` bytes).
+    // Always consumes `len` bytes; false if anything but printable ASCII precedes the
+    // terminator (control characters, or bytes >= 0x80 that aren't valid text on their own).
+    bool stringFixed(size_t len, std::string& out) {
+        out.clear();
+        for (size_t i = 0; i < len; ++i) {
+            const uint8_t c = offset_ + i < buffer_.size() ? buffer_[offset_ + i] : 0;
+            if (c == 0) {
+                break;
+            }
+            if (c < 0x20 || c >= 0x7F) {
+                return false;
+            }
+            out.push_back(static_cast<char>(c));
+        }
+        offset_ += len;
+        return true;
+    }
+
+private:
+    template<typename T>
+    T readLe() {
+        T value = 0;
+        for (size_t i = 0; i < sizeof(T); ++i, ++offset_) {
+            if (offset_ < buffer_.size()) {
+                value |= static_cast<T>(static_cast<T>(buffer_[offset_]) << (i * 8));
+            }
+        }
+        return value;
+    }
+
+    std::span<const uint8_t> buffer_;
+    size_t offset_ = 0;
+};
+
+} // namespace rollback
@@ -0,0 +1,25 @@
+#pragma once
+
+#include "threadSafeMap.h"
+#include <chrono>
+#include <cstdint>
+#include <optional>
+
+namespace rollback
+{
+
+    // Pings awaiting a reply, by the server message sequence they went out under
+    using PendingPings = ThreadSafeMap<uint32_t, std::chrono::steady_clock::time_point>;
+
+    /**
+     * Claims the ping filed under `sequence` and measures its round trip. Both a
+     * QualityData and an input ack echo the sequence they answer, so the same ping
+     * can come back twice; only the first to arrive gets the sample. Thread-safe.
+     *
+     * @return The round trip in ms, capped to 255 (negative if `now` is before the
+     *         send), or nullopt if the ping was never filed or is already claimed
+     */
+    std::optional<int16_t> takePingSample(PendingPings& pending, uint32_t sequence,
+                                          std::chrono::steady_clock::time_point now);
+
+} // namespace rollback
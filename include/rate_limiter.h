@@ -0,0 +1,43 @@
+#pragma once
+
+#include <chrono>
+#include <mutex>
+#include <string>
+#include <unordered_map>
+
+namespace rollback
+{
+
+    /**
+     * Per-key token bucket. Each key may burst up to `burst` requests and then
+     * refills at `ratePerSecond`. Used to stop a single source address from
+     * turning a flood of connection packets into a flood of backend calls.
+     */
+    class RateLimiter
+    {
+    public:
+        RateLimiter(double ratePerSecond, double burst);
+
+        // Takes a token for `key`; false if the bucket is empty
+        bool allow(const std::string& key,
+                   std::chrono::steady_clock::time_point now = std::chrono::steady_clock::now());
+
+        // Forgets keys whose bucket has refilled completely
+        void prune(std::chrono::steady_clock::time_point now = std::chrono::steady_clock::now());
+
+    private:
+        struct Bucket
+        {
+            double tokens;
+            std::chrono::steady_clock::time_point lastRefill;
+        };
+
+        double refill(Bucket& bucket, std::chrono::steady_clock::time_point now) const;
+
+        double ratePerSecond_;
+        double burst_;
+        std::mutex mutex_;
+        std::unordered_map<std::string, Bucket> buckets_;
+    };
+
+} // namespace rollback
@@ -0,0 +1,78 @@
+#pragma once
+
+#include <chrono>
+#include <cstdint>
+#include <fstream>
+#include <mutex>
+#include <optional>
+#include <span>
+#include <string>
+#include <vector>
+
+namespace rollback
+{
+
+    enum class ReplayDirection : uint8_t
+    {
+        ClientToServer = 0,
+        ServerToClient = 1
+    };
+
+    constexpr uint8_t REPLAY_NO_PLAYER = 0xFF; // spectators, and every record of a version 1 file
+
+    // One datagram exactly as it crossed the socket (still compressed)
+    struct ReplayRecord
+    {
+        std::chrono::microseconds offset; // time since the recording started
+        ReplayDirection direction;
+        uint8_t playerIndex;              // sender or recipient, or REPLAY_NO_PLAYER
+        std::vector<uint8_t> datagram;
+    };
+
+    /**
+     * Appends every datagram of a match to a file so desyncs can be replayed offline.
+     *
+     * File layout (all little endian):
+     *   header: "MVSR" magic, uint16 format version
+     *   record: uint64 offset in microseconds, uint8 direction, uint8 player index,
+     *           uint16 length, length bytes
+     * Version 1 files lack the player index.
+     *
+     * Safe to call from multiple threads.
+     */
+    class ReplayRecorder
+    {
+    public:
+        explicit ReplayRecorder(const std::string& path);
+
+        bool isOpen() const { return out_.is_open(); }
+        void record(ReplayDirection direction, uint8_t playerIndex, std::span<const uint8_t> datagram);
+
+    private:
+        std::mutex mutex_;
+        std::ofstream out_;
+        std::chrono::steady_clock::time_point start_;
+    };
+
+    /**
+     * Reads back a file written by ReplayRecorder in recording order.
+     * Use decompressPacket() and parseClientMessage() to decode ClientToServer records.
+     */
+    class ReplayReader
+    {
+    public:
+        explicit ReplayReader(const std::string& path);
+
+        // False if the file is missing or doesn't start with a valid header
+        bool isValid() const { return valid_; }
+
+        // Next record, or nullopt at end of file (or on a truncated record)
+        std::optional<ReplayRecord> next();
+
+    private:
+        std::ifstream in_;
+        uint16_t version_ = 0;
+        bool valid_ = false;
+    };
+
+} // namespace rollback
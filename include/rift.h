@@ -0,0 +1,58 @@
+#pragma once
+
+#include <cstdint>
+
+namespace rollback
+{
+
+    constexpr float DEFAULT_RIFT_CLAMP = 20.0f; // frames either side of the server
+    constexpr float PING_ALPHA = 0.1f;          // 0.1 means 10% of the new sample, 90% of the old
+
+    // Rift is how many frames a client is ahead of where the server expects it to be:
+    // its reported frame, plus the half round trip since it sent that, minus the
+    // server's frame. Positive means the client runs ahead and should slow down;
+    // negative means it lags and should speed up. PlayerInputPayload::rift carries
+    // it with the same sign, as rift * 100 in an int16.
+    //
+    // Rift estimate for one player, carried from one update to the next
+    struct RiftState
+    {
+        bool initialized = false;
+        float smoothRift = 0.0f; // smoothed value sent to the client
+        float rawRift = 0.0f;    // last unsmoothed sample
+    };
+
+    /**
+     * Advances the rift estimate with a new sample. Pure: no I/O and no shared state.
+     *
+     * @param previous The state returned by the previous call (default-constructed for the first)
+     * @param smoothedPingMs Smoothed round-trip time to the client
+     * @param lastClientFrame Latest frame the client reported simulating
+     * @param serverFrame The server's current frame
+     * @param minRift Lower bound for smoothRift; must be less than maxRift
+     * @param maxRift Upper bound for smoothRift
+     * @param frameTimeMs Length of one frame at the match's tick rate
+     * @return The updated state, with smoothRift clamped to [minRift, maxRift]
+     */
+    RiftState updateRift(const RiftState& previous, float smoothedPingMs, uint32_t lastClientFrame, uint32_t serverFrame,
+                         float minRift = -DEFAULT_RIFT_CLAMP, float maxRift = DEFAULT_RIFT_CLAMP,
+                         float frameTimeMs = 1000 / 60);
+
+    /**
+     * Folds a round-trip sample into the smoothed ping. The first sample is taken as is.
+     *
+     * @param previous The smoothed ping so far (ignored when !initialized)
+     * @param initialized Whether previous holds a real value yet
+     * @param sampleMs The new round-trip time, already capped to 255
+     * @return The new smoothed ping, within [-255, 255]
+     */
+    float smoothPing(float previous, bool initialized, float sampleMs);
+
+    // Whether the tick for serverFrame recomputes rift: every tick while the match
+    // settles in (the first 500 frames), once a second after that
+    inline bool isRiftUpdateFrame(uint32_t serverFrame)
+    {
+        return serverFrame % 60 == 0 || serverFrame <= 500;
+    }
+
+} // namespace rollback
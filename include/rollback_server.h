@@ -5,6 +5,15 @@
 
 #include "message_types.h"
 #include "serialization.h"
+#include "replay.h"
+#include "match_backend.h"
+#include "clock.h"
+#include "network_conditioner.h"
+#include "ping_ledger.h"
+#include "jitter.h"
+#include "logging.h"
+#include "rate_limiter.h"
+#include "rift.h"
 #include <asio.hpp>
 #include <asio/experimental/awaitable_operators.hpp>
 #include <memory>
@@ -13,6 +22,8 @@
 #include <mutex>
 #include <shared_mutex>
 #include <map>
+#include <set>
+#include <deque>
 #include <chrono>
 #include <iostream>
 #include <optional>
@@ -26,35 +37,30 @@ namespace rollback
     using namespace asio::experimental::awaitable_operators;
     using namespace std::chrono;
 
-    // HTTP match configuration structures
-    struct MVSIPlayer {
-        uint16_t player_index;
-        std::string ip;
-        bool is_host;
-    };
-
-    struct MVSIMatchConfig {
-        uint8_t max_players;
-        uint32_t match_duration;
-        std::vector<MVSIPlayer> players;
-    };
-
     // Structure to hold player information
     struct PlayerInfo
     {
         bool disconnected = false; // true if player has disconnected
+        std::optional<KickReason> kickedFor; // set by kickPlayer; a kicked player stays disconnected whatever it sends
         std::chrono::steady_clock::time_point lastInputTime; // Last time we received input from this player
         mutable std::shared_mutex mutex;
         asio::ip::address address;
         uint16_t port;
         std::string matchId;
         uint16_t playerIndex;
+        uint16_t teamId = 0;
         uint32_t lastSeqRecv;
+        uint32_t sequenceJumps = 0;                           // packets in a row dropped for jumping past maxSequenceJump
+        uint32_t jumpSequence = 0;                            // sequence of the last of those, which the next must follow on from
         uint32_t lastSeqSent;
         std::vector<uint32_t> ackedFrames;                    // how many frames of each player this client has acked
+        uint32_t lastAckSeq = 0;                              // serverMessageSequenceNumber of the newest PlayerInputAck handled
+        bool hasAckSeq = false;
         bool ready;
 
         std::optional<time_point<steady_clock>> lastSentTime; // timestamp when we last sent a PlayerInput
+        std::atomic<steady_clock::rep> lastAnySendTime{ 0 };   // server clock ticks of the last datagram of any type we sent
+        std::atomic<steady_clock::rep> lastHeardFrom{ 0 };     // server clock ticks of the last packet of any type this client sent
 
         // === NEW FIELDS for ping‐smoothing and deferred rift calculation ===
         float smoothedPing = 0.0f;   // EWMA‐smoothed ping (ms)
@@ -64,15 +70,43 @@ namespace rollback
         bool riftInit = false;
 
         int16_t ping = 0;
+        int16_t packetsLossPercent = 0;
+        std::atomic<uint32_t> pingSamples{ 0 }; // QualityData replies matched to one of our pings
+        uint32_t lossWindowSamples = 0;         // pingSamples when packetsLossPercent was last computed
+        bool checksums = false;                   // connected with CAPABILITY_CHECKSUMS
+        bool compression = true;                  // false when connected with CAPABILITY_UNCOMPRESSED
+        std::optional<uint32_t> sessionNonce;     // the match's nonce when connected with CAPABILITY_SESSION_NONCE
+        InputEncoding inputEncoding = InputEncoding::Raw; // RunLength when connected with CAPABILITY_RLE_INPUTS
+        std::vector<std::vector<uint8_t>> outbox;  // serialized messages waiting for flushOutbox (coalesceMessages)
+        std::mutex outboxMutex;
+        std::deque<std::vector<uint8_t>> sendQueue; // compressed datagrams waiting for drainSendQueue (sendQueueLimit)
+        bool sendQueueDraining = false;             // a drainSendQueue task is running; guarded by sendQueueMutex
+        std::mutex sendQueueMutex;
+        std::atomic<uint32_t> sendQueueDrops{ 0 };  // datagrams dropped because sendQueue was full or their send failed
+        std::atomic<uint32_t> corruptPackets{ 0 }; // CRC failures since packetsLossPercent was last computed
 
         uint32_t lastClientFrame = 0;
         bool     hasNewFrame = false; // Set to true whenever handleClientInput() updates lastClientFrame
 
+        std::optional<time_point<steady_clock>> lastInputArrival; // also the proof a player has started: unset until its first Input
+        std::optional<time_point<steady_clock>> lastStartGameSent; // last StartGame retransmission (tick loop only)
+        std::atomic<uint32_t> framesReceived{ 0 }; // distinct frames of real input stored for this player
+        JitterState jitter;
+
+        uint32_t progressClientFrame = 0; // lastClientFrame as of progressServerFrame
+        uint32_t progressServerFrame = 0; // server frame at which lastClientFrame last moved
+        bool stalled = false;             // lastClientFrame stuck for more than stallTicks
+        uint32_t stallCount = 0;
+        std::atomic<bool> lagging{ false };   // some recipient went laggyMissedInputs ticks without this player's next input
+        std::atomic<uint32_t> lagCount{ 0 };  // times it started lagging this match
+        uint32_t inputBufferFrames = 0; // frames ahead of the server the client is asked to run (adaptive mode)
+
         float rift = 0.0f;
         ThreadSafeMap<uint32_t, uint32_t>  missedInputs;
         // std::map<uint32_t, time_point<steady_clock>> pendingPings;
-        ThreadSafeMap<uint32_t, time_point<steady_clock>> pendingPings;
+        PendingPings pendingPings;
         bool emulated;
+        bool spectator = false; // receives every player's inputs, never sends or acks any
 
         // --- small helper to clamp a float into ±maxRange ---
         static float clampFloat(float in, float maxRange)
@@ -83,6 +117,57 @@ namespace rollback
         }
     };
 
+    // Per-player netcode sample recorded every tick, for plotting how rift evolves
+    struct TelemetrySample
+    {
+        uint32_t serverFrame;
+        uint16_t playerIndex;
+        uint32_t clientFrame;
+        int16_t ping;
+        float rift;
+    };
+
+    constexpr size_t MAX_TELEMETRY_SAMPLES = 10000; // oldest samples are dropped beyond this
+
+    // Lifecycle of a match: players connect and ping until everyone is ready,
+    // then StartGame moves it to InProgress until all players have left
+    enum class MatchPhase : uint8_t
+    {
+        WaitingForPlayers,
+        InProgress,
+        Ended
+    };
+
+    const char* matchPhaseName(MatchPhase phase);
+
+    // Per-match datagram accounting. Sizes are on-the-wire (compressed) unless noted;
+    // received packets are zero-padded before compression, so only their wire size means anything
+    struct TrafficCounters
+    {
+        std::atomic<uint64_t> packetsSent{ 0 };
+        std::atomic<uint64_t> bytesSent{ 0 };
+        std::atomic<uint64_t> uncompressedBytesSent{ 0 };
+        std::atomic<uint64_t> packetsReceived{ 0 };
+        std::atomic<uint64_t> bytesReceived{ 0 };
+
+        // Rolling throughput, refreshed about once a second by the maintenance loop
+        std::atomic<uint64_t> sendBytesPerSec{ 0 };
+        std::atomic<uint64_t> recvBytesPerSec{ 0 };
+        uint64_t rateBytesSent = 0;
+        uint64_t rateBytesReceived = 0;
+        steady_clock::time_point rateSampleTime = steady_clock::now();
+    };
+
+    // A PlayerDisconnected broadcast; its position in MatchState::disconnectNotices is
+    // the playerDisconnectedArrayIndex clients echo back in PlayerDisconnectedAck
+    struct DisconnectNotice
+    {
+        PlayerDisconnectedPayload payload;
+        std::set<std::string> unacked; // keys of recipients that haven't acked yet
+        uint32_t attempts = 1;
+        steady_clock::time_point lastSent = steady_clock::now();
+    };
+
     // Structure to hold match state
     struct MatchState
     {
@@ -90,40 +175,262 @@ namespace rollback
         std::string matchId;
         std::string key;
         ThreadSafeMap<std::string, std::shared_ptr<PlayerInfo>> players;
+        ThreadSafeMap<std::string, std::shared_ptr<PlayerInfo>> spectators;
         uint32_t durationInFrames;
         float tickIntervalMs;
         uint32_t currentFrame;
         int max_players_;
+        std::atomic<MatchPhase> phase{ MatchPhase::WaitingForPlayers };
+        NetcodeMode netcodeMode = NetcodeMode::Rollback;
+        uint8_t inputDelayFrames = 0;
+        uint32_t releasedFrame = 0; // InputDelay: last frame every player's input has been released for
+        std::atomic<steady_clock::rep> configuredAt{ 0 }; // server clock ticks when PlayersConfigurationData went out; 0 before
+        std::atomic<steady_clock::rep> startedAt{ 0 };    // server clock ticks when the match went InProgress; 0 before
+        std::atomic<bool> draining{ false }; // every player reached durationInFrames; only real inputs are still sent
+        float riftMin = -DEFAULT_RIFT_CLAMP;
+        float riftMax = DEFAULT_RIFT_CLAMP;
+        std::vector<MVSIPlayer> allowedPlayers; // roster returned by the backend for this match
+        std::mutex rosterMutex;                 // held while allowedPlayers may still be refetched (it was empty at registration)
+        std::atomic<bool> rosterRefetching{ false }; // a refetchRoster is already asking the backend
+        uint32_t sessionNonce = 0;              // random per match; CAPABILITY_SESSION_NONCE clients must echo it
         // std::vector<std::map<uint32_t, uint32_t>> inputs;     // one map per player: frame → input
-        std::vector<ThreadSafeMap<uint32_t, uint32_t>> inputs;     // one map per player: frame → input
+        std::vector<ThreadSafeMap<uint32_t, FrameInput>> inputs;   // one map per player: frame → input
+        std::vector<ThreadSafeMap<uint32_t, bool>> predictedFrames; // frames in `inputs` the server filled in itself
+        std::vector<uint32_t> prunedThrough;                        // per player: newest frame dropped from `inputs`, 0 if none; guarded by mutex
+        std::shared_ptr<ReplayRecorder> recorder;                  // null unless replay recording is enabled
+
+        TrafficCounters traffic;
+
+        std::vector<DisconnectNotice> disconnectNotices;
+        std::mutex disconnectMutex;
+
+        std::map<uint16_t, uint8_t> reportedWinners; // player index -> winning team its MatchResult named, latest wins
+        std::mutex resultMutex;
+
+        std::deque<TelemetrySample> telemetry; // ring buffer, capped at MAX_TELEMETRY_SAMPLES
+        mutable std::mutex telemetryMutex;
 
         uint32_t sequenceCounter;
         uint32_t pingPhaseCount; // how many pings sent so far
-        uint32_t pingPhaseTotal; // e.g. 65
+        uint32_t pingPhaseTotal; // replies wanted from every player before PlayersConfigurationData
 
         std::atomic<bool> tickRunning;         // Signal to start/stop tick thread
         std::condition_variable tickCondition; // CV for tick thread synchronization
         std::mutex tickMutex;                  // Mutex for CV
     };
 
+    // Point-in-time view of a single player, as returned by getMatchStats()
+    struct PlayerStats
+    {
+        uint16_t playerIndex;
+        std::string endpoint;              // "address:port" the player is sending from
+        bool disconnected;
+        int16_t ping;
+        float smoothedPing;
+        int16_t packetsLossPercent;
+        float rift;                        // smoothed rift, as sent to the client
+        float rawRift;
+        uint32_t lastClientFrame;
+        std::vector<uint32_t> ackedFrames; // last frame of each player this client has acked
+        size_t bufferedInputs;             // inputs from this player held in the match history
+        float jitterMs;                    // smoothed input packet inter-arrival jitter
+        uint32_t inputBufferFrames;        // current target input buffer depth, 0 unless adaptiveInputBuffer
+        bool stalled;                      // still connected, but lastClientFrame isn't advancing
+        uint32_t stallCount;               // stalls detected so far this match
+        bool lagging;                      // its inputs are being predicted because they keep arriving too late
+        uint32_t lagCount;                 // times it started lagging so far this match
+        uint32_t sendQueueDrops;           // datagrams dropped because the socket couldn't keep up
+    };
+
+    struct TrafficStats
+    {
+        uint64_t packetsSent;
+        uint64_t bytesSent;
+        uint64_t uncompressedBytesSent; // bytesSent / uncompressedBytesSent is the compression ratio
+        uint64_t packetsReceived;
+        uint64_t bytesReceived;
+        uint64_t sendBytesPerSec;
+        uint64_t recvBytesPerSec;
+    };
+
+    // One roster slot as the pre-match lobby shows it
+    struct LobbySlot
+    {
+        uint16_t playerIndex;
+        bool connected; // a client holds the slot and hasn't disconnected
+        bool ready;     // sent ReadyToStartMatch
+        int16_t ping;   // ms, 0 until the ping phase has a sample
+    };
+
+    // Point-in-time view of a match, safe to read while the match is running
+    struct MatchStats
+    {
+        std::string matchId;
+        MatchPhase phase;
+        uint32_t currentFrame;
+        uint32_t sequenceCounter;
+        std::vector<PlayerStats> players;
+        size_t spectators;
+        TrafficStats traffic;
+    };
+
+    // What to do with a match whose players haven't all readied up by ServerConfig::readyTimeoutMs
+    enum class ReadyTimeoutPolicy : uint8_t
+    {
+        KickAndStart, // kick the stragglers (AI takes their slots) and start with the rest
+        Abort         // kick everyone and end the match
+    };
+
+    // What the receive loop does with a datagram when maxConcurrentHandlers are already running
+    enum class HandlerOverflowPolicy : uint8_t
+    {
+        Inline, // handle it before receiving the next one, letting the socket buffer absorb the burst
+        Drop    // discard it
+    };
+
+    // Stable codes handed to the error callback; values never change meaning
+    enum class ServerError : int32_t
+    {
+        Internal = 1,           // an exception escaped a server loop or handler
+        MatchRegistration = 2,  // the backend didn't return a config for a new match
+        MatchRejected = 3,      // the backend's config was unusable
+        PlayerKicked = 4,       // a connection was answered with Kick
+        ConnectionRejected = 5, // a connection was refused (e.g. not in the roster)
+        PlayerStalled = 6,      // a connected player's simulation stopped advancing (see ServerConfig::stallTicks)
+        MatchDisputed = 7,      // the players' MatchResult reports gave no team a majority
+    };
+
+    // `message` is only valid for the duration of the call; copy it to keep it
+    using ErrorCallback = std::function<void(ServerError code, const std::string& message)>;
+
+    // Opens and binds the server's UDP socket on the io_context it's given, in place of
+    // binding ServerConfig::bindAddress and port. Called again on every restart
+    using SocketFactory = std::function<udp::socket(asio::io_context& io)>;
+
+    // Runtime configuration for RollbackServer
+    struct ServerConfig
+    {
+        uint16_t port = GAME_SERVER_PORT; // 0 binds an ephemeral port, see RollbackServer::localPort()
+        int maxPlayers = MAX_PLAYERS;
+        std::string bindAddress; // IPv4/IPv6 literal; empty binds all interfaces (dual-stack)
+        std::string httpEndpoint; // match backend base URL; empty falls back to the mvsi_server env var
+        std::string replayDirectory; // when set, every match is recorded to <dir>/<matchId>.mvsr
+        uint32_t keepaliveIntervalMs = 1000; // ping idle players of matches that aren't ticking yet; 0 disables
+        double connectRatePerSecond = 1.0;   // backend lookups allowed per source IP, sustained
+        double connectBurst = 5.0;           // ...and in a burst
+        std::string snapshotPath;            // when set, match metadata (keys included; the file is 0600) is saved here and restored on start()
+        uint32_t snapshotIntervalMs = 5000;
+        float riftMin = -DEFAULT_RIFT_CLAMP; // bounds for the rift sent to clients, in frames; riftMin < riftMax
+        float riftMax = DEFAULT_RIFT_CLAMP;
+        uint32_t pingCount = 20;           // ping replies each player must answer before the match is configured
+        uint32_t pingIntervalMs = 50;
+        uint32_t pingPhaseTimeoutMs = 5000; // go ahead with whatever samples we have after this long
+        uint32_t pendingPingTimeoutMs = 2000; // unanswered sequences older than this count as lost
+        uint32_t disconnectRetransmitMs = 500; // resend unacked PlayerDisconnected notices this often...
+        uint32_t disconnectMaxAttempts = 10;   // ...up to this many sends in total
+        NetworkConditions networkConditions;   // simulated latency/jitter/loss on both directions, for local testing only
+        bool coalesceMessages = false;         // pack messages queued for a client in the same pass into Batch datagrams; clients must support Batch
+        size_t maxConcurrentHandlers = 256;    // received datagrams being handled at once; 0 is unbounded
+        HandlerOverflowPolicy handlerOverflowPolicy = HandlerOverflowPolicy::Inline;
+        size_t sendQueueLimit = 64;            // datagrams a player may have waiting for the socket before new ones are dropped; 0 sends inline
+        bool packetChecksums = false;          // grant CAPABILITY_CHECKSUMS to clients that ask for it
+        bool uncompressedClients = false;      // grant CAPABILITY_UNCOMPRESSED, for clients that can't run the codec
+        bool sessionNonceClients = false;      // grant CAPABILITY_SESSION_NONCE, which ties a client's packets to its match
+        bool rleInputClients = false;          // grant CAPABILITY_RLE_INPUTS, for clients that run-length encode their inputs
+        bool rebindPorts = false;              // a player's address sending from a new port is moved there (NAT rebinding) if no one else shares the address
+        bool rebindRequiresNonce = true;       // ...but only players whose packets carry a session nonce, which proves who sent them; off lets anyone on that address take the slot
+        uint32_t maxFrameLead = 300;           // inputs claiming frames further than this past the server's frame are dropped; 0 disables
+        uint32_t maxFramesPerPeer = 0;         // frames of one peer's input a single PlayerInput may carry, up to 255; 0 fits as many as the datagram allows
+        uint32_t maxSequenceJump = 10000;      // packets whose sequence leaps further than this ahead are dropped rather than trusted; 0 disables...
+        uint32_t sequenceResyncPackets = 16;   // ...unless this many arrive in a row, each following on from the last, taken as the client restarting its counter; 0 never
+        uint32_t stallTicks = 120;             // ticks a player's reported frame may stand still in a running match; 0 disables
+        uint32_t laggyMissedInputs = 10;       // ticks in a row a peer's next input may be missing (its last one is repeated) before it's predicted and flagged lagging; 0 predicts at once
+        bool widenLaggyInputBuffer = false;    // ...and then ask it to run a frame further ahead, up to maxInputBufferFrames
+        uint32_t readyTimeoutMs = 15000;       // from PlayersConfigurationData until every player must have readied; 0 waits forever
+        uint32_t idlePlayerTimeoutMs = 30000;  // silent this long, a player of a match not yet ticking (or any spectator) is removed, or handed to the AI once configured; 0 keeps them
+        uint32_t rosterFetchRetries = 2;       // extra backend lookups when a match's roster is still empty as a player connects...
+        uint32_t rosterRetryDelayMs = 250;     // ...this far apart
+        ReadyTimeoutPolicy readyTimeoutPolicy = ReadyTimeoutPolicy::KickAndStart;
+        uint32_t inputsReadyFrames = 10;       // frames of its own input a player must have sent before it's sent PlayerInputs
+        uint32_t startGameRetransmitMs = 100;  // resend StartGame this often to players that haven't sent their first input...
+        uint32_t startGameRetransmitTimeoutMs = 10000; // ...for at most this long after kick-off; 0 sends it once
+        uint32_t matchDrainTimeoutMs = 2000;   // after the last frame, keep resending unacked inputs for at most this long
+        size_t inputWidth = DEFAULT_INPUT_WIDTH; // bytes per frame of input, 1..MAX_INPUT_WIDTH; must match the game build
+        uint32_t tickRate = 60;                  // frames the server simulates per second; must match the game build
+        bool adaptiveInputBuffer = false; // ask jittery players to run further ahead so their inputs arrive in time
+        uint32_t maxInputBufferFrames = DEFAULT_MAX_INPUT_BUFFER_FRAMES;
+        uint16_t adminPort = 0; // TCP port on 127.0.0.1 that dumps getMatchStatsJson() to each connection; 0 disables
+        uint32_t workerThreads = 2; // threads running the io_context; 1 runs every handler and tick loop on a single thread
+    };
+
     class RollbackServer
     {
     public:
         RollbackServer(uint16_t port = GAME_SERVER_PORT, int maxPlayers = MAX_PLAYERS);
+        explicit RollbackServer(const ServerConfig& config);
+        // Uses `backend` for match registration instead of HTTP (httpEndpoint is then ignored).
+        // Ping round trips and tick pacing read the time from `clock` (the steady clock if null).
+        // The socket comes from `socketFactory` when given, e.g. a loopback pair in a test.
+        RollbackServer(const ServerConfig& config, std::unique_ptr<MatchBackend> backend,
+            std::shared_ptr<const Clock> clock = nullptr, SocketFactory socketFactory = nullptr);
         ~RollbackServer();
 
+        // start() after stop() runs the server again from scratch: the port is bound
+        // anew (a fresh ephemeral one if ServerConfig::port is 0, or whatever the
+        // socket factory returns this time) and resetState() is
+        // applied first, so only what snapshotPath restores carries over
         void start();
         void stop();
 
+        // Forgets every match, player and counter from earlier runs. Only while stopped.
+        void resetState();
+
+        // UDP port the server is actually bound to (differs from ServerConfig::port when that was 0)
+        uint16_t localPort() const;
+
+        // Datagrams dropped because they couldn't be decompressed or parsed, since start
+        uint64_t malformedPacketCount() const { return malformedPackets_; }
+
+        // Datagrams that arrived with every maxConcurrentHandlers slot busy, since start
+        // (handled inline or dropped, per handlerOverflowPolicy)
+        uint64_t handlerOverflowCount() const { return handlerOverflows_; }
+
+        // Called on significant errors, from an io_context thread with no server locks held.
+        // Replaces any previous callback; pass nullptr to stop reporting.
+        void setErrorCallback(ErrorCallback callback);
+
+        // True if this server is hosting `matchId` (registered and not yet ended)
+        bool isMatchActive(const std::string& matchId) const;
+
+        // Snapshot of a running match, or nullopt if the match isn't known
+        std::optional<MatchStats> getMatchStats(const std::string& matchId) const;
+        std::vector<MatchStats> getAllMatchStats() const;
+
+        // All active matches as a JSON object keyed by match id ("{}" when idle)
+        std::string getMatchStatsJson() const;
+
+        // Every slot of a match, connected or not, in player index order; nullopt if
+        // the match isn't known. Meant for the lobby before StartGame, but works in any phase
+        std::optional<std::vector<LobbySlot>> getLobbyState(const std::string& matchId) const;
+
+        // getLobbyState as a JSON array ("[]" if the match isn't known)
+        std::string getLobbyStateJson(const std::string& matchId) const;
+
+        // Recorded telemetry for a match, oldest first (empty if the match isn't known)
+        std::vector<TelemetrySample> getTelemetryHistory(const std::string& matchId) const;
+
     private:
         std::vector<std::thread> worker_threads_;
         // Network methods
         std::vector<std::shared_ptr<MatchState>> active_ping_matches_;
         std::mutex active_ping_mutex_;
+        void openSocket();
         asio::awaitable<void> runUdpServer();
+        asio::awaitable<void> runMaintenanceLoop();
+        asio::awaitable<void> runAdminServer();
+        asio::awaitable<void> sendKeepalives(std::shared_ptr<MatchState> match);
         asio::awaitable<void> handleMessage(
-            std::vector<uint8_t> buffer,
-            size_t bytesReceived,
+            std::vector<uint8_t> packet,
             udp::endpoint remote);
 
         // Game logic methods
@@ -132,6 +439,39 @@ namespace rollback
             const udp::endpoint& remote,
             bool debug = false);
 
+        // Logs, reports `code` and kicks a connecting client with `reason`, so it can leave
+        // the lobby instead of waiting for a reply that will never come
+        void rejectConnection(const NewConnectionPayload& payload, const udp::endpoint& remote,
+            KickReason reason, uint32_t param1, ServerError code, const std::string& why);
+
+        // Kicks and returns false if the client asks for a capability this server doesn't
+        // grant; a plain version other than SUPPORTED_PROTOCOL_VERSION is only logged
+        bool checkProtocolVersion(const NewConnectionPayload& payload, const udp::endpoint& remote);
+        // The CAPABILITY_* bits config_ grants
+        uint16_t enabledCapabilities() const;
+
+        // Kicks and returns false if the client's key isn't the one the match was registered with
+        bool checkMatchKey(const std::shared_ptr<MatchState>& match, const NewConnectionPayload& payload, const udp::endpoint& remote);
+
+        std::shared_ptr<PlayerInfo> handleNewSpectator(
+            const NewConnectionPayload& payload,
+            const udp::endpoint& remote);
+
+        // Moves a player to a new endpoint, re-keying every map that finds players by "ip:port"
+        void rekeyPlayer(const std::shared_ptr<MatchState>& match, const std::shared_ptr<PlayerInfo>& player, const udp::endpoint& remote);
+        void sendConnectionReply(const std::shared_ptr<MatchState>& match, const std::shared_ptr<PlayerInfo>& player);
+        // Whether a connection from `remote` may take over `player`'s slot: it comes from the
+        // player's own address, or from the address the roster lists for the slot
+        bool mayReclaimSlot(const std::shared_ptr<MatchState>& match, const std::shared_ptr<PlayerInfo>& player, const udp::endpoint& remote);
+        // Asks the backend for the roster again while it's empty, from backendPool_; connections
+        // for the match are dropped until it lands (clients resend them)
+        asio::awaitable<void> refetchRoster(std::shared_ptr<MatchState> match);
+        // The one player a packet from an unknown port of a known address may belong to, if any (see rebindPorts)
+        std::shared_ptr<PlayerInfo> findRebindCandidate(const udp::endpoint& remote) const;
+        // players_ and endpointsByAddress_ together, so the two never disagree
+        void addEndpoint(const std::string& key, const std::shared_ptr<PlayerInfo>& player);
+        void removeEndpoint(const std::string& key);
+
         void startPingPhase(std::shared_ptr<MatchState> match);
         asio::awaitable<void> broadcastRequestQuality(std::shared_ptr<MatchState> match);
         asio::awaitable<void> broadcastPlayersConfiguration(std::shared_ptr<MatchState> match);
@@ -141,10 +481,35 @@ namespace rollback
             std::shared_ptr<PlayerInfo> player,
             const PlayerInputAckPayload& payload);
 
+        // Turns the reply to one of our sequence numbers into a ping sample.
+        // Caller holds player->mutex. False if the sequence wasn't pending (or was already used).
+        bool recordRtt(std::shared_ptr<PlayerInfo> player, uint32_t sequence);
+
+        // Drops pending pings nobody answered in time and folds them into packetsLossPercent
+        void prunePendingPings(std::shared_ptr<PlayerInfo> player, steady_clock::time_point now);
+
+        // Tells everyone else in the match that `player` is gone (once per player)
+        void announceDisconnect(std::shared_ptr<MatchState> match, std::shared_ptr<PlayerInfo> player);
+        // Sends `player` a Kick, marks it disconnected and announces it; reports `why` as PlayerKicked
+        void kickPlayer(std::shared_ptr<MatchState> match, std::shared_ptr<PlayerInfo> player, KickReason reason, uint32_t param1,
+            const std::string& why);
+        void retransmitDisconnectNotices(std::shared_ptr<MatchState> match, steady_clock::time_point now);
+        void handleDisconnectAck(
+            std::shared_ptr<MatchState> match,
+            std::shared_ptr<PlayerInfo> player,
+            const PlayerDisconnectedAckPayload& payload);
+
         void handleReady(
             std::shared_ptr<MatchState> match,
             std::shared_ptr<PlayerInfo> player,
             bool isReady);
+        // Moves the match InProgress (once) and tells everyone to start
+        void startMatch(std::shared_ptr<MatchState> match);
+        // Applies readyTimeoutPolicy to a match still waiting on players past readyTimeoutMs
+        void checkReadyTimeout(std::shared_ptr<MatchState> match, steady_clock::time_point now);
+        // Removes clients silent for idlePlayerTimeoutMs, emulated players aside; once the match is configured
+        // its players are disconnected and announced instead. A ticking match times its players out itself
+        void sweepIdlePlayers(std::shared_ptr<MatchState> match, steady_clock::time_point now);
 
         void handleClientInput(
             std::shared_ptr<MatchState> match,
@@ -152,42 +517,111 @@ namespace rollback
             const InputPayload& payload);
 
         void calcRiftVariableTick(
+            std::shared_ptr<MatchState> match,
             std::shared_ptr<PlayerInfo> player,
             uint32_t serverFrame);
 
+        // True when every player has left or timed out (or the match has none)
+        bool allPlayersDisconnected(const std::shared_ptr<MatchState>& match) const;
+        // Resends StartGame to every player with no input yet, per startGameRetransmitMs
+        asio::awaitable<void> retransmitStartGame(std::shared_ptr<MatchState> match, steady_clock::time_point now);
+        // Flags a player whose lastClientFrame hasn't moved for stallTicks. Caller holds player->mutex.
+        void detectStall(const std::shared_ptr<MatchState>& match, const std::shared_ptr<PlayerInfo>& player, uint32_t serverFrame);
+        // Flags `peer` as lagging as a recipient starts predicting its inputs (after laggyMissedInputs ticks without them)
+        void markLagging(const std::shared_ptr<PlayerInfo>& peer, uint32_t missingFrame);
+        // First frame the AI may play for `player` without any remaining client rolling back past the handoff
+        uint32_t aiTakeControlFrame(const std::shared_ptr<MatchState>& match, const std::shared_ptr<PlayerInfo>& player) const;
+        // True once every connected player has simulated the match's last frame
+        bool allPlayersFinished(const std::shared_ptr<MatchState>& match) const;
+        // Tallies the MatchResult reports (tallyMatchResult) and logs the verdict
+        MatchOutcome settleMatchResult(const std::shared_ptr<MatchState>& match);
+        // True when every connected player has acked every input the server holds
+        bool allInputsAcked(const std::shared_ptr<MatchState>& match) const;
+        // Reports the match to the backend and forgets it and its players; safe to call twice
+        void endMatch(std::shared_ptr<MatchState> match, const char* reason);
+
         void startTickLoop(std::shared_ptr<MatchState> match);
         asio::awaitable<void> runTickLoop(std::shared_ptr<MatchState> match);
         asio::awaitable<void> tick(std::shared_ptr<MatchState> match);
+        uint32_t advanceLockstepFrame(
+            std::shared_ptr<MatchState> match,
+            const std::map<std::string, std::shared_ptr<PlayerInfo>>& playersSnapshot);
 
-        asio::awaitable<void> sendPlayerInput(
+        // Sequence the PlayerInput went out with, 0 if it wasn't sent
+        asio::awaitable<uint32_t> sendPlayerInput(
             std::shared_ptr<MatchState> match,
             std::shared_ptr<PlayerInfo> player,
-            const PlayerInputPayload& payload);
+            const PlayerInputPayload& payload,
+            time_point<steady_clock> sentAt);
 
+        // Sequence the message went out with, 0 if it wasn't sent. With `pingSentAt` the
+        // sequence is filed in player->pendingPings before the datagram leaves, so even
+        // the fastest ack finds it, and taken out again if the send fails
         asio::awaitable<uint32_t> sendServerMessage(
             std::shared_ptr<MatchState> match,
             std::shared_ptr<PlayerInfo> player,
             ServerMessageType type,
-            const ServerMessageVariant& payload);
+            const ServerMessageVariant& payload,
+            std::optional<time_point<steady_clock>> pingSentAt = std::nullopt);
+        // Sends everything queued in player->outbox, as few datagrams as fit
+        asio::awaitable<void> flushOutbox(std::shared_ptr<MatchState> match, std::shared_ptr<PlayerInfo> player);
+        // Compresses, records and sends (or queues, see sendQueueLimit) one datagram's worth
+        // of serialized data to a player. False if the player is gone or its queue is full.
+        asio::awaitable<bool> transmit(
+            std::shared_ptr<MatchState> match,
+            std::shared_ptr<PlayerInfo> player,
+            const std::vector<uint8_t>& buf);
+        // Sends player->sendQueue until it's empty, one datagram at a time
+        asio::awaitable<void> drainSendQueue(std::shared_ptr<PlayerInfo> player);
+
+        // For replies to endpoints that never became a registered player (sequence 0)
+        asio::awaitable<void> sendUnregisteredMessage(
+            udp::endpoint remote,
+            ServerMessageType type,
+            ServerMessageVariant payload,
+            uint16_t messageVersion = SUPPORTED_PROTOCOL_VERSION); // framed as a client speaking this version expects
+        // The one place datagrams leave the socket, so the network conditioner sees them all
+        asio::awaitable<void> sendDatagram(std::vector<uint8_t> datagram, udp::endpoint remote);
 
-        // Fetch match config from HTTP server
-        std::optional<MVSIMatchConfig> fetchMatchConfigFromServer(const std::string& matchId, const std::string& key);
+        MatchStats buildMatchStats(const std::shared_ptr<MatchState>& match) const;
 
+        // Crash recovery: match/player metadata only, input history is not kept
+        void saveSnapshot();
+        void restoreSnapshot();
 
-        void sendEndMatch(const std::string& matchId, const std::string& key);
+        // Hands `message` to the error callback, if any, via the io_context
+        void reportError(ServerError code, std::string message);
+        // co_spawn completion handler that logs and reports whatever escaped `task`
+        std::function<void(std::exception_ptr)> supervise(LogContext context, std::string task);
 
         // Server state
+        ServerConfig config_;
+        std::unique_ptr<MatchBackend> backend_;
+        asio::thread_pool backendPool_{ 1 }; // blocking backend calls made on behalf of a running match, kept off the io threads
+        std::shared_ptr<const Clock> clock_;
+        SocketFactory socketFactory_;
         asio::io_context io_context_;
         udp::socket socket_;
+        std::unique_ptr<asio::ip::tcp::acceptor> adminAcceptor_;
         std::shared_ptr<udp::endpoint> remote_endpoint_;
 
         std::atomic<bool> running_;
+        std::atomic<uint32_t> generation_{ 0 }; // bumped by stop(), so a loop left over from an earlier start() knows to quit
         std::thread udp_thread_;
         std::thread tick_thread_;
   
         // std::map<std::string, std::shared_ptr<MatchState>> matches_;
         ThreadSafeMap<std::string, std::shared_ptr<MatchState>> matches_;
         ThreadSafeMap<std::string, std::shared_ptr<PlayerInfo>> players_;
+        std::map<std::string, std::set<std::string>> endpointsByAddress_; // address -> its "ip:port" keys in players_
+        mutable std::mutex endpointsByAddressMutex_;
+        RateLimiter connectLimiter_; // keyed by source IP, guards backend_->registerMatch
+        NetworkConditioner conditioner_;
+        std::atomic<uint64_t> malformedPackets_{ 0 };
+        std::atomic<size_t> activeHandlers_{ 0 };
+        std::atomic<uint64_t> handlerOverflows_{ 0 };
+        ErrorCallback errorCallback_;
+        std::mutex errorCallbackMutex_;
 
     };
 
@@ -3,9 +3,11 @@
 #include "message_types.h"
 #include <vector>
 #include <cstdint>
+#include <map>
 #include <span>
 #include <variant>
 #include <optional>
+#include <string>
 
 namespace rollback {
 
@@ -47,15 +49,121 @@ struct ServerMessageComplete {
 };
 
 /**
- * Parse a raw buffer into a client message
+ * Parse a raw buffer into a client message. Input frames are `inputWidth` bytes each,
+ * laid out as `inputEncoding` says.
+ * Connection packets are rejected when truncated or when a string field holds
+ * anything but printable ASCII, and Input when a run is empty; if `error` is given
+ * it then names the offending field.
  */
-std::optional<ClientMessageComplete> parseClientMessage(std::span<const uint8_t> buffer);
+std::optional<ClientMessageComplete> parseClientMessage(std::span<const uint8_t> buffer,
+                                                        size_t inputWidth = DEFAULT_INPUT_WIDTH,
+                                                        std::string* error = nullptr,
+                                                        InputEncoding inputEncoding = InputEncoding::Raw);
+
+// "Input", "QualityData", ... for logging; "unknown" for a value outside the enum
+const char* clientMessageTypeName(ClientMessageType type);
+
+/**
+ * Size of a serialized server message of `type` for a `maxPlayers` match, header
+ * included. For PlayerInput this excludes the input frames, which add
+ * numFrames[i] * inputWidth bytes per player. Nullopt for unknown types.
+ */
+std::optional<size_t> expectedServerMessageSize(ServerMessageType type, int maxPlayers);
+
+// Frames of one player's input a PlayerInput carries when the recipient isn't catching up
+constexpr uint8_t MAX_INPUTS_PER_FRAME = 30;
+
+// Largest payload the server compresses into one datagram: zero-suppression adds a
+// mask byte per 8, so 900 bytes come out at no more than 1013, plus the checksum,
+// under the 1024-byte limit
+constexpr size_t MAX_UNCOMPRESSED_PAYLOAD = 900;
+
+struct PlayerInputFrameBudget {
+    uint8_t normalFrames;  // per player, recipient keeping up
+    uint8_t catchUpFrames; // per player, recipient behind
+};
+
+/**
+ * Frames of each player's input one PlayerInput may carry in a `maxPlayers` match,
+ * sized so every player's run at full length still fits MAX_UNCOMPRESSED_PAYLOAD, and
+ * never above `frameCap` (0 for no cap beyond the 255 the count field holds). Both are
+ * at least 1 frame.
+ */
+PlayerInputFrameBudget playerInputFrameBudget(int maxPlayers, size_t inputWidth, size_t frameCap = 0);
+
+/**
+ * Frames of one player's `history` a PlayerInput carries after `lastAcked`: the
+ * contiguous run from lastAcked + 1, at most `maxFrames` long and ending no later
+ * than `lastFrame`. Stops at UINT32_MAX rather than wrapping, so it is 0 once
+ * lastAcked is already there.
+ */
+uint8_t playerInputRunLength(const std::map<uint32_t, FrameInput>& history, uint32_t lastAcked, uint8_t maxFrames,
+                             uint32_t lastFrame = UINT32_MAX);
+
+constexpr size_t INPUT_HISTORY_FRAMES = 150; // frames of each player's input kept for resending
+
+/**
+ * Newest frame to drop from one player's input history (`frames`, in any order) so
+ * that only its last `keep` frames remain; nullopt if it holds no more than that.
+ */
+std::optional<uint32_t> inputHistoryPruneThrough(std::vector<uint32_t> frames, size_t keep = INPUT_HISTORY_FRAMES);
+
+/**
+ * Folds a PlayerInputAck's `ackFrame` into `acked`, the newest frame of each player
+ * the client has confirmed. Frames up to `prunedThrough` can't be sent again, so a
+ * client that had confirmed none of them and still doesn't will never get them: its
+ * entry is left alone and the first such player is returned, for the caller to kick.
+ */
+std::optional<size_t> applyInputAcks(std::vector<uint32_t>& acked, const std::vector<uint32_t>& ackFrame,
+                                     const std::vector<uint32_t>& prunedThrough);
 
 /**
  * Serialize a server message into a buffer
+ *
+ * The length is checked against expectedServerMessageSize(): an assertion in debug
+ * builds, a logged warning otherwise.
+ *
+ * @throws std::invalid_argument If the payload isn't the one header.type carries
+ *         (see ServerMessageType), including unknown types
  */
 std::vector<uint8_t> serializeServerMessage(const ServerHeader& header, 
                                            const ServerMessageVariant& payload,
-                                           int maxPlayers);
+                                           int maxPlayers,
+                                           size_t inputWidth = DEFAULT_INPUT_WIDTH);
+
+/**
+ * Parse a raw buffer into a server message, as the game client would. `capabilities`
+ * are the CAPABILITY_* bits the client connected with: only with
+ * CAPABILITY_SESSION_NONCE does a NewConnectionReply carry a nonce, since the codec's
+ * zero padding would otherwise pass for one.
+ * Returns nullopt for unknown types or a truncated payload.
+ */
+std::optional<ServerMessageComplete> parseServerMessage(std::span<const uint8_t> buffer, int maxPlayers,
+                                                        size_t inputWidth = DEFAULT_INPUT_WIDTH, uint16_t capabilities = 0);
+
+/**
+ * Packs already-serialized server messages into one ServerMessageType::Batch
+ * datagram: the type byte, then each message as a uint16LE length and its bytes.
+ * A zero length (or the end of the buffer) ends the batch.
+ *
+ * @throws std::invalid_argument If a message is empty or longer than 65535 bytes
+ */
+std::vector<uint8_t> serializeBatch(const std::vector<std::vector<uint8_t>>& messages);
+
+/**
+ * Parse every server message in a datagram: each one of a Batch, or the single
+ * message otherwise. Returns nullopt if any of them is malformed.
+ */
+std::optional<std::vector<ServerMessageComplete>> parseServerMessages(std::span<const uint8_t> buffer, int maxPlayers,
+                                                                      size_t inputWidth = DEFAULT_INPUT_WIDTH,
+                                                                      uint16_t capabilities = 0);
+
+/**
+ * Checks and removes the session nonce a CAPABILITY_SESSION_NONCE client
+ * puts after the header of a decompressed packet, leaving what parseClientMessage
+ * expects. Returns false, with the packet untouched, if it's too short or the
+ * nonce isn't `nonce`.
+ */
+bool takeSessionNonce(std::vector<uint8_t>& packet, uint32_t nonce);
 
 } // namespace rollback
\ No newline at end of file
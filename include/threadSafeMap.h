@@ -1,4 +1,7 @@
+#pragma once
+
 #include <map>
+#include <mutex>
 #include <shared_mutex>
 #include <optional>
 
@@ -91,6 +94,21 @@ public:
         return std::nullopt;
     }
 
+    // Erase by key, returning what was there; of several callers racing for the
+    // same key, exactly one gets the value
+    std::optional<Value> take(const Key &key)
+    {
+        std::unique_lock lock(mutex_);
+        auto it = map_.find(key);
+        if (it == map_.end())
+        {
+            return std::nullopt;
+        }
+        Value value = std::move(it->second);
+        map_.erase(it);
+        return value;
+    }
+
     // Check if key exists
     bool contains(const Key &key) const
     {
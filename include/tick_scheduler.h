@@ -0,0 +1,58 @@
+#pragma once
+
+#include "clock.h"
+#include <asio.hpp>
+#include <chrono>
+#include <cstdint>
+#include <memory>
+
+namespace rollback
+{
+
+    /**
+     * Paces a fixed-rate loop (the 60Hz match tick) as precisely as the OS allows.
+     *
+     * Tick n is due at start + n * interval, an absolute deadline, so time spent
+     * working between waits and timer wake-up error never accumulate: a late tick
+     * just leaves less sleep before the next one. Each wait sleeps on an asio timer
+     * until shortly before the deadline and then spins for the remainder, since even
+     * with timeBeginPeriod(1) Windows timers can wake up to a millisecond late.
+     * On a virtual clock (see Clock::realTime) it just checks back every millisecond.
+     */
+    class TickScheduler
+    {
+    public:
+        // Falling further behind than this many ticks (a stall, a debugger) skips the
+        // missed ticks instead of running them back to back
+        static constexpr uint32_t MAX_LAG_TICKS = 3;
+
+        // `clock` defaults to the steady clock; the first tick is due one interval from now
+        explicit TickScheduler(std::chrono::nanoseconds interval, std::shared_ptr<const Clock> clock = nullptr);
+
+        // Suspends until the next tick is due; returns immediately when running behind.
+        // Throws std::system_error if the underlying timer is cancelled.
+        asio::awaitable<void> waitForNextTick();
+
+        /**
+         * Moves on to the next tick and returns when it is due: start + n * interval,
+         * unless `now` is more than MAX_LAG_TICKS past that, in which case the schedule
+         * restarts from `now`. waitForNextTick() is this plus the wait.
+         */
+        Clock::time_point advance(Clock::time_point now);
+
+        std::chrono::nanoseconds interval() const { return interval_; }
+        uint64_t ticks() const { return ticks_; }
+
+        // Largest |actual - intended| wake-up time seen since the last resetStats()
+        std::chrono::nanoseconds maxDrift() const { return maxDrift_; }
+        void resetStats() { maxDrift_ = std::chrono::nanoseconds::zero(); }
+
+    private:
+        std::chrono::nanoseconds interval_;
+        std::shared_ptr<const Clock> clock_;
+        Clock::time_point start_;
+        uint64_t ticks_ = 0;
+        std::chrono::nanoseconds maxDrift_{ 0 };
+    };
+
+} // namespace rollback
@@ -1,14 +1,40 @@
 #include "compression.h"
+#include <algorithm>
 #include <stdexcept>
+#include <zlib.h>
 
 namespace rollback {
 
-std::vector<uint8_t> compressPacket(std::span<const uint8_t> input) {
+static uint32_t packetCrc(std::span<const uint8_t> data) {
+    return static_cast<uint32_t>(crc32(0L, data.data(), static_cast<uInt>(data.size())));
+}
+
+bool hasValidChecksum(std::span<const uint8_t> packet) {
+    if (packet.size() <= CHECKSUM_SIZE) {
+        return false;
+    }
+    const auto body = packet.first(packet.size() - CHECKSUM_SIZE);
+    const auto trailer = packet.last(CHECKSUM_SIZE);
+    uint32_t expected = 0;
+    for (size_t i = 0; i < CHECKSUM_SIZE; ++i) {
+        expected |= static_cast<uint32_t>(trailer[i]) << (i * 8);
+    }
+    return packetCrc(body) == expected;
+}
+
+std::vector<uint8_t> compressPacket(std::span<const uint8_t> input, bool appendChecksum) {
+    std::vector<uint8_t> outBuf;
+    compressPacketInto(input, outBuf, appendChecksum);
+    return outBuf;
+}
+
+void compressPacketInto(std::span<const uint8_t> input, std::vector<uint8_t>& outBuf, bool appendChecksum) {
     const size_t n = input.size();
-    if (n == 0) return {};
+    outBuf.clear();
+    if (n == 0) return;
 
-    // Pre-allocate exactly 1024 bytes
-    std::vector<uint8_t> outBuf(1024, 0);
+    // Room for the worst case; only reallocates the first time a buffer is used
+    outBuf.resize(1024);
     size_t inPos = 0;
     size_t outPos = 0;
 
@@ -39,16 +65,42 @@ std::vector<uint8_t> compressPacket(std::span<const uint8_t> input) {
 
     // Return only the used portion
     outBuf.resize(outPos);
+
+    if (appendChecksum) {
+        if (outPos + CHECKSUM_SIZE > 1024) {
+            throw std::runtime_error("compressPacket: output buffer overflow (1024 bytes)");
+        }
+        const uint32_t crc = packetCrc(outBuf);
+        for (size_t i = 0; i < CHECKSUM_SIZE; ++i) {
+            outBuf.push_back(static_cast<uint8_t>((crc >> (i * 8)) & 0xFF));
+        }
+    }
+}
+
+std::vector<uint8_t> decompressPacket(std::span<const uint8_t> compressedBuffer, size_t originalLength,
+                                      bool verifyChecksum) {
+    std::vector<uint8_t> outBuf;
+    decompressPacketInto(compressedBuffer, outBuf, originalLength, verifyChecksum);
     return outBuf;
 }
 
-std::vector<uint8_t> decompressPacket(std::span<const uint8_t> compressedBuffer, size_t originalLength) {
+void decompressPacketInto(std::span<const uint8_t> compressedBuffer, std::vector<uint8_t>& outBuf,
+                          size_t originalLength, bool verifyChecksum) {
+    outBuf.clear();
     if (originalLength > 1024) {
         throw std::runtime_error("decompressPacket: originalLength must be between 0 and 1024");
     }
 
-    // Pre-allocate exactly 1024 bytes for the output
-    std::vector<uint8_t> outBuf(1024, 0);
+    if (verifyChecksum) {
+        if (!hasValidChecksum(compressedBuffer)) {
+            throw ChecksumMismatch("decompressPacket: CRC32 mismatch");
+        }
+        compressedBuffer = compressedBuffer.first(compressedBuffer.size() - CHECKSUM_SIZE);
+    }
+
+    // Every mask byte expands to at most 8 bytes, so short input never needs the
+    // whole originalLength; the output grows to what the data actually decodes to
+    outBuf.reserve(std::min(originalLength, compressedBuffer.size() * 8));
     size_t readPos = 0;
     size_t writePos = 0;
 
@@ -68,19 +120,17 @@ std::vector<uint8_t> decompressPacket(std::span<const uint8_t> compressedBuffer,
                 if (writePos >= 1024) {
                     throw std::runtime_error("decompressPacket: output buffer overflow (1024 bytes)");
                 }
-                outBuf[writePos++] = compressedBuffer[readPos++];
+                outBuf.push_back(compressedBuffer[readPos++]);
+                ++writePos;
             } else {
                 if (writePos >= 1024) {
                     throw std::runtime_error("decompressPacket: output buffer overflow (1024 bytes)");
                 }
-                outBuf[writePos++] = 0;
+                outBuf.push_back(0);
+                ++writePos;
             }
         }
     }
-
-    // Return only the requested portion
-    outBuf.resize(originalLength);
-    return outBuf;
 }
 
 } // namespace rollback
\ No newline at end of file
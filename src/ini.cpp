@@ -0,0 +1,55 @@
+#include "ini.h"
+#include <fstream>
+
+namespace rollback {
+
+namespace {
+
+std::string trim(const std::string& s) {
+    const auto first = s.find_first_not_of(" \t\r\n");
+    if (first == std::string::npos) {
+        return {};
+    }
+    const auto last = s.find_last_not_of(" \t\r\n");
+    return s.substr(first, last - first + 1);
+}
+
+std::string unquote(const std::string& s) {
+    if (s.size() >= 2 && (s.front() == '"' || s.front() == '\'') && s.back() == s.front()) {
+        return s.substr(1, s.size() - 2);
+    }
+    return s;
+}
+
+} // namespace
+
+IniValues parseIni(std::istream& in) {
+    IniValues values;
+    std::string line;
+    while (std::getline(in, line)) {
+        line = trim(line);
+        if (line.empty() || line.front() == ';' || line.front() == '#' || line.front() == '[') {
+            continue;
+        }
+        const auto eq = line.find('=');
+        if (eq == std::string::npos) {
+            continue;
+        }
+        auto key = trim(line.substr(0, eq));
+        if (key.empty()) {
+            continue;
+        }
+        values[std::move(key)] = unquote(trim(line.substr(eq + 1)));
+    }
+    return values;
+}
+
+std::optional<IniValues> loadIniFile(const std::string& path) {
+    std::ifstream in(path);
+    if (!in) {
+        return std::nullopt;
+    }
+    return parseIni(in);
+}
+
+} // namespace rollback
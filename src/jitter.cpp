@@ -0,0 +1,33 @@
+#include "jitter.h"
+#include <algorithm>
+#include <cmath>
+
+namespace rollback {
+
+namespace {
+
+constexpr float MEAN_ALPHA = 0.1f;          // 10% of the new gap, 90% of the old
+constexpr float JITTER_ALPHA = 1.0f / 16.0f; // the RFC 3550 interarrival jitter gain
+
+} // namespace
+
+JitterState updateJitter(const JitterState& previous, float intervalMs) {
+    JitterState next = previous;
+    if (!next.initialized) {
+        next.initialized = true;
+        next.meanIntervalMs = intervalMs;
+        return next;
+    }
+
+    const float deviation = std::fabs(intervalMs - next.meanIntervalMs);
+    next.jitterMs += JITTER_ALPHA * (deviation - next.jitterMs);
+    next.meanIntervalMs = MEAN_ALPHA * intervalMs + (1.0f - MEAN_ALPHA) * next.meanIntervalMs;
+    return next;
+}
+
+uint32_t inputBufferFrames(float jitterMs, uint32_t maxFrames, float frameTimeMs) {
+    const float frames = std::ceil(2.0f * std::max(jitterMs, 0.0f) / frameTimeMs);
+    return std::min(static_cast<uint32_t>(frames), maxFrames);
+}
+
+} // namespace rollback
@@ -0,0 +1,125 @@
+#include "logging.h"
+#include <chrono>
+#include <ctime>
+#include <iomanip>
+#include <atomic>
+#include <fstream>
+#include <iostream>
+#include <mutex>
+
+namespace rollback {
+
+namespace {
+
+std::mutex g_logMutex;
+std::atomic<LogLevel> g_minLevel{LogLevel::Info};
+std::ofstream g_logFile; // guarded by g_logMutex; console output while closed
+
+const char* levelName(LogLevel level) {
+    switch (level) {
+        case LogLevel::Debug: return "debug";
+        case LogLevel::Info: return "info";
+        case LogLevel::Warn: return "warn";
+        case LogLevel::Error: return "error";
+    }
+    return "info";
+}
+
+// Quote a value so spaces, quotes and newlines can't break the key=value layout
+void writeQuoted(std::ostream& out, const std::string& value) {
+    out << '"';
+    for (char c : value) {
+        switch (c) {
+            case '"': out << "\\\""; break;
+            case '\\': out << "\\\\"; break;
+            case '\n': out << "\\n"; break;
+            case '\r': out << "\\r"; break;
+            default: out << c; break;
+        }
+    }
+    out << '"';
+}
+
+void writeTimestamp(std::ostream& out) {
+    const auto now = std::chrono::system_clock::now();
+    const auto secs = std::chrono::system_clock::to_time_t(now);
+    const auto millis = std::chrono::duration_cast<std::chrono::milliseconds>(now.time_since_epoch()).count() % 1000;
+
+    std::tm tm{};
+#ifdef _WIN32
+    gmtime_s(&tm, &secs);
+#else
+    gmtime_r(&secs, &tm);
+#endif
+    out << std::put_time(&tm, "%Y-%m-%dT%H:%M:%S") << '.' << std::setw(3) << std::setfill('0') << millis << 'Z';
+}
+
+} // namespace
+
+void setLogLevel(LogLevel level) {
+    g_minLevel = level;
+}
+
+bool isLogEnabled(LogLevel level) {
+    return level >= g_minLevel.load();
+}
+
+bool initLogging(LogLevel level, const std::string& logFile) {
+    setLogLevel(level);
+
+    std::lock_guard lock(g_logMutex);
+    if (logFile.empty()) {
+        if (g_logFile.is_open()) {
+            g_logFile.close();
+        }
+        return true;
+    }
+
+    std::ofstream file(logFile, std::ios::app);
+    if (!file) {
+        return false;
+    }
+    g_logFile = std::move(file);
+    return true;
+}
+
+std::optional<LogLevel> parseLogLevel(const std::string& name) {
+    for (auto level : { LogLevel::Debug, LogLevel::Info, LogLevel::Warn, LogLevel::Error }) {
+        if (name == levelName(level)) {
+            return level;
+        }
+    }
+    return std::nullopt;
+}
+
+void logMessage(LogLevel level, const LogContext& ctx, const std::string& message) {
+    if (!isLogEnabled(level)) {
+        return;
+    }
+    std::ostringstream line;
+    line << "ts=";
+    writeTimestamp(line);
+    line << " level=" << levelName(level);
+    if (!ctx.matchId.empty()) {
+        line << " match_id=";
+        writeQuoted(line, ctx.matchId);
+    }
+    if (ctx.playerIndex.has_value()) {
+        line << " player_index=" << *ctx.playerIndex;
+    }
+    if (!ctx.src.empty()) {
+        line << " src=" << ctx.src;
+    }
+    line << " msg=";
+    writeQuoted(line, message);
+
+    std::lock_guard lock(g_logMutex);
+    if (g_logFile.is_open()) {
+        g_logFile << line.str() << std::endl;
+        return;
+    }
+    auto& out = (level == LogLevel::Warn || level == LogLevel::Error) ? std::cerr : std::cout;
+    out << line.str() << std::endl;
+}
+
+} // namespace rollback
@@ -1,6 +1,10 @@
 #include "rollback_server.h"
+#include "ini.h"
 #include <iostream>
 #include <csignal>
+#include <cstdio>
+#include <cstdlib>
+#include <stdexcept>
 
 namespace
 {
@@ -12,17 +16,69 @@ void signal_handler(int signal)
     g_signal_status = signal;
 }
 
+// An integer setting from settings.ini, or `fallback` if it's missing or not a number
+static int iniInt(const rollback::IniValues &settings, const std::string &key, int fallback)
+{
+    auto it = settings.find(key);
+    if (it == settings.end())
+    {
+        return fallback;
+    }
+    try
+    {
+        return std::stoi(it->second);
+    }
+    catch (...)
+    {
+        std::cerr << "Invalid " << key << " in settings file. Using default: " << fallback << std::endl;
+        return fallback;
+    }
+}
+
 int main(int argc, char *argv[])
 {
+    // settings.ini (or the file named by mvsi_settings) only supplies defaults:
+    // command line arguments and environment variables take precedence over it
+    const char *settingsPath = std::getenv("mvsi_settings");
+    const auto settings = rollback::loadIniFile(settingsPath ? settingsPath : "settings.ini").value_or(rollback::IniValues{});
+    if (settingsPath && settings.empty())
+    {
+        std::cerr << "Could not read mvsi_settings file " << settingsPath << std::endl;
+    }
+    auto setting = [&settings](const std::string &key) -> const char *
+    {
+        auto it = settings.find(key);
+        return it != settings.end() && !it->second.empty() ? it->second.c_str() : nullptr;
+    };
+
     // Parse command line arguments
     uint16_t port = rollback::GAME_SERVER_PORT;
-    int maxPlayers = rollback::MAX_PLAYERS;
+    const int settingsPort = iniInt(settings, "port", rollback::GAME_SERVER_PORT);
+    if (settingsPort < 1 || settingsPort > 65535)
+    {
+        std::cerr << "port must be between 1 and 65535. Using default: " << port << std::endl;
+    }
+    else
+    {
+        port = static_cast<uint16_t>(settingsPort);
+    }
+    int maxPlayers = iniInt(settings, "max_players", rollback::MAX_PLAYERS);
+    if (maxPlayers <= 0 || maxPlayers > 4)
+    {
+        std::cerr << "Max players must be between 1 and 4. Using default: " << rollback::MAX_PLAYERS << std::endl;
+        maxPlayers = rollback::MAX_PLAYERS;
+    }
 
     if (argc > 1)
     {
         try
         {
-            port = static_cast<uint16_t>(std::stoi(argv[1]));
+            const int argPort = std::stoi(argv[1]);
+            if (argPort < 1 || argPort > 65535)
+            {
+                throw std::out_of_range("port");
+            }
+            port = static_cast<uint16_t>(argPort);
         }
         catch (...)
         {
@@ -47,6 +103,97 @@ int main(int argc, char *argv[])
         }
     }
 
+    const char *logLevel = std::getenv("mvsi_log_level");
+    const char *logFile = std::getenv("mvsi_log_file");
+    if (!logLevel)
+    {
+        logLevel = setting("log_level");
+    }
+    if (!logFile)
+    {
+        logFile = setting("log_file");
+    }
+    if (logLevel || logFile)
+    {
+        auto level = rollback::parseLogLevel(logLevel ? logLevel : "info");
+        if (!level)
+        {
+            std::cerr << "Invalid mvsi_log_level, using info" << std::endl;
+        }
+        if (!rollback::initLogging(level.value_or(rollback::LogLevel::Info), logFile ? logFile : ""))
+        {
+            std::cerr << "Could not open mvsi_log_file, logging to the console" << std::endl;
+        }
+    }
+
+    rollback::ServerConfig config;
+    config.port = port;
+    config.maxPlayers = maxPlayers;
+    const int workerThreads = iniInt(settings, "worker_threads", static_cast<int>(config.workerThreads));
+    if (workerThreads < 1 || workerThreads > 64)
+    {
+        std::cerr << "worker_threads must be between 1 and 64. Using default: " << config.workerThreads << std::endl;
+    }
+    else
+    {
+        config.workerThreads = static_cast<uint32_t>(workerThreads);
+    }
+    const int tickRate = iniInt(settings, "tick_rate", static_cast<int>(config.tickRate));
+    if (tickRate < 1 || tickRate > 240)
+    {
+        std::cerr << "tick_rate must be between 1 and 240. Using default: " << config.tickRate << std::endl;
+    }
+    else
+    {
+        config.tickRate = static_cast<uint32_t>(tickRate);
+    }
+    // The game's own settings.ini names the backend bDomain
+    if (!std::getenv("mvsi_server"))
+    {
+        const char *endpoint = setting("endpoint");
+        config.httpEndpoint = endpoint ? endpoint : (setting("bDomain") ? setting("bDomain") : "");
+    }
+    if (const char *replayDir = std::getenv("mvsi_replay_dir"))
+    {
+        config.replayDirectory = replayDir;
+    }
+    if (const char *snapshotPath = std::getenv("mvsi_snapshot_path"))
+    {
+        config.snapshotPath = snapshotPath;
+    }
+    if (const char *adminPort = std::getenv("mvsi_admin_port"))
+    {
+        try
+        {
+            config.adminPort = static_cast<uint16_t>(std::stoi(adminPort));
+        }
+        catch (...)
+        {
+            std::cerr << "Invalid mvsi_admin_port, admin interface disabled" << std::endl;
+        }
+    }
+
+    // Local testing only: "latencyMs,jitterMs,dropRate", e.g. "80,20,0.05"
+    if (const char *netsim = std::getenv("mvsi_netsim"))
+    {
+        double latency = 0, jitter = 0, drop = 0;
+        if (std::sscanf(netsim, "%lf,%lf,%lf", &latency, &jitter, &drop) == 3 && latency >= 0 && jitter >= 0 && drop >= 0 && drop <= 1)
+        {
+            config.networkConditions.latencyMs = static_cast<uint32_t>(latency);
+            config.networkConditions.jitterMs = static_cast<uint32_t>(jitter);
+            config.networkConditions.dropRate = drop;
+        }
+        else
+        {
+            std::cerr << "Invalid mvsi_netsim, expected latencyMs,jitterMs,dropRate" << std::endl;
+        }
+    }
+
+    if (argc > 3)
+    {
+        config.bindAddress = argv[3];
+    }
+
     // Set up signal handling
     std::signal(SIGINT, signal_handler);
     std::signal(SIGTERM, signal_handler);
@@ -54,7 +201,7 @@ int main(int argc, char *argv[])
     try
     {
         // Create and start server
-        rollback::RollbackServer server(port, maxPlayers);
+        rollback::RollbackServer server(config);
         server.start();
 
         std::cout << "Server running. Press Ctrl+C to stop." << std::endl;
@@ -0,0 +1,102 @@
+#include "match_backend.h"
+#include "logging.h"
+#include <algorithm>
+
+#include <curl/curl.h>
+#include <nlohmann/json.hpp>
+
+namespace rollback {
+
+HttpMatchBackend::HttpMatchBackend(std::string endpoint)
+    : endpoint_(std::move(endpoint)) {
+}
+
+// Every request names the match and proves it's ours with the key
+static nlohmann::json requestJson(const std::string& matchId, const std::string& key) {
+    nlohmann::json req_json;
+    req_json["matchId"] = matchId;
+    req_json["key"] = key;
+    return req_json;
+}
+
+std::optional<std::string> HttpMatchBackend::post(const std::string& path, const std::string& matchId, const std::string& req_body) {
+    std::string url = endpoint_ + path;
+
+    CURL* curl = curl_easy_init();
+    if (!curl) {
+        logError({ matchId, std::nullopt, {} }, "Failed to init curl");
+        return std::nullopt;
+    }
+    struct curl_slist* headers = nullptr;
+    headers = curl_slist_append(headers, "Content-Type: application/json");
+    std::string response;
+    curl_easy_setopt(curl, CURLOPT_URL, url.c_str());
+    curl_easy_setopt(curl, CURLOPT_HTTPHEADER, headers);
+    curl_easy_setopt(curl, CURLOPT_POSTFIELDS, req_body.c_str());
+    curl_easy_setopt(curl, CURLOPT_WRITEFUNCTION, +[](char* ptr, size_t size, size_t nmemb, void* userdata) -> size_t {
+        std::string* resp = static_cast<std::string*>(userdata);
+        resp->append(ptr, size * nmemb);
+        return size * nmemb;
+    });
+    curl_easy_setopt(curl, CURLOPT_WRITEDATA, &response);
+    CURLcode res = curl_easy_perform(curl);
+    curl_slist_free_all(headers);
+    curl_easy_cleanup(curl);
+    if (res != CURLE_OK) {
+        logError({ matchId, std::nullopt, {} }, "Failed to POST to ", url, ": ", curl_easy_strerror(res));
+        return std::nullopt;
+    }
+    return response;
+}
+
+std::optional<MVSIMatchConfig> HttpMatchBackend::registerMatch(const std::string& matchId, const std::string& key) {
+    auto response = post("/mvsi_register", matchId, requestJson(matchId, key).dump());
+    if (!response) {
+        return std::nullopt;
+    }
+    nlohmann::json resp_json = nlohmann::json::parse(*response, nullptr, false);
+    if (resp_json.is_discarded()) {
+        logError({ matchId, std::nullopt, {} }, "Invalid JSON from mvsi_register");
+        return std::nullopt;
+    }
+    MVSIMatchConfig config;
+    // Clamped into a byte here; the server rejects anything outside 1..MAX_MATCH_PLAYERS
+    config.max_players = static_cast<uint8_t>(std::clamp(resp_json.value("max_players", 2), 0, 255));
+    config.match_duration = resp_json.value("match_duration", 36000);
+    if (resp_json.value("netcode_mode", "rollback") == "input_delay") {
+        config.netcode_mode = NetcodeMode::InputDelay;
+        config.input_delay = static_cast<uint8_t>(std::clamp(resp_json.value("input_delay", 0), 0, 30));
+    }
+    if (resp_json.contains("players")) {
+        for (const auto& p : resp_json["players"]) {
+            MVSIPlayer player;
+            player.player_index = p.value("player_index", 0);
+            player.ip = p.value("ip", "");
+            player.is_host = p.value("is_host", false);
+            // Absent on older backends, which only ran 1v1 and 2v2 with teams alternating
+            player.team_index = p.value("team_index", static_cast<uint16_t>(player.player_index % 2));
+            config.players.push_back(player);
+        }
+    }
+    return config;
+}
+
+void HttpMatchBackend::endMatch(const std::string& matchId, const std::string& key, const MatchOutcome& outcome) {
+    nlohmann::json req_json = requestJson(matchId, key);
+    switch (outcome.status) {
+    case MatchResultStatus::Unreported:
+        req_json["result"] = "unreported";
+        break;
+    case MatchResultStatus::Decided:
+        req_json["result"] = "decided";
+        req_json["winningTeamIndex"] = outcome.winning_team_index;
+        break;
+    case MatchResultStatus::Disputed:
+        req_json["result"] = "disputed";
+        break;
+    }
+    req_json["resultReports"] = outcome.reports;
+    post("/mvsi_end_match", matchId, req_json.dump());
+}
+
+} // namespace rollback
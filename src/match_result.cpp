@@ -0,0 +1,27 @@
+#include "match_result.h"
+
+namespace rollback {
+
+MatchOutcome tallyMatchResult(const std::map<uint16_t, uint8_t>& reportedWinners, int maxPlayers) {
+    MatchOutcome outcome;
+    outcome.reports = static_cast<uint8_t>(reportedWinners.size());
+    if (reportedWinners.empty()) {
+        return outcome;
+    }
+
+    std::map<uint8_t, int> votes;
+    for (const auto& [playerIndex, team] : reportedWinners) {
+        votes[team]++;
+    }
+
+    outcome.status = MatchResultStatus::Disputed;
+    for (const auto& [team, count] : votes) {
+        if (count * 2 > maxPlayers) {
+            outcome.status = MatchResultStatus::Decided;
+            outcome.winning_team_index = team;
+        }
+    }
+    return outcome;
+}
+
+} // namespace rollback
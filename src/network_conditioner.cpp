@@ -0,0 +1,22 @@
+#include "network_conditioner.h"
+
+namespace rollback {
+
+NetworkConditioner::NetworkConditioner(NetworkConditions conditions, uint32_t seed)
+    : conditions_(conditions),
+      rng_(seed) {
+}
+
+std::optional<std::chrono::milliseconds> NetworkConditioner::delayFor() {
+    std::lock_guard lock(mutex_);
+    if (conditions_.dropRate > 0.0 && std::uniform_real_distribution<double>(0.0, 1.0)(rng_) < conditions_.dropRate) {
+        return std::nullopt;
+    }
+    uint32_t delay = conditions_.latencyMs;
+    if (conditions_.jitterMs > 0) {
+        delay += std::uniform_int_distribution<uint32_t>(0, conditions_.jitterMs)(rng_);
+    }
+    return std::chrono::milliseconds(delay);
+}
+
+} // namespace rollback
@@ -0,0 +1,16 @@
+#include "ping_ledger.h"
+#include <algorithm>
+
+namespace rollback {
+
+std::optional<int16_t> takePingSample(PendingPings& pending, uint32_t sequence,
+                                      std::chrono::steady_clock::time_point now) {
+    const auto sentAt = pending.take(sequence);
+    if (!sentAt) {
+        return std::nullopt;
+    }
+    const auto rttMs = std::chrono::duration_cast<std::chrono::milliseconds>(now - *sentAt).count();
+    return static_cast<int16_t>(std::clamp<int64_t>(rttMs, -1, 255));
+}
+
+} // namespace rollback
@@ -0,0 +1,41 @@
+#include "rate_limiter.h"
+#include <algorithm>
+
+namespace rollback {
+
+RateLimiter::RateLimiter(double ratePerSecond, double burst)
+    : ratePerSecond_(ratePerSecond),
+      burst_(std::max(burst, 1.0)) {
+}
+
+double RateLimiter::refill(Bucket& bucket, std::chrono::steady_clock::time_point now) const {
+    const std::chrono::duration<double> elapsed = now - bucket.lastRefill;
+    if (elapsed.count() > 0) {
+        bucket.tokens = std::min(burst_, bucket.tokens + elapsed.count() * ratePerSecond_);
+        bucket.lastRefill = now;
+    }
+    return bucket.tokens;
+}
+
+bool RateLimiter::allow(const std::string& key, std::chrono::steady_clock::time_point now) {
+    std::lock_guard lock(mutex_);
+    auto [it, inserted] = buckets_.try_emplace(key, Bucket{ burst_, now });
+    if (refill(it->second, now) < 1.0) {
+        return false;
+    }
+    it->second.tokens -= 1.0;
+    return true;
+}
+
+void RateLimiter::prune(std::chrono::steady_clock::time_point now) {
+    std::lock_guard lock(mutex_);
+    for (auto it = buckets_.begin(); it != buckets_.end();) {
+        if (refill(it->second, now) >= burst_) {
+            it = buckets_.erase(it);
+        } else {
+            ++it;
+        }
+    }
+}
+
+} // namespace rollback
@@ -0,0 +1,100 @@
+#include "replay.h"
+#include <array>
+#include <cstring>
+
+namespace rollback {
+
+namespace {
+
+constexpr std::array<char, 4> REPLAY_MAGIC = {'M', 'V', 'S', 'R'};
+constexpr uint16_t REPLAY_FORMAT_VERSION = 2;
+
+template<typename T>
+void writeLittleEndian(std::ofstream& out, T value) {
+    for (size_t i = 0; i < sizeof(T); ++i) {
+        out.put(static_cast<char>((value >> (i * 8)) & 0xFF));
+    }
+}
+
+template<typename T>
+bool readLittleEndian(std::ifstream& in, T& value) {
+    value = 0;
+    for (size_t i = 0; i < sizeof(T); ++i) {
+        const int c = in.get();
+        if (c == std::char_traits<char>::eof()) {
+            return false;
+        }
+        value |= static_cast<T>(static_cast<uint8_t>(c)) << (i * 8);
+    }
+    return true;
+}
+
+} // namespace
+
+ReplayRecorder::ReplayRecorder(const std::string& path)
+    : out_(path, std::ios::binary | std::ios::trunc),
+      start_(std::chrono::steady_clock::now()) {
+    if (out_.is_open()) {
+        out_.write(REPLAY_MAGIC.data(), REPLAY_MAGIC.size());
+        writeLittleEndian<uint16_t>(out_, REPLAY_FORMAT_VERSION);
+    }
+}
+
+void ReplayRecorder::record(ReplayDirection direction, uint8_t playerIndex, std::span<const uint8_t> datagram) {
+    const auto offset = std::chrono::duration_cast<std::chrono::microseconds>(
+        std::chrono::steady_clock::now() - start_);
+
+    std::lock_guard lock(mutex_);
+    if (!out_.is_open()) {
+        return;
+    }
+    writeLittleEndian<uint64_t>(out_, static_cast<uint64_t>(offset.count()));
+    out_.put(static_cast<char>(direction));
+    out_.put(static_cast<char>(playerIndex));
+    writeLittleEndian<uint16_t>(out_, static_cast<uint16_t>(datagram.size()));
+    out_.write(reinterpret_cast<const char*>(datagram.data()), datagram.size());
+}
+
+ReplayReader::ReplayReader(const std::string& path)
+    : in_(path, std::ios::binary) {
+    if (!in_.is_open()) {
+        return;
+    }
+    std::array<char, 4> magic{};
+    in_.read(magic.data(), magic.size());
+    valid_ = in_.gcount() == static_cast<std::streamsize>(magic.size())
+        && magic == REPLAY_MAGIC
+        && readLittleEndian(in_, version_)
+        && version_ >= 1 && version_ <= REPLAY_FORMAT_VERSION;
+}
+
+std::optional<ReplayRecord> ReplayReader::next() {
+    if (!valid_) {
+        return std::nullopt;
+    }
+
+    uint64_t offset = 0;
+    uint16_t length = 0;
+    if (!readLittleEndian(in_, offset)) {
+        return std::nullopt;
+    }
+    const int direction = in_.get();
+    const int playerIndex = version_ >= 2 ? in_.get() : REPLAY_NO_PLAYER;
+    if (direction == std::char_traits<char>::eof() || playerIndex == std::char_traits<char>::eof()
+        || !readLittleEndian(in_, length)) {
+        return std::nullopt;
+    }
+
+    ReplayRecord record;
+    record.offset = std::chrono::microseconds(offset);
+    record.direction = static_cast<ReplayDirection>(direction);
+    record.playerIndex = static_cast<uint8_t>(playerIndex);
+    record.datagram.resize(length);
+    in_.read(reinterpret_cast<char*>(record.datagram.data()), length);
+    if (in_.gcount() != length) {
+        return std::nullopt;
+    }
+    return record;
+}
+
+} // namespace rollback
@@ -0,0 +1,61 @@
+#include "rift.h"
+#include <algorithm>
+#include <cmath>
+
+namespace rollback {
+
+namespace {
+
+constexpr float RIFT_ALPHA = 0.05f; // 5% of the new sample, 95% of the old
+
+} // namespace
+
+RiftState updateRift(const RiftState& previous, float smoothedPingMs, uint32_t lastClientFrame, uint32_t serverFrame,
+                     float minRift, float maxRift, float frameTimeMs) {
+    RiftState next = previous;
+
+    // Convert half of smoothedPing from ms → frames
+    float halfPingFrames = (smoothedPingMs * 0.5f) / frameTimeMs;
+
+    // Compute raw rift (client vs. server) from where the client “must be” by now.
+    // Subtract the frame numbers as integers first: past 2^24 a float can no longer
+    // hold every frame number, and subtracting two rounded values loses the rift.
+    const int64_t frameDiff = static_cast<int64_t>(lastClientFrame) - static_cast<int64_t>(serverFrame);
+    float rawRift = static_cast<float>(frameDiff) + halfPingFrames;
+    if (!next.initialized) {
+        next.initialized = true;
+        next.smoothRift = rawRift;
+    }
+    else {
+        next.rawRift = rawRift;
+
+        if (std::fabs(rawRift) < 1.0f) {
+            // blend toward zero instead of toward rawRift
+            // e.g. kill half of the remaining smoothed error every tick
+            next.smoothRift *= 0.5f;
+
+            // once it's tiny, zero it out completely:
+            if (std::fabs(next.smoothRift) < 0.01f)
+                next.smoothRift = 0.0f;
+        }
+        else {
+            next.smoothRift = RIFT_ALPHA * rawRift + (1.0f - RIFT_ALPHA) * next.smoothRift;
+        }
+
+        if (std::fabs(rawRift) < std::fabs(next.smoothRift)) {
+            next.smoothRift = rawRift;
+        }
+    }
+
+    next.smoothRift = std::clamp(next.smoothRift, minRift, maxRift);
+    return next;
+}
+
+float smoothPing(float previous, bool initialized, float sampleMs) {
+    if (!initialized) {
+        return sampleMs;
+    }
+    return std::clamp(PING_ALPHA * sampleMs + (1.0f - PING_ALPHA) * previous, -255.0f, 255.0f);
+}
+
+} // namespace rollback
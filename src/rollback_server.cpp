@@ -1,12 +1,20 @@
 ﻿#include "rollback_server.h"
 #include "compression.h"
+#include "logging.h"
+#include "match_result.h"
+#include "rift.h"
+#include "tick_scheduler.h"
 #include <iomanip>
 #include <sstream>
 #include <thread>
 #include <chrono>
 #include <algorithm>
+#include <cmath>
 #include <iostream>
+#include <fstream>
+#include <filesystem>
 #include <format>
+#include <random>
 
 #include <curl/curl.h>
 #include <nlohmann/json.hpp>
@@ -18,653 +26,2873 @@
 #pragma comment(lib, "winmm.lib") // Link with winmm.lib for timeBeginPeriod/timeEndPeriod
 #endif
 
-const float TARGET_FRAME_TIME = 1000 / 60;
-// We’ll do a simple EWMA on ping:
-static constexpr float PING_ALPHA = 0.1f;  // 0.1 means 10% of the new sample, 90% of the old
-static constexpr float RIFT_ALPHA = 0.05f; // 0.1 means 10% of the new sample, 90% of the old
-constexpr uint8_t MAX_INPUTS_PER_FRAME = 30;
+constexpr uint32_t CATCH_UP_LAG = 60;          // frames a recipient's ack may trail a peer's input before we send bigger bursts
 constexpr uint8_t DISCONECT_TIMEOUT = 30;
+constexpr uint32_t AI_TAKEOVER_MARGIN = 5; // frames of slack past the furthest-ahead client's estimated position
+constexpr auto STOP_DRAIN_TIMEOUT = std::chrono::seconds(2); // stop() waits this long for the loops to wind down before cutting them off
 
 namespace rollback
 {
 
 	using namespace std::chrono;
 
-	RollbackServer::RollbackServer(uint16_t port, int maxPlayers)
-		: io_context_(),
-		socket_(io_context_, udp::endpoint(udp::v4(), port)),
-		remote_endpoint_(std::make_shared<udp::endpoint>()),
-		running_(false)
+	// The dual-stack socket reports IPv4 peers as ::ffff:a.b.c.d; key and log them as plain IPv4
+	static asio::ip::address normalizeAddress(const asio::ip::address& address)
 	{
-
-		std::cout << "Initializing rollback server on port " << port << std::endl;
-		curl_global_init(CURL_GLOBAL_DEFAULT);
-#ifdef _WIN32
-		// Request 1ms timer resolution for more precise timing
-		MMRESULT result = timeBeginPeriod(1);
-		if (result == TIMERR_NOERROR)
+		if (address.is_v6() && address.to_v6().is_v4_mapped())
 		{
-			std::cout << "Successfully set timer resolution to 1ms" << std::endl;
+			return asio::ip::make_address_v4(asio::ip::v4_mapped, address.to_v6());
 		}
-		else
+		return address;
+	}
+
+	static std::string endpointString(const asio::ip::address& address, uint16_t port)
+	{
+		auto normalized = normalizeAddress(address);
+		if (normalized.is_v6())
 		{
-			std::cerr << "Failed to set timer resolution to 1ms" << std::endl;
+			return "[" + normalized.to_string() + "]:" + std::to_string(port);
 		}
-#endif
+		return normalized.to_string() + ":" + std::to_string(port);
 	}
 
-	RollbackServer::~RollbackServer()
+	// The address part of an endpointString() key: everything before the port
+	static std::string keyAddress(const std::string& key)
 	{
-		stop();
-		curl_global_cleanup();
+		return key.substr(0, key.rfind(':'));
 	}
 
-	void RollbackServer::start()
+	// "0a 1f 00 ..." for the first `limit` bytes, for logging packets we couldn't read
+	static std::string hexPrefix(std::span<const uint8_t> data, size_t limit = 16)
 	{
-		if (running_)
-			return;
-		running_ = true;
-
-		// Only spawn UDP server; matches will spawn their own tick loops
-		asio::co_spawn(io_context_, runUdpServer(), asio::detached);
-
-		// Launch two threads to run the io_context_
-		for (int i = 0; i < 2; ++i)
+		static constexpr char digits[] = "0123456789abcdef";
+		std::string out;
+		for (size_t i = 0; i < data.size() && i < limit; ++i)
 		{
-			worker_threads_.emplace_back([this]()
-				{
-					try {
-						io_context_.run();
-					}
-					catch (const std::exception& e) {
-						std::cerr << "Exception in io_context thread: " << e.what() << std::endl;
-					} });
+			if (i > 0)
+				out += ' ';
+			out += digits[data[i] >> 4];
+			out += digits[data[i] & 0x0F];
 		}
+		if (data.size() > limit)
+			out += " ...";
+		return out;
+	}
 
-		std::cout << "Rollback server started" << std::endl;
+	static LogContext logCtx(const std::shared_ptr<MatchState>& match)
+	{
+		return { match->matchId, std::nullopt, {} };
 	}
 
-	void RollbackServer::stop()
+	static LogContext logCtx(const std::shared_ptr<PlayerInfo>& player)
 	{
-		if (!running_)
-			return;
-		running_ = false;
+		return { player->matchId, player->playerIndex, endpointString(player->address, player->port) };
+	}
 
-		io_context_.stop();
+	static LogContext logCtx(const udp::endpoint& remote)
+	{
+		return { {}, std::nullopt, endpointString(remote.address(), remote.port()) };
+	}
 
-		for (auto& t : worker_threads_)
+	// The payload a message of this type carries, or null (and a warning naming the
+	// type) when the parser paired it with some other one
+	template <typename Payload>
+	static const Payload* expectPayload(const ClientMessageComplete& message, const udp::endpoint& remote)
+	{
+		const auto* payload = std::get_if<Payload>(&message.payload);
+		if (!payload)
 		{
-			if (t.joinable())
-				t.join();
+			logWarn(logCtx(remote), "Dropped ", clientMessageTypeName(message.header.type), " message with a mismatched payload (variant index ",
+				message.payload.index(), ")");
 		}
-		worker_threads_.clear();
-
-		std::error_code ec;
-		socket_.close(ec);
+		return payload;
+	}
 
-		std::cout << "Rollback server stopped" << std::endl;
+	// The match id comes from the client, so only [A-Za-z0-9_-] of it reaches the file
+	// name; an id that needed changing gets a hash of the original so two can't collide
+	static std::string replayFileName(const std::string& matchId)
+	{
+		std::string name;
+		bool changed = matchId.empty();
+		for (const char c : matchId)
+		{
+			const bool safe = (c >= 'A' && c <= 'Z') || (c >= 'a' && c <= 'z') || (c >= '0' && c <= '9') || c == '_' || c == '-';
+			name += safe ? c : '_';
+			changed = changed || !safe;
+		}
+		if (changed)
+		{
+			std::ostringstream hash;
+			hash << '-' << std::hex << std::setw(16) << std::setfill('0') << std::hash<std::string>{}(matchId);
+			name += hash.str();
+		}
+		return name + ".mvsr";
 	}
 
-	asio::awaitable<void> RollbackServer::runUdpServer()
+	// A client that connects again has restarted its counters; what we remember of the
+	// old connection would make every packet of the new one look stale
+	static void resetConnectionSequence(const std::shared_ptr<PlayerInfo>& player)
 	{
-		std::vector<uint8_t> recv_buffer(1024);
+		std::unique_lock lock(player->mutex);
+		player->lastSeqRecv = 0;
+		player->sequenceJumps = 0;
+		player->jumpSequence = 0;
+		player->lastAckSeq = 0;
+		player->hasAckSeq = false;
+	}
 
-		while (running_)
+	// Why a backend config's roster is unusable, or nullopt if it's fine. An empty
+	// roster is fine: the backend may not have filled it in yet, and it's asked again
+	// when a player connects (see refetchRoster)
+	static std::optional<std::string> rosterProblem(const MVSIMatchConfig& config)
+	{
+		if (!config.players.empty() && config.players.size() != config.max_players)
 		{
-			try
+			return "roster has " + std::to_string(config.players.size()) + " players but max_players=" + std::to_string(config.max_players);
+		}
+		std::set<uint16_t> seen;
+		for (const auto& p : config.players)
+		{
+			if (p.player_index >= config.max_players)
 			{
-				// Wait for incoming message
-				udp::endpoint remote;
-				size_t bytes_received = co_await socket_.async_receive_from(
-					asio::buffer(recv_buffer), remote,
-					asio::use_awaitable);
-
-				// Process message asynchronously
-				asio::co_spawn(io_context_,
-					handleMessage(recv_buffer, bytes_received, remote),
-					asio::detached);
+				return "player_index " + std::to_string(p.player_index) + " is out of range";
 			}
-			catch (const std::exception& e)
+			if (!seen.insert(p.player_index).second)
 			{
-				std::cerr << "Error in UDP server: " << e.what() << std::endl;
-				if (!running_)
-					break;
+				return "player_index " + std::to_string(p.player_index) + " appears twice";
 			}
 		}
+		return std::nullopt;
+	}
 
-		co_return;
+	// The wire form of a serialized message for a client with these capabilities
+	static std::vector<uint8_t> encodeDatagram(const std::vector<uint8_t>& buf, bool compression, bool checksum)
+	{
+		return compression ? compressPacket(buf, checksum) : buf;
 	}
 
-	asio::awaitable<void> RollbackServer::handleMessage(
-		std::vector<uint8_t> buffer, size_t bytesReceived, udp::endpoint remote)
+	// A NewConnection or SpectatorConnection sent raw by a CAPABILITY_UNCOMPRESSED client.
+	// Connection packets have a fixed size, which a compressed one practically never shares
+	// along with a type byte and version in the right places.
+	static bool isUncompressedConnection(std::span<const uint8_t> packet)
 	{
+		const size_t CONNECTION_PACKET_SIZE = 5 + 2 + 4 + 25 + 45 + 25;
+		if (packet.size() != CONNECTION_PACKET_SIZE)
+			return false;
+		const auto type = static_cast<ClientMessageType>(packet[0]);
+		const uint16_t version = static_cast<uint16_t>(packet[5] | (packet[6] << 8));
+		return (type == ClientMessageType::NewConnection || type == ClientMessageType::SpectatorConnection)
+			&& (protocolCapabilities(version) & CAPABILITY_UNCOMPRESSED);
+	}
 
-		try
+	// Sets up how `player` is framed and parsed from the capabilities its connection packet asked for
+	static void applyCapabilities(const std::shared_ptr<PlayerInfo>& player, uint16_t messageVersion, uint32_t sessionNonce)
+	{
+		const uint16_t capabilities = protocolCapabilities(messageVersion);
+		player->checksums = (capabilities & CAPABILITY_CHECKSUMS) != 0;
+		player->compression = (capabilities & CAPABILITY_UNCOMPRESSED) == 0;
+		player->inputEncoding = (capabilities & CAPABILITY_RLE_INPUTS) ? InputEncoding::RunLength : InputEncoding::Raw;
+		if (capabilities & CAPABILITY_SESSION_NONCE)
+		{
+			player->sessionNonce = sessionNonce;
+		}
+		else
 		{
-			// Decompress and parse message
-			auto decompressed = decompressPacket(std::span<const uint8_t>(buffer.data(), bytesReceived));
-			auto clientMsg = parseClientMessage(decompressed);
+			player->sessionNonce.reset(); // a reconnect may drop what the last connection negotiated
+		}
+	}
 
-			if (!clientMsg)
-			{
-				co_return;
-			}
+	static uint8_t replayPlayerIndex(const std::shared_ptr<PlayerInfo>& player)
+	{
+		return player->spectator ? REPLAY_NO_PLAYER : static_cast<uint8_t>(player->playerIndex);
+	}
 
-			const auto& header = clientMsg->header;
-			const auto& type = header.type;
-			const auto& sequence = header.sequence;
+	void to_json(nlohmann::json& j, const PlayerStats& s)
+	{
+		j = nlohmann::json{
+			{ "player_index", s.playerIndex },
+			{ "endpoint", s.endpoint },
+			{ "disconnected", s.disconnected },
+			{ "ping", s.ping },
+			{ "smoothed_ping", s.smoothedPing },
+			{ "packets_loss_percent", s.packetsLossPercent },
+			{ "rift", s.rift },
+			{ "raw_rift", s.rawRift },
+			{ "last_client_frame", s.lastClientFrame },
+			{ "acked_frames", s.ackedFrames },
+			{ "buffered_inputs", s.bufferedInputs },
+			{ "jitter_ms", s.jitterMs },
+			{ "input_buffer_frames", s.inputBufferFrames },
+			{ "stalled", s.stalled },
+			{ "stall_count", s.stallCount },
+			{ "lagging", s.lagging },
+			{ "lag_count", s.lagCount },
+			{ "send_queue_drops", s.sendQueueDrops },
+		};
+	}
 
-			// Log packet
-			// logPacket(buffer, std::to_string(static_cast<int>(type)), "RECV");
+	void to_json(nlohmann::json& j, const LobbySlot& s)
+	{
+		j = nlohmann::json{
+			{ "player_index", s.playerIndex },
+			{ "connected", s.connected },
+			{ "ready", s.ready },
+			{ "ping", s.ping },
+		};
+	}
 
-			// Get the match (if any)
-			std::shared_ptr<MatchState> match;
-			std::shared_ptr<PlayerInfo> player;
+	void to_json(nlohmann::json& j, const TrafficStats& s)
+	{
+		j = nlohmann::json{
+			{ "packets_sent", s.packetsSent },
+			{ "bytes_sent", s.bytesSent },
+			{ "uncompressed_bytes_sent", s.uncompressedBytesSent },
+			{ "packets_received", s.packetsReceived },
+			{ "bytes_received", s.bytesReceived },
+			{ "send_bytes_per_sec", s.sendBytesPerSec },
+			{ "recv_bytes_per_sec", s.recvBytesPerSec },
+		};
+	}
 
-			if (type == ClientMessageType::NewConnection)
-			{
-				// Special case for new connection
-				auto payload = std::get<NewConnectionPayload>(clientMsg->payload);
-				player = handleNewConnection(payload, remote);
-				if (player)
-				{
-					auto matchOptional = matches_.find(player->matchId);
-					if (matchOptional.has_value())
-					{
-						match = matchOptional.value();
-					}
-				}
-			}
-			else
-			{
-				// Find existing player
-				auto ipStr = remote.address().to_string();
-				auto portStr = std::to_string(remote.port());
-				std::string key = ipStr + ":" + portStr;
+	// Turns the running byte totals into bytes/second over the last window
+	static void updateThroughput(TrafficCounters& traffic, steady_clock::time_point now)
+	{
+		const std::chrono::duration<double> elapsed = now - traffic.rateSampleTime;
+		if (elapsed < std::chrono::seconds(1))
+			return;
 
-				auto playerOptional = players_.find(key);
-				if (playerOptional.has_value())
+		const uint64_t sent = traffic.bytesSent;
+		const uint64_t received = traffic.bytesReceived;
+		traffic.sendBytesPerSec = static_cast<uint64_t>((sent - traffic.rateBytesSent) / elapsed.count());
+		traffic.recvBytesPerSec = static_cast<uint64_t>((received - traffic.rateBytesReceived) / elapsed.count());
+		traffic.rateBytesSent = sent;
+		traffic.rateBytesReceived = received;
+		traffic.rateSampleTime = now;
+	}
+
+	const char* matchPhaseName(MatchPhase phase)
+	{
+		switch (phase)
+		{
+		case MatchPhase::WaitingForPlayers: return "waiting_for_players";
+		case MatchPhase::InProgress: return "in_progress";
+		case MatchPhase::Ended: return "ended";
+		}
+		return "unknown";
+	}
+
+	void to_json(nlohmann::json& j, const MatchStats& s)
+	{
+		j = nlohmann::json{
+			{ "match_id", s.matchId },
+			{ "phase", matchPhaseName(s.phase) },
+			{ "current_frame", s.currentFrame },
+			{ "sequence", s.sequenceCounter },
+			{ "players", s.players },
+			{ "spectators", s.spectators },
+			{ "traffic", s.traffic },
+		};
+	}
+
+	RollbackServer::RollbackServer(uint16_t port, int maxPlayers)
+		: RollbackServer(ServerConfig{ port, maxPlayers })
+	{
+	}
+
+	RollbackServer::RollbackServer(const ServerConfig& config)
+		: RollbackServer(config, nullptr)
+	{
+	}
+
+	RollbackServer::RollbackServer(const ServerConfig& config, std::unique_ptr<MatchBackend> backend,
+		std::shared_ptr<const Clock> clock, SocketFactory socketFactory)
+		: config_(config),
+		backend_(std::move(backend)),
+		clock_(clock ? std::move(clock) : std::make_shared<SteadyClock>()),
+		socketFactory_(std::move(socketFactory)),
+		io_context_(static_cast<int>(config.workerThreads)),
+		socket_(io_context_),
+		remote_endpoint_(std::make_shared<udp::endpoint>()),
+		running_(false),
+		connectLimiter_(config.connectRatePerSecond, config.connectBurst),
+		conditioner_(config.networkConditions)
+	{
+
+		if (conditioner_.enabled())
+		{
+			logWarn({}, "Network conditioner active: +", config_.networkConditions.latencyMs, "ms +0-", config_.networkConditions.jitterMs,
+				"ms, ", config_.networkConditions.dropRate * 100.0, "% loss each way");
+		}
+		if (!(config_.riftMin < config_.riftMax))
+		{
+			throw std::invalid_argument("riftMin must be less than riftMax");
+		}
+		if (config_.workerThreads == 0)
+		{
+			throw std::invalid_argument("workerThreads must be at least 1");
+		}
+		if (config_.inputWidth == 0 || config_.inputWidth > MAX_INPUT_WIDTH)
+		{
+			throw std::invalid_argument("inputWidth must be between 1 and " + std::to_string(MAX_INPUT_WIDTH));
+		}
+		if (config_.tickRate == 0)
+		{
+			throw std::invalid_argument("tickRate must be at least 1");
+		}
+		if (!backend_)
+		{
+			if (config_.httpEndpoint.empty())
+			{
+				if (const char* env_p = std::getenv("mvsi_server"))
 				{
-					player = playerOptional.value();
+					config_.httpEndpoint = env_p;
 				}
-
-				if (player)
+				else
 				{
-					auto matchOptional = matches_.find(player->matchId);
-					if (matchOptional.has_value())
-					{
-						match = matchOptional.value();
-					}
+					throw std::runtime_error("No match backend configured: set ServerConfig::httpEndpoint or the mvsi_server environment variable");
 				}
 			}
+			backend_ = std::make_unique<HttpMatchBackend>(config_.httpEndpoint);
+		}
 
-			if (!player || !match)
-			{
-				co_return;
-			}
+		openSocket();
+		logInfo({}, "Initializing rollback server on ", endpointString(socket_.local_endpoint().address(), localPort()));
+		curl_global_init(CURL_GLOBAL_DEFAULT);
+#ifdef _WIN32
+		// Request 1ms timer resolution for more precise timing
+		MMRESULT result = timeBeginPeriod(1);
+		if (result == TIMERR_NOERROR)
+		{
+			logInfo({}, "Successfully set timer resolution to 1ms");
+		}
+		else
+		{
+			logWarn({}, "Failed to set timer resolution to 1ms");
+		}
+#endif
+	}
 
-			// Filter out-of-order packets
-			if (sequence <= player->lastSeqRecv)
+	void RollbackServer::openSocket()
+	{
+		if (socketFactory_)
+		{
+			socket_ = socketFactory_(io_context_);
+			if (!socket_.is_open())
 			{
-				co_return;
+				throw std::runtime_error("The socket factory returned a closed socket");
 			}
-			player->lastSeqRecv = sequence;
+			return;
+		}
 
-			// Handle quality data
-			if (type == ClientMessageType::QualityData)
+		const uint16_t port = config_.port;
+		if (!config_.bindAddress.empty())
+		{
+			std::error_code parseEc;
+			auto address = asio::ip::make_address(config_.bindAddress, parseEc);
+			if (parseEc)
 			{
-				auto payload = std::get<QualityDataPayload>(clientMsg->payload);
-
-				// Find the matching timestamp in this player's pendingPings
-				// auto it = player->pendingPings.find(payload.serverMessageSequenceNumber);
-				auto pendingPingOpt = player->pendingPings.find(payload.serverMessageSequenceNumber);
-				if (pendingPingOpt.has_value())
-				{
-					player->ping = static_cast<int16_t>(
-						duration_cast<milliseconds>(steady_clock::now() - pendingPingOpt.value()).count());
-					player->pendingPings.erase(payload.serverMessageSequenceNumber);
-				}
+				throw std::invalid_argument("Invalid bind address: " + config_.bindAddress);
 			}
 
-			// Handle other message types
-			switch (type)
+			udp::endpoint endpoint(address, port);
+			socket_.open(endpoint.protocol());
+			if (address.is_v6() && address.is_unspecified())
 			{
-			case ClientMessageType::PlayerInputAck:
-			{
-				auto payload = std::get<PlayerInputAckPayload>(clientMsg->payload);
-				handlePlayerInputAck(match, player, payload);
-				break;
+				socket_.set_option(asio::ip::v6_only(false), parseEc);
 			}
-			case ClientMessageType::ReadyToStartMatch:
+			std::error_code bindEc;
+			socket_.bind(endpoint, bindEc);
+			if (bindEc)
 			{
-				auto payload = std::get<ReadyToStartMatchPayload>(clientMsg->payload);
-				handleReady(match, player, payload.ready == 1);
-				break;
+				socket_.close();
+				throw std::runtime_error("Failed to bind " + endpointString(address, port) + ": " + bindEc.message());
 			}
-			case ClientMessageType::Input:
+			return;
+		}
+
+		// Prefer a dual-stack socket so IPv6-only players can connect; IPv4 peers
+		// arrive on it as v4-mapped addresses
+		std::error_code ec;
+		socket_.open(udp::v6(), ec);
+		if (!ec)
+		{
+			socket_.set_option(asio::ip::v6_only(false), ec);
+			if (!ec)
 			{
-				auto payload = std::get<InputPayload>(clientMsg->payload);
-				handleClientInput(match, player, payload);
-				break;
+				socket_.bind(udp::endpoint(udp::v6(), port), ec);
 			}
-			case ClientMessageType::Disconnecting:
+			if (!ec)
 			{
-				// Mark player as disconnected
-				{
-					std::unique_lock lock(player->mutex);
-					player->disconnected = true;
-				}
-				std::cout << "Player index " << player->playerIndex << " sent Disconnecting message" << std::endl;
-				break;
-			}
-			default:
-				break;
+				return;
 			}
-		}
-		catch (const std::exception& e)
-		{
-			std::cerr << "Error handling message: " << e.what() << std::endl;
+			std::error_code closeEc;
+			socket_.close(closeEc);
 		}
 
-		co_return;
+		logWarn({}, "Dual-stack socket unavailable (", ec.message(), "), falling back to IPv4 only");
+		socket_.open(udp::v4());
+		socket_.bind(udp::endpoint(udp::v4(), port));
 	}
 
-	std::shared_ptr<PlayerInfo> RollbackServer::handleNewConnection(
-		const NewConnectionPayload& payload, const udp::endpoint& remote, bool debug)
+	uint16_t RollbackServer::localPort() const
 	{
+		return socket_.local_endpoint().port();
+	}
 
-		auto ipStr = remote.address().to_string();
-		auto portStr = std::to_string(remote.port());
-		std::string key = ipStr + ":" + portStr;
+	void RollbackServer::setErrorCallback(ErrorCallback callback)
+	{
+		std::lock_guard lock(errorCallbackMutex_);
+		errorCallback_ = std::move(callback);
+	}
 
-		const auto& matchData = payload.matchData;
-		std::shared_ptr<MatchState> match;
-		std::unique_lock match_lock(matches_.mutex_);
+	void RollbackServer::reportError(ServerError code, std::string message)
+	{
+		ErrorCallback callback;
 		{
-			// std::shared_lock read_lock(matches_mutex_);
-			// auto it = matches_.find(matchData.matchId);
-			auto matchOpt = matches_.find(matchData.matchId, true);
-			if (matchOpt.has_value())
-			{
-				match = matchOpt.value();
-			}
+			std::lock_guard lock(errorCallbackMutex_);
+			callback = errorCallback_;
 		}
+		if (!callback)
+			return;
+		// Callers may be holding match or player locks; run the callback on its own
+		asio::post(io_context_, [callback = std::move(callback), code, message = std::move(message)]()
+			{
+				try
+				{
+					callback(code, message);
+				}
+				catch (const std::exception& e)
+				{
+					logError({}, "Error callback threw: ", e.what());
+				}
+			});
+	}
 
-		if (!match)
-		{
-			// --- New logic: Fetch match config from HTTP server ---
-			std::cout << "New Match : " << matchData.matchId << std::endl;
-			auto configOpt = fetchMatchConfigFromServer(matchData.matchId, matchData.key);
-			if (!configOpt.has_value()) {
-				std::cerr << "Failed to fetch match config from server" << std::endl;
-				return nullptr;
-			}
-			const auto& config = configOpt.value();
-			// Create new match using config
-			match = std::make_shared<MatchState>();
-			match->matchId = matchData.matchId;
+	std::function<void(std::exception_ptr)> RollbackServer::supervise(LogContext context, std::string task)
+	{
+		return [this, context = std::move(context), task = std::move(task)](std::exception_ptr e)
+			{
+				if (!e)
+					return;
+				std::string what = "unknown exception";
+				try
+				{
+					std::rethrow_exception(e);
+				}
+				catch (const std::exception& ex)
+				{
+					what = ex.what();
+				}
+				catch (...)
+				{
+				}
+				logError(context, task, " failed: ", what);
+				reportError(ServerError::Internal, task + " failed: " + what);
+			};
+	}
+
+	RollbackServer::~RollbackServer()
+	{
+		stop();
+		backendPool_.wait(); // let end-of-match reports still queued reach the backend before it goes
+		curl_global_cleanup();
+	}
+
+	void RollbackServer::start()
+	{
+		if (running_)
+			return;
+
+		// The previous run's stop() closed the socket and stopped the io_context
+		if (!socket_.is_open())
+		{
+			resetState();
+			io_context_.restart();
+			openSocket();
+			logInfo({}, "Restarting rollback server on port ", localPort());
+		}
+		running_ = true;
+
+		restoreSnapshot();
+
+		// Only spawn UDP server; matches will spawn their own tick loops
+		asio::co_spawn(io_context_, runUdpServer(), supervise({}, "UDP server"));
+		asio::co_spawn(io_context_, runMaintenanceLoop(), supervise({}, "Maintenance loop"));
+
+		if (config_.adminPort != 0)
+		{
+			// Loopback only: the dump includes player addresses
+			adminAcceptor_ = std::make_unique<asio::ip::tcp::acceptor>(io_context_,
+				asio::ip::tcp::endpoint(asio::ip::make_address("127.0.0.1"), config_.adminPort));
+			asio::co_spawn(io_context_, runAdminServer(), supervise({}, "Admin server"));
+			logInfo({}, "Admin interface listening on 127.0.0.1:", config_.adminPort);
+		}
+
+		// Launch the configured number of threads to run the io_context_
+		for (uint32_t i = 0; i < config_.workerThreads; ++i)
+		{
+			worker_threads_.emplace_back([this]()
+				{
+					try {
+						io_context_.run();
+					}
+					catch (const std::exception& e) {
+						logError({}, "Exception in io_context thread: ", e.what());
+					} });
+		}
+
+		logInfo({}, "Rollback server started with ", config_.workerThreads, " worker thread(s)");
+	}
+
+	void RollbackServer::stop()
+	{
+		if (!running_)
+			return;
+		running_ = false;
+		generation_++;
+
+		// Nothing of this run may carry on into the next start(): tick loops and ping
+		// phases stop at their next wakeup, and closing the sockets aborts pending reads
+		for (const auto& m : matches_.snapshot())
+		{
+			m.second->tickRunning = false;
+			m.second->phase = MatchPhase::Ended;
+		}
+		std::error_code ec;
+		socket_.close(ec);
+		if (adminAcceptor_)
+		{
+			adminAcceptor_->close(ec);
+		}
+
+		// Let every loop see that and return, so the io_context runs out of work
+		// rather than being stopped with their handlers still queued
+		const auto deadline = steady_clock::now() + STOP_DRAIN_TIMEOUT;
+		while (!io_context_.stopped() && steady_clock::now() < deadline)
+		{
+			std::this_thread::sleep_for(std::chrono::milliseconds(10));
+		}
+		if (!io_context_.stopped())
+		{
+			logWarn({}, "Handlers still running after ", STOP_DRAIN_TIMEOUT.count(), "s, stopping anyway");
+			io_context_.stop();
+		}
+
+		for (auto& t : worker_threads_)
+		{
+			if (t.joinable())
+				t.join();
+		}
+		worker_threads_.clear();
+		adminAcceptor_.reset();
+
+		logInfo({}, "Rollback server stopped");
+	}
+
+	void RollbackServer::resetState()
+	{
+		if (running_)
+			throw std::logic_error("resetState() called while the server is running");
+
+		for (const auto& m : matches_.snapshot())
+		{
+			m.second->tickRunning = false;
+			m.second->phase = MatchPhase::Ended;
+		}
+		matches_.clear();
+		players_.clear();
+		{
+			std::lock_guard lock(endpointsByAddressMutex_);
+			endpointsByAddress_.clear();
+		}
+		{
+			std::lock_guard lock(active_ping_mutex_);
+			active_ping_matches_.clear();
+		}
+		malformedPackets_ = 0;
+		activeHandlers_ = 0;
+		handlerOverflows_ = 0;
+	}
+
+	bool RollbackServer::isMatchActive(const std::string& matchId) const
+	{
+		auto matchOpt = matches_.find(matchId);
+		return matchOpt.has_value() && matchOpt.value()->phase != MatchPhase::Ended;
+	}
+
+	std::optional<MatchStats> RollbackServer::getMatchStats(const std::string& matchId) const
+	{
+		auto matchOpt = matches_.find(matchId);
+		if (!matchOpt.has_value())
+		{
+			return std::nullopt;
+		}
+		return buildMatchStats(matchOpt.value());
+	}
+
+	std::vector<MatchStats> RollbackServer::getAllMatchStats() const
+	{
+		std::vector<MatchStats> result;
+		for (const auto& m : matches_.snapshot())
+		{
+			result.push_back(buildMatchStats(m.second));
+		}
+		return result;
+	}
+
+	std::string RollbackServer::getMatchStatsJson() const
+	{
+		nlohmann::json j = nlohmann::json::object();
+		for (const auto& stats : getAllMatchStats())
+		{
+			j[stats.matchId] = stats;
+		}
+		return j.dump();
+	}
+
+	std::optional<std::vector<LobbySlot>> RollbackServer::getLobbyState(const std::string& matchId) const
+	{
+		auto matchOpt = matches_.find(matchId);
+		if (!matchOpt.has_value())
+		{
+			return std::nullopt;
+		}
+		auto match = matchOpt.value();
+
+		std::vector<LobbySlot> slots(match->max_players_);
+		for (size_t i = 0; i < slots.size(); ++i)
+		{
+			slots[i] = { static_cast<uint16_t>(i), false, false, 0 };
+		}
+		for (const auto& p : match->players.snapshot())
+		{
+			auto player = p.second;
+			std::shared_lock lock(player->mutex);
+			if (player->playerIndex < slots.size())
+			{
+				slots[player->playerIndex] = { player->playerIndex, !player->disconnected, player->ready, player->ping };
+			}
+		}
+		return slots;
+	}
+
+	std::string RollbackServer::getLobbyStateJson(const std::string& matchId) const
+	{
+		auto slots = getLobbyState(matchId);
+		return nlohmann::json(slots.value_or(std::vector<LobbySlot>{})).dump();
+	}
+
+	std::vector<TelemetrySample> RollbackServer::getTelemetryHistory(const std::string& matchId) const
+	{
+		auto matchOpt = matches_.find(matchId);
+		if (!matchOpt.has_value())
+		{
+			return {};
+		}
+		auto match = matchOpt.value();
+		std::lock_guard lock(match->telemetryMutex);
+		return { match->telemetry.begin(), match->telemetry.end() };
+	}
+
+	MatchStats RollbackServer::buildMatchStats(const std::shared_ptr<MatchState>& match) const
+	{
+		MatchStats stats;
+		stats.matchId = match->matchId;
+		stats.phase = match->phase;
+		stats.spectators = match->spectators.size();
+		stats.traffic = {
+			match->traffic.packetsSent,
+			match->traffic.bytesSent,
+			match->traffic.uncompressedBytesSent,
+			match->traffic.packetsReceived,
+			match->traffic.bytesReceived,
+			match->traffic.sendBytesPerSec,
+			match->traffic.recvBytesPerSec,
+		};
+		{
+			std::shared_lock lock(match->mutex);
+			stats.currentFrame = match->currentFrame;
+			stats.sequenceCounter = match->sequenceCounter;
+		}
+
+		for (const auto& p : match->players.snapshot())
+		{
+			auto player = p.second;
+			PlayerStats ps;
+			{
+				std::shared_lock lock(player->mutex);
+				ps.playerIndex = player->playerIndex;
+				ps.endpoint = endpointString(player->address, player->port);
+				ps.disconnected = player->disconnected;
+				ps.ping = player->ping;
+				ps.smoothedPing = player->smoothedPing;
+				ps.packetsLossPercent = player->packetsLossPercent;
+				ps.rift = player->smoothRift;
+				ps.rawRift = player->rift;
+				ps.lastClientFrame = player->lastClientFrame;
+				ps.ackedFrames = player->ackedFrames;
+				ps.jitterMs = player->jitter.jitterMs;
+				ps.inputBufferFrames = player->inputBufferFrames;
+				ps.stalled = player->stalled;
+				ps.stallCount = player->stallCount;
+			}
+			ps.sendQueueDrops = player->sendQueueDrops;
+			ps.lagging = player->lagging;
+			ps.lagCount = player->lagCount;
+			ps.bufferedInputs = ps.playerIndex < match->inputs.size() ? match->inputs[ps.playerIndex].size() : 0;
+			stats.players.push_back(std::move(ps));
+		}
+
+		std::sort(stats.players.begin(), stats.players.end(),
+			[](const PlayerStats& a, const PlayerStats& b) { return a.playerIndex < b.playerIndex; });
+		return stats;
+	}
+
+	asio::awaitable<void> RollbackServer::runUdpServer()
+	{
+		std::vector<uint8_t> recv_buffer(1024);
+		const uint32_t generation = generation_;
+
+		while (running_ && generation == generation_)
+		{
+			try
+			{
+				// Wait for incoming message
+				udp::endpoint remote;
+				size_t bytes_received = co_await socket_.async_receive_from(
+					asio::buffer(recv_buffer), remote,
+					asio::use_awaitable);
+
+				// Hand the handler only the bytes of this datagram so nothing left
+				// over from an earlier, longer one can be read
+				std::vector<uint8_t> packet(recv_buffer.begin(), recv_buffer.begin() + bytes_received);
+
+				std::chrono::milliseconds delay{ 0 };
+				if (conditioner_.enabled())
+				{
+					auto fate = conditioner_.delayFor();
+					if (!fate)
+						continue; // simulated loss
+					delay = *fate;
+				}
+
+				// Process message asynchronously
+				if (delay.count() > 0)
+				{
+					asio::co_spawn(io_context_,
+						[this, packet = std::move(packet), remote, delay]() mutable -> asio::awaitable<void>
+						{
+							asio::steady_timer timer(io_context_, delay);
+							co_await timer.async_wait(asio::use_awaitable);
+							co_await handleMessage(std::move(packet), remote);
+						},
+						supervise(logCtx(remote), "Message handler"));
+					continue;
+				}
+				if (config_.maxConcurrentHandlers > 0 && activeHandlers_ >= config_.maxConcurrentHandlers)
+				{
+					handlerOverflows_++;
+					if (config_.handlerOverflowPolicy == HandlerOverflowPolicy::Drop)
+					{
+						logDebug(logCtx(remote), "Dropping packet: all message handlers busy");
+						continue;
+					}
+					co_await handleMessage(std::move(packet), remote);
+					continue;
+				}
+				activeHandlers_++;
+				asio::co_spawn(io_context_,
+					handleMessage(std::move(packet), remote),
+					[this, done = supervise(logCtx(remote), "Message handler")](std::exception_ptr e)
+					{
+						activeHandlers_--;
+						done(e);
+					});
+			}
+			catch (const std::exception& e)
+			{
+				// stop() closing the socket under a pending receive is how this loop ends
+				if (!running_ || generation != generation_)
+					break;
+				logError({}, "Error in UDP server: ", e.what());
+				reportError(ServerError::Internal, std::string("UDP server: ") + e.what());
+			}
+		}
+
+		co_return;
+	}
+
+	asio::awaitable<void> RollbackServer::runMaintenanceLoop()
+	{
+		asio::steady_timer timer(io_context_);
+		auto lastSnapshot = clock_->now();
+		const uint32_t generation = generation_;
+		while (running_ && generation == generation_)
+		{
+			timer.expires_after(std::chrono::milliseconds(250));
+			try
+			{
+				co_await timer.async_wait(asio::use_awaitable);
+			}
+			catch (const std::system_error&)
+			{
+				break;
+			}
+
+			for (const auto& m : matches_.snapshot())
+			{
+				updateThroughput(m.second->traffic, clock_->now());
+				auto members = m.second->players.snapshot();
+				members.merge(m.second->spectators.snapshot());
+				for (const auto& p : members)
+				{
+					prunePendingPings(p.second, clock_->now());
+				}
+				retransmitDisconnectNotices(m.second, clock_->now());
+				checkReadyTimeout(m.second, clock_->now());
+				sweepIdlePlayers(m.second, clock_->now());
+				if (m.second->phase == MatchPhase::Ended)
+					continue;
+
+				// A running match cleans itself up from the tick loop; one that never got
+				// that far would otherwise stay registered after everyone left
+				if (!m.second->tickRunning && m.second->players.size() > 0 && allPlayersDisconnected(m.second))
+				{
+					endMatch(m.second, "abandoned before start");
+					continue;
+				}
+
+				try
+				{
+					co_await sendKeepalives(m.second);
+				}
+				catch (const std::exception& e)
+				{
+					logError(logCtx(m.second), "Exception in maintenance loop: ", e.what());
+					reportError(ServerError::Internal, "Maintenance of match " + m.first + ": " + e.what());
+				}
+			}
+			connectLimiter_.prune();
+
+			if (!config_.snapshotPath.empty() &&
+				clock_->now() - lastSnapshot >= std::chrono::milliseconds(config_.snapshotIntervalMs))
+			{
+				lastSnapshot = clock_->now();
+				saveSnapshot();
+			}
+		}
+
+		co_return;
+	}
+
+	asio::awaitable<void> RollbackServer::runAdminServer()
+	{
+		const uint32_t generation = generation_;
+		while (running_ && generation == generation_ && adminAcceptor_ && adminAcceptor_->is_open())
+		{
+			try
+			{
+				auto conn = co_await adminAcceptor_->async_accept(asio::use_awaitable);
+				std::error_code ec;
+				const auto peer = conn.remote_endpoint(ec);
+				if (ec || !normalizeAddress(peer.address()).is_loopback())
+				{
+					logWarn({}, "Refusing admin connection from ", ec ? "unknown peer" : peer.address().to_string());
+					conn.close(ec);
+					continue;
+				}
+
+				// Read-only: write one snapshot and hang up, whatever the client sent
+				std::string body = getMatchStatsJson() + "\n";
+				co_await asio::async_write(conn, asio::buffer(body), asio::use_awaitable);
+				conn.shutdown(asio::ip::tcp::socket::shutdown_both, ec);
+				conn.close(ec);
+			}
+			catch (const std::system_error& e)
+			{
+				if (!running_ || generation != generation_)
+					break;
+				logWarn({}, "Admin connection failed: ", e.what());
+			}
+		}
+
+		co_return;
+	}
+
+	void RollbackServer::saveSnapshot()
+	{
+		nlohmann::json matchesJson = nlohmann::json::array();
+		for (const auto& m : matches_.snapshot())
+		{
+			auto match = m.second;
+			if (match->phase == MatchPhase::Ended)
+				continue;
+
+			nlohmann::json playersJson = nlohmann::json::array();
+			auto members = match->players.snapshot();
+			members.merge(match->spectators.snapshot());
+			for (const auto& p : members)
+			{
+				auto player = p.second;
+				std::shared_lock lock(player->mutex);
+				playersJson.push_back({
+					{ "address", player->address.to_string() },
+					{ "port", player->port },
+					{ "player_index", player->playerIndex },
+					{ "team_id", player->teamId },
+					{ "spectator", player->spectator },
+					{ "ready", player->ready },
+					{ "disconnected", player->disconnected },
+					{ "kicked_for", player->kickedFor ? static_cast<int>(*player->kickedFor) : 0 },
+					{ "last_seq_recv", player->lastSeqRecv },
+					{ "last_seq_sent", player->lastSeqSent },
+					{ "last_client_frame", player->lastClientFrame },
+					{ "acked_frames", player->ackedFrames },
+					{ "session_nonce", player->sessionNonce.has_value() },
+				});
+			}
+
+			nlohmann::json rosterJson = nlohmann::json::array();
+			{
+				std::lock_guard rosterLock(match->rosterMutex);
+				for (const auto& allowed : match->allowedPlayers)
+				{
+					rosterJson.push_back({ { "player_index", allowed.player_index }, { "ip", allowed.ip }, { "is_host", allowed.is_host }, { "team_index", allowed.team_index } });
+				}
+			}
+
+			std::shared_lock lock(match->mutex);
+			matchesJson.push_back({
+				{ "match_id", match->matchId },
+				{ "key", match->key },
+				{ "phase", static_cast<int>(match->phase.load()) },
+				{ "max_players", match->max_players_ },
+				{ "netcode_mode", static_cast<int>(match->netcodeMode) },
+				{ "input_delay", match->inputDelayFrames },
+				{ "released_frame", match->releasedFrame },
+				{ "duration_in_frames", match->durationInFrames },
+				{ "session_nonce", match->sessionNonce },
+				{ "current_frame", match->currentFrame },
+				{ "sequence", match->sequenceCounter },
+				{ "roster", rosterJson },
+				{ "players", playersJson },
+			});
+		}
+
+		// Write next to the target and rename so a crash mid-write never leaves a torn file
+		const std::string tmpPath = config_.snapshotPath + ".tmp";
+		{
+			std::ofstream out(tmpPath, std::ios::trunc);
+			if (!out)
+			{
+				logWarn({}, "Could not write snapshot ", tmpPath);
+				return;
+			}
+			// It holds every match key, so only the server's own user may read it
+			std::error_code permissionsEc;
+			std::filesystem::permissions(tmpPath, std::filesystem::perms::owner_read | std::filesystem::perms::owner_write,
+				std::filesystem::perm_options::replace, permissionsEc);
+			if (permissionsEc)
+			{
+				logWarn({}, "Could not restrict snapshot ", tmpPath, ": ", permissionsEc.message());
+				return;
+			}
+			const auto savedAt = std::chrono::duration_cast<std::chrono::milliseconds>(
+				std::chrono::system_clock::now().time_since_epoch()).count();
+			out << nlohmann::json{ { "saved_at_ms", savedAt }, { "matches", matchesJson } }.dump();
+		}
+		std::error_code ec;
+		std::filesystem::rename(tmpPath, config_.snapshotPath, ec);
+		if (ec)
+		{
+			logWarn({}, "Could not replace snapshot ", config_.snapshotPath, ": ", ec.message());
+		}
+	}
+
+	void RollbackServer::restoreSnapshot()
+	{
+		if (config_.snapshotPath.empty())
+			return;
+
+		std::ifstream in(config_.snapshotPath);
+		if (!in)
+			return;
+
+		nlohmann::json root = nlohmann::json::parse(in, nullptr, false);
+		if (root.is_discarded() || !root.contains("matches"))
+		{
+			logWarn({}, "Ignoring unreadable snapshot ", config_.snapshotPath);
+			return;
+		}
+
+		const auto now = clock_->now();
+		// Wall time, not the server clock: that one started over with this process
+		int64_t downMs = 0;
+		if (root.contains("saved_at_ms") && root["saved_at_ms"].is_number_integer())
+		{
+			downMs = std::max<int64_t>(0, std::chrono::duration_cast<std::chrono::milliseconds>(
+				std::chrono::system_clock::now().time_since_epoch()).count() - root["saved_at_ms"].get<int64_t>());
+		}
+		for (const auto& m : root["matches"])
+		{
+			try
+			{
+				const int maxPlayers = m.at("max_players").get<int>();
+				if (maxPlayers < 1 || maxPlayers > MAX_MATCH_PLAYERS)
+					continue;
+
+				auto match = std::make_shared<MatchState>();
+				match->traffic.rateSampleTime = now;
+				match->matchId = m.at("match_id").get<std::string>();
+				match->key = m.at("key").get<std::string>();
+				const int phase = m.at("phase").get<int>();
+				if (phase != static_cast<int>(MatchPhase::WaitingForPlayers) && phase != static_cast<int>(MatchPhase::InProgress))
+					throw std::runtime_error("unknown phase " + std::to_string(phase));
+				match->phase = static_cast<MatchPhase>(phase);
+				match->max_players_ = maxPlayers;
+				const int netcodeMode = m.value("netcode_mode", 0);
+				if (netcodeMode != static_cast<int>(NetcodeMode::Rollback) && netcodeMode != static_cast<int>(NetcodeMode::InputDelay))
+					throw std::runtime_error("unknown netcode mode " + std::to_string(netcodeMode));
+				match->netcodeMode = static_cast<NetcodeMode>(netcodeMode);
+				match->inputDelayFrames = m.value("input_delay", 0);
+				match->riftMin = config_.riftMin;
+				match->riftMax = config_.riftMax;
+				match->releasedFrame = m.value("released_frame", 0u);
+				match->durationInFrames = m.at("duration_in_frames").get<uint32_t>();
+				match->sessionNonce = m.value("session_nonce", static_cast<uint32_t>(std::random_device{}()));
+				match->tickIntervalMs = 1000.0f / config_.tickRate;
+				match->currentFrame = m.at("current_frame").get<uint32_t>();
+				if (match->phase == MatchPhase::InProgress)
+				{
+					// The clients kept their clocks running while we were down; pick up where they are
+					// now rather than where we stopped, and let a match that ran out in the meantime go
+					const uint64_t missedFrames = static_cast<uint64_t>(downMs / match->tickIntervalMs);
+					const uint64_t resumeFrame = match->currentFrame + missedFrames;
+					if (match->durationInFrames != 0 && resumeFrame >= match->durationInFrames)
+						throw std::runtime_error("match " + match->matchId + " ran out while the server was down");
+					match->currentFrame = static_cast<uint32_t>(std::min<uint64_t>(resumeFrame, UINT32_MAX));
+				}
+				match->sequenceCounter = m.at("sequence").get<uint32_t>();
+				match->inputs.resize(maxPlayers);
+				match->predictedFrames.resize(maxPlayers);
+				match->prunedThrough.resize(maxPlayers, 0);
+				match->pingPhaseCount = 0;
+				match->pingPhaseTotal = config_.pingCount;
+				match->tickRunning = false;
+				for (const auto& r : m.at("roster"))
+				{
+					const uint16_t index = r.at("player_index").get<uint16_t>();
+					match->allowedPlayers.push_back({ index, r.at("ip").get<std::string>(), r.at("is_host").get<bool>(), r.value("team_index", static_cast<uint16_t>(index % 2)) });
+				}
+
+				for (const auto& p : m.at("players"))
+				{
+					auto player = std::make_shared<PlayerInfo>();
+					player->address = asio::ip::make_address(p.at("address").get<std::string>());
+					player->port = p.at("port").get<uint16_t>();
+					player->matchId = match->matchId;
+					player->playerIndex = p.at("player_index").get<uint16_t>();
+					player->teamId = p.value("team_id", 0);
+					player->spectator = p.at("spectator").get<bool>();
+					player->ready = p.at("ready").get<bool>();
+					player->disconnected = p.at("disconnected").get<bool>();
+					const int kickedFor = p.value("kicked_for", 0);
+					if (kickedFor > 0 && kickedFor <= static_cast<int>(KickReason::InputHistoryLost))
+					{
+						player->kickedFor = static_cast<KickReason>(kickedFor);
+					}
+					player->lastSeqRecv = p.at("last_seq_recv").get<uint32_t>();
+					player->lastSeqSent = p.at("last_seq_sent").get<uint32_t>();
+					player->lastClientFrame = p.at("last_client_frame").get<uint32_t>();
+					player->ackedFrames = p.at("acked_frames").get<std::vector<uint32_t>>();
+					player->ackedFrames.resize(maxPlayers, 0);
+					if (p.value("session_nonce", false))
+					{
+						player->sessionNonce = match->sessionNonce;
+					}
+					player->lastInputTime = now; // give clients the full timeout to find us again
+					player->lastHeardFrom = now.time_since_epoch().count();
+					player->emulated = false;
+					if (!player->spectator && player->playerIndex >= maxPlayers)
+						continue;
+
+					const std::string key = endpointString(player->address, player->port);
+					(player->spectator ? match->spectators : match->players).insert_or_assign(key, player);
+					addEndpoint(key, player);
+				}
+
+				matches_.insert_or_assign(match->matchId, match);
+				logInfo(logCtx(match), "Restored match from snapshot at frame ", match->currentFrame, " (", match->players.size(), " players)");
+				if (match->phase == MatchPhase::InProgress)
+				{
+					startTickLoop(match);
+				}
+			}
+			catch (const std::exception& e)
+			{
+				logWarn({}, "Skipping match in snapshot: ", e.what());
+			}
+		}
+	}
+
+	asio::awaitable<void> RollbackServer::sendKeepalives(std::shared_ptr<MatchState> match)
+	{
+		// Once the tick loop runs every player hears from us 60 times a second
+		if (config_.keepaliveIntervalMs == 0 || match->tickRunning)
+		{
+			co_return;
+		}
+
+		// Between phases (e.g. waiting for ready-ups) NATs may drop an idle mapping,
+		// so ping anyone we haven't sent anything to for a while
+		const auto now = clock_->now();
+		const auto interval = std::chrono::milliseconds(config_.keepaliveIntervalMs);
+		for (const auto& p : match->players.snapshot())
+		{
+			auto player = p.second;
+			const auto lastSend = steady_clock::time_point(steady_clock::duration(player->lastAnySendTime.load()));
+			if (player->disconnected || now - lastSend < interval)
+			{
+				continue;
+			}
+
+			RequestQualityDataPayload payload;
+			payload.ping = player->ping;
+			payload.packetsLossPercent = player->packetsLossPercent;
+			co_await sendServerMessage(match, player, ServerMessageType::RequestQualityData, payload, now);
+		}
+
+		co_return;
+	}
+
+	asio::awaitable<void> RollbackServer::handleMessage(
+		std::vector<uint8_t> packet, udp::endpoint remote)
+	{
+
+		try
+		{
+			// Checksummed clients CRC every packet, their first connection packet included,
+			// so a stranger's packet is taken as checksummed only if its trailer verifies
+			const std::string sender = endpointString(remote.address(), remote.port());
+			bool checksummed = false;
+			bool compressed = true;
+			InputEncoding inputEncoding = InputEncoding::Raw;
+			auto known = players_.find(sender);
+			// A player whose NAT moved it to a new port is decoded as that player, but only
+			// re-keyed once its packet has passed the same checks as any other
+			std::shared_ptr<PlayerInfo> rebound;
+			if (!known.has_value() && config_.rebindPorts)
+			{
+				rebound = findRebindCandidate(remote);
+				if (rebound)
+				{
+					known = rebound;
+				}
+			}
+			if (known.has_value())
+			{
+				checksummed = known.value()->checksums;
+				compressed = known.value()->compression;
+				inputEncoding = known.value()->inputEncoding;
+			}
+			else if (config_.uncompressedClients && isUncompressedConnection(packet))
+			{
+				compressed = false;
+			}
+			else if (config_.packetChecksums)
+			{
+				checksummed = hasValidChecksum(packet);
+			}
+
+			// Decompress and parse message. The scratch buffer is per thread, which is
+			// safe only because nothing suspends until the parse has copied out of it
+			static thread_local std::vector<uint8_t> decompressed;
+			try
+			{
+				if (compressed)
+				{
+					decompressPacketInto(packet, decompressed, 1024, checksummed);
+				}
+				else
+				{
+					decompressed.assign(packet.begin(), packet.end());
+				}
+			}
+			catch (const ChecksumMismatch&)
+			{
+				logWarn(logCtx(remote), "Dropped packet with a bad checksum");
+				known.value()->corruptPackets++; // only known players get verified
+				co_return;
+			}
+			catch (const std::runtime_error& e)
+			{
+				// Garbage off the wire is routine for a public UDP port, not a server error
+				malformedPackets_++;
+				logWarn(logCtx(remote), "Dropped undecodable packet (", e.what(), "), ", packet.size(), " bytes: ", hexPrefix(packet));
+				co_return;
+			}
+
+			// Everything but a (re)connection from a nonce client must carry its match's nonce
+			if (known.has_value() && known.value()->sessionNonce && !decompressed.empty()
+				&& decompressed[0] != static_cast<uint8_t>(ClientMessageType::NewConnection)
+				&& decompressed[0] != static_cast<uint8_t>(ClientMessageType::SpectatorConnection)
+				&& !takeSessionNonce(decompressed, *known.value()->sessionNonce))
+			{
+				malformedPackets_++;
+				logWarn(logCtx(remote), "Dropped packet without this match's session nonce");
+				co_return;
+			}
+
+			std::string parseError;
+			auto clientMsg = parseClientMessage(decompressed, config_.inputWidth, &parseError, inputEncoding);
+
+			if (!clientMsg)
+			{
+				malformedPackets_++;
+				logDebug(logCtx(remote), "Dropped malformed packet: ", parseError);
+				co_return;
+			}
+
+			const auto& header = clientMsg->header;
+			const auto& type = header.type;
+			const auto& sequence = header.sequence;
+
+			// Log packet
+			// logPacket(packet, std::to_string(static_cast<int>(type)), "RECV");
+
+			// Get the match (if any)
+			std::shared_ptr<MatchState> match;
+			std::shared_ptr<PlayerInfo> player;
+
+			if (type == ClientMessageType::NewConnection || type == ClientMessageType::SpectatorConnection)
+			{
+				// Special case for new connection
+				const auto* payload = expectPayload<NewConnectionPayload>(*clientMsg, remote);
+				if (!payload)
+					co_return;
+				player = type == ClientMessageType::SpectatorConnection
+					? handleNewSpectator(*payload, remote)
+					: handleNewConnection(*payload, remote);
+				// A connection packet that isn't newer than what we have from this endpoint
+				// is a client that restarted on the same port
+				if (player && known.has_value() && known.value() == player && !isSequenceNewer(sequence, player->lastSeqRecv))
+				{
+					logInfo(logCtx(player), "Connection restarted at sequence ", sequence);
+					resetConnectionSequence(player);
+				}
+				if (player)
+				{
+					auto matchOptional = matches_.find(player->matchId);
+					if (matchOptional.has_value())
+					{
+						match = matchOptional.value();
+					}
+				}
+			}
+			else
+			{
+				// Find existing player
+				std::string key = endpointString(remote.address(), remote.port());
+
+				auto playerOptional = players_.find(key);
+				if (playerOptional.has_value())
+				{
+					player = playerOptional.value();
+				}
+				else if (rebound)
+				{
+					player = rebound;
+				}
+
+				if (player)
+				{
+					auto matchOptional = matches_.find(player->matchId);
+					if (matchOptional.has_value())
+					{
+						match = matchOptional.value();
+					}
+				}
+
+			}
+
+			if (!player || !match)
+			{
+				co_return;
+			}
+
+			// Stragglers for a match that is being torn down
+			if (match->phase == MatchPhase::Ended)
+			{
+				co_return;
+			}
+
+			match->traffic.packetsReceived++;
+			match->traffic.bytesReceived += packet.size();
+			player->lastHeardFrom = clock_->now().time_since_epoch().count();
+
+			if (match->recorder)
+			{
+				match->recorder->record(ReplayDirection::ClientToServer, replayPlayerIndex(player), packet);
+			}
+
+			// Filter out-of-order packets
+			if (!isSequenceNewer(sequence, player->lastSeqRecv))
+			{
+				co_return;
+			}
+			// One corrupt or forged sequence far ahead would otherwise make every genuine
+			// packet after it look stale
+			if (config_.maxSequenceJump != 0 && player->lastSeqRecv != 0
+				&& sequence - player->lastSeqRecv > config_.maxSequenceJump)
+			{
+				// A restarted counter shows up as a run of packets that follow on from each
+				// other; scattered garbage each starts the run over
+				const bool continuesRun = player->sequenceJumps != 0 && isSequenceNewer(sequence, player->jumpSequence)
+					&& sequence - player->jumpSequence <= config_.maxSequenceJump;
+				player->sequenceJumps = continuesRun ? player->sequenceJumps + 1 : 1;
+				player->jumpSequence = sequence;
+				if (config_.sequenceResyncPackets == 0 || player->sequenceJumps < config_.sequenceResyncPackets)
+				{
+					logWarn(logCtx(player), "Dropped packet with sequence ", sequence, ", ", sequence - player->lastSeqRecv, " past the last one");
+					co_return;
+				}
+				logWarn(logCtx(player), "Sequence jumped to ", sequence, " for ", player->sequenceJumps, " packets in a row, resynchronizing");
+			}
+			player->sequenceJumps = 0;
+			player->lastSeqRecv = sequence;
+
+			// Only now, with its nonce (if any) and sequence checked like any other packet,
+			// does a packet from a new port move the player there
+			if (rebound && player == rebound && type != ClientMessageType::NewConnection && type != ClientMessageType::SpectatorConnection)
+			{
+				logWarn(logCtx(player), "Player's port changed mid-match, now sending from ", endpointString(remote.address(), remote.port()));
+				rekeyPlayer(match, player, remote);
+			}
+
+			// Handle quality data
+			if (type == ClientMessageType::QualityData)
+			{
+				if (const auto* payload = expectPayload<QualityDataPayload>(*clientMsg, remote))
+				{
+					std::unique_lock lock(player->mutex);
+					recordRtt(player, payload->serverMessageSequenceNumber);
+				}
+			}
+
+			// Handle other message types
+			switch (type)
+			{
+			case ClientMessageType::PlayerInputAck:
+			{
+				const auto* payload = expectPayload<PlayerInputAckPayload>(*clientMsg, remote);
+				if (payload)
+					handlePlayerInputAck(match, player, *payload);
+				break;
+			}
+			case ClientMessageType::ReadyToStartMatch:
+			{
+				if (player->spectator)
+					break;
+				// Late resends after kick-off; the tick loop keeps repeating StartGame instead
+				if (match->phase != MatchPhase::WaitingForPlayers)
+					break;
+				const auto* payload = expectPayload<ReadyToStartMatchPayload>(*clientMsg, remote);
+				if (payload)
+					handleReady(match, player, payload->ready == 1);
+				break;
+			}
+			case ClientMessageType::Input:
+			{
+				if (player->spectator)
+					break;
+				// Frames only mean something once the tick loop is counting them; input from
+				// the ping or ready phase (a reordered packet, a client ahead of StartGame) is dropped
+				if (match->phase != MatchPhase::InProgress)
+				{
+					logWarn(logCtx(player), "Dropped input received before the match started");
+					break;
+				}
+				const auto* payload = expectPayload<InputPayload>(*clientMsg, remote);
+				if (payload)
+					handleClientInput(match, player, *payload);
+				break;
+			}
+			case ClientMessageType::Disconnecting:
+			{
+				if (player->spectator)
+				{
+					std::string key = endpointString(remote.address(), remote.port());
+					match->spectators.erase(key);
+					removeEndpoint(key);
+					logInfo(logCtx(player), "Spectator left");
+					break;
+				}
+				// Mark player as disconnected
+				bool wasConnected;
+				{
+					std::unique_lock lock(player->mutex);
+					wasConnected = !player->disconnected;
+					player->disconnected = true;
+				}
+				logInfo(logCtx(player), "Player sent Disconnecting message");
+				if (wasConnected)
+				{
+					announceDisconnect(match, player);
+				}
+				break;
+			}
+			case ClientMessageType::MatchResult:
+			{
+				if (player->spectator || match->phase != MatchPhase::InProgress)
+					break;
+				const auto* payload = expectPayload<MatchResultPayload>(*clientMsg, remote);
+				if (!payload)
+					break;
+				std::lock_guard lock(match->resultMutex);
+				auto [it, first] = match->reportedWinners.insert_or_assign(player->playerIndex, payload->winningTeamIndex);
+				if (first)
+				{
+					logInfo(logCtx(player), "Reported team ", +payload->winningTeamIndex, " as the winner");
+				}
+				break;
+			}
+			case ClientMessageType::PlayerDisconnectedAck:
+			{
+				const auto* payload = expectPayload<PlayerDisconnectedAckPayload>(*clientMsg, remote);
+				if (payload)
+					handleDisconnectAck(match, player, *payload);
+				break;
+			}
+			default:
+				break;
+			}
+		}
+		catch (const std::exception& e)
+		{
+			logError(logCtx(remote), "Error handling message: ", e.what());
+			reportError(ServerError::Internal, "Handling message from " + endpointString(remote.address(), remote.port()) + ": " + e.what());
+		}
+
+		co_return;
+	}
+
+	std::shared_ptr<PlayerInfo> RollbackServer::handleNewConnection(
+		const NewConnectionPayload& payload, const udp::endpoint& remote, bool debug)
+	{
+
+		std::string key = endpointString(remote.address(), remote.port());
+
+		const auto& matchData = payload.matchData;
+		if (!checkProtocolVersion(payload, remote))
+		{
+			return nullptr;
+		}
+
+		std::shared_ptr<MatchState> match;
+		std::unique_lock match_lock(matches_.mutex_);
+		{
+			// std::shared_lock read_lock(matches_mutex_);
+			// auto it = matches_.find(matchData.matchId);
+			auto matchOpt = matches_.find(matchData.matchId, true);
+			if (matchOpt.has_value())
+			{
+				match = matchOpt.value();
+			}
+		}
+
+		if (!match)
+		{
+			// Every unknown match id costs a backend round trip, so throttle per source
+			if (!connectLimiter_.allow(remote.address().to_string()))
+			{
+				logWarn({ matchData.matchId, std::nullopt, key }, "Dropping connection attempt: rate limit exceeded");
+				return nullptr;
+			}
+
+			// --- New logic: Fetch match config from HTTP server ---
+			logInfo({ matchData.matchId, std::nullopt, key }, "New match");
+			auto configOpt = backend_->registerMatch(matchData.matchId, matchData.key);
+			if (!configOpt.has_value()) {
+				rejectConnection(payload, remote, KickReason::MatchUnavailable, 0, ServerError::MatchRegistration,
+					"no match config from the backend");
+				return nullptr;
+			}
+			const auto& config = configOpt.value();
+			if (config.max_players < 1 || config.max_players > MAX_MATCH_PLAYERS) {
+				rejectConnection(payload, remote, KickReason::MatchUnavailable, 0, ServerError::MatchRejected,
+					"backend returned max_players=" + std::to_string(config.max_players) + " (allowed 1-" + std::to_string(MAX_MATCH_PLAYERS) + ")");
+				return nullptr;
+			}
+			// Per-player state is sized by max_players and indexed by player_index,
+			// so a roster that disagrees with it can't be played
+			if (auto problem = rosterProblem(config))
+			{
+				rejectConnection(payload, remote, KickReason::MatchUnavailable, 0, ServerError::MatchRejected, *problem);
+				return nullptr;
+			}
+			// Create new match using config
+			match = std::make_shared<MatchState>();
+			match->matchId = matchData.matchId;
 			match->key = matchData.key;
 			match->durationInFrames = config.match_duration;
-			match->tickIntervalMs = 1000.0f / 60.0f;
+			match->sessionNonce = std::random_device{}();
+			match->traffic.rateSampleTime = clock_->now();
+			match->tickIntervalMs = 1000.0f / config_.tickRate;
 			match->currentFrame = 0;
 			match->inputs.resize(config.max_players);
+			match->predictedFrames.resize(config.max_players);
+			match->prunedThrough.resize(config.max_players, 0);
 			match->pingPhaseCount = 0;
-			match->pingPhaseTotal = 20;
+			match->pingPhaseTotal = config_.pingCount;
 			match->sequenceCounter = -1;
 			match->tickRunning = false;
 			match->max_players_ = config.max_players;
+			match->netcodeMode = config.netcode_mode;
+			match->inputDelayFrames = config.input_delay;
+			match->riftMin = config_.riftMin;
+			match->riftMax = config_.riftMax;
+			match->allowedPlayers = config.players;
+			if (!config_.replayDirectory.empty())
+			{
+				auto path = config_.replayDirectory + "/" + replayFileName(matchData.matchId);
+				match->recorder = std::make_shared<ReplayRecorder>(path);
+				if (!match->recorder->isOpen())
+				{
+					logWarn(logCtx(match), "Could not open replay file ", path);
+					match->recorder.reset();
+				}
+			}
 			matches_.insert_or_assign(matchData.matchId, match, true);
 		}
-		match_lock.unlock();
+		match_lock.unlock();
+
+		if (!checkMatchKey(match, payload, remote))
+		{
+			return nullptr;
+		}
+
+		auto existingPlayer = players_.find(key);
+		if (existingPlayer.has_value())
+		{
+			return existingPlayer.value();
+		}
+
+		const uint16_t playerIndex = payload.playerData.playerIndex;
+		bool rosterEmpty;
+		bool allowed;
+		{
+			std::lock_guard lock(match->rosterMutex);
+			rosterEmpty = match->allowedPlayers.empty();
+			allowed = std::any_of(match->allowedPlayers.begin(), match->allowedPlayers.end(),
+				[playerIndex](const MVSIPlayer& p) { return p.player_index == playerIndex; });
+		}
+		if (rosterEmpty)
+		{
+			if (!match->rosterRefetching.exchange(true))
+			{
+				asio::co_spawn(io_context_, refetchRoster(match), supervise(logCtx(match), "Roster refetch"));
+			}
+			logInfo({ match->matchId, playerIndex, key }, "Holding connection until the backend sends the roster");
+			return nullptr;
+		}
+		if (!allowed)
+		{
+			logWarn({ match->matchId, playerIndex, key }, "Rejecting connection: player index not in match roster");
+			reportError(ServerError::ConnectionRejected, key + " is not in the roster of match " + match->matchId);
+			NewConnectionReplyPayload rejectPayload{};
+			rejectPayload.success = static_cast<uint8_t>(ConnectionResult::NotInMatch);
+			rejectPayload.matchNumPlayers = static_cast<uint8_t>(match->players.size());
+			rejectPayload.playerIndex = static_cast<uint8_t>(playerIndex);
+			rejectPayload.matchDurationInFrames = match->durationInFrames;
+			asio::co_spawn(io_context_,
+				sendUnregisteredMessage(remote, ServerMessageType::NewConnectionReply, rejectPayload, payload.messageVersion),
+				asio::detached);
+			return nullptr;
+		}
+
+		// The same slot connecting from somewhere new is the client reconnecting (a
+		// restarted socket, a new NAT mapping): carry the player over rather than
+		// adding a second one with the same index
+		for (const auto& p : match->players.snapshot())
+		{
+			if (p.second->playerIndex == playerIndex)
+			{
+				auto player = p.second;
+				std::optional<KickReason> kickedFor;
+				{
+					std::shared_lock lock(player->mutex);
+					kickedFor = player->kickedFor;
+				}
+				if (kickedFor)
+				{
+					rejectConnection(payload, remote, *kickedFor, 0, ServerError::ConnectionRejected,
+						"player " + std::to_string(playerIndex) + " was kicked from the match");
+					return nullptr;
+				}
+				// Knowing the match key isn't enough to move someone else's slot
+				if (!mayReclaimSlot(match, player, remote))
+				{
+					rejectConnection(payload, remote, KickReason::SlotTaken, 0, ServerError::ConnectionRejected,
+						"player " + std::to_string(playerIndex) + " is connected from another address");
+					return nullptr;
+				}
+				logInfo(logCtx(player), "Player reconnected from ", key);
+				rekeyPlayer(match, player, remote);
+				resetConnectionSequence(player);
+				{
+					// The reply already goes out in whatever format this connection asked for
+					std::unique_lock lock(player->mutex);
+					applyCapabilities(player, payload.messageVersion, match->sessionNonce);
+				}
+				sendConnectionReply(match, player);
+				return player;
+			}
+		}
+
+		// Only a slot already in the match may come back once the players have been
+		// configured: a latecomer would be missing from everyone's PlayersConfigurationData
+		if (match->phase != MatchPhase::WaitingForPlayers || match->configuredAt != 0)
+		{
+			uint32_t serverFrame;
+			{
+				std::shared_lock lock(match->mutex);
+				serverFrame = match->currentFrame;
+			}
+			rejectConnection(payload, remote, KickReason::MatchAlreadyStarted, serverFrame, ServerError::ConnectionRejected,
+				"match already started, player " + std::to_string(playerIndex) + " wasn't in it");
+			return nullptr;
+		}
+		if (match->players.size() >= static_cast<size_t>(match->max_players_))
+		{
+			rejectConnection(payload, remote, KickReason::MatchFull, static_cast<uint32_t>(match->max_players_), ServerError::ConnectionRejected,
+				"all " + std::to_string(match->max_players_) + " slots are taken");
+			return nullptr;
+		}
+
+		// Create new player
+		auto newPlayer = std::make_shared<PlayerInfo>();
+		newPlayer->address = remote.address();
+		newPlayer->port = remote.port();
+		newPlayer->matchId = matchData.matchId;
+		newPlayer->playerIndex = payload.playerData.playerIndex;
+		newPlayer->teamId = payload.playerData.teamId;
+		applyCapabilities(newPlayer, payload.messageVersion, match->sessionNonce);
+		newPlayer->lastSeqRecv = 0;
+		newPlayer->lastSeqSent = 0;
+		newPlayer->ackedFrames.resize(match->max_players_, 0);
+		newPlayer->ping = 0;
+		newPlayer->ready = debug;
+		newPlayer->lastClientFrame = 0;
+		newPlayer->lastInputTime = clock_->now();
+		newPlayer->lastHeardFrom = newPlayer->lastInputTime.time_since_epoch().count();
+		newPlayer->rift = 0;
+		newPlayer->emulated = debug;
+
+		// Add player to match and global list
+		{
+
+			match->players.insert_or_assign(key, newPlayer);
+			addEndpoint(key, newPlayer);
+		}
+		logInfo(logCtx(newPlayer), "Player joined");
+
+		sendConnectionReply(match, newPlayer);
+
+		// Start ping phase if all players have connected
+		{
+
+			if (match->players.size() == static_cast<size_t>(match->max_players_))
+			{
+				startPingPhase(match);
+			}
+		}
+
+		return newPlayer;
+	}
+
+	void RollbackServer::sendConnectionReply(const std::shared_ptr<MatchState>& match, const std::shared_ptr<PlayerInfo>& player)
+	{
+		NewConnectionReplyPayload replyPayload;
+		replyPayload.success = static_cast<uint8_t>(ConnectionResult::Success);
+		replyPayload.matchNumPlayers = static_cast<uint8_t>(match->players.size());
+		replyPayload.playerIndex = static_cast<uint8_t>(player->playerIndex);
+		replyPayload.matchDurationInFrames = match->durationInFrames;
+		replyPayload.unknown = 0;
+		replyPayload.isValidationServerDebugMode = 0;
+		replyPayload.sessionNonce = player->sessionNonce;
+
+		asio::co_spawn(io_context_,
+			sendServerMessage(match, player, ServerMessageType::NewConnectionReply, replyPayload),
+			asio::detached);
+	}
+
+	std::shared_ptr<PlayerInfo> RollbackServer::findRebindCandidate(const udp::endpoint& remote) const
+	{
+		std::string candidateKey;
+		{
+			std::lock_guard lock(endpointsByAddressMutex_);
+			const auto it = endpointsByAddress_.find(keyAddress(endpointString(remote.address(), remote.port())));
+			// Two clients behind one NAT can't be told apart by address alone
+			if (it == endpointsByAddress_.end() || it->second.size() != 1)
+				return nullptr;
+			candidateKey = *it->second.begin();
+		}
+		auto found = players_.find(candidateKey);
+		if (!found.has_value())
+			return nullptr;
+		auto candidate = found.value();
+		{
+			std::shared_lock lock(candidate->mutex);
+			if (candidate->spectator || candidate->disconnected)
+				return nullptr;
+			if (config_.rebindRequiresNonce && !candidate->sessionNonce)
+				return nullptr;
+		}
+
+		auto match = matches_.find(candidate->matchId);
+		if (!match.has_value() || match.value()->phase == MatchPhase::Ended)
+			return nullptr;
+		return candidate;
+	}
+
+	bool RollbackServer::mayReclaimSlot(const std::shared_ptr<MatchState>& match, const std::shared_ptr<PlayerInfo>& player, const udp::endpoint& remote)
+	{
+		const auto address = normalizeAddress(remote.address());
+		{
+			std::shared_lock lock(player->mutex);
+			if (normalizeAddress(player->address) == address)
+				return true;
+		}
+		std::lock_guard lock(match->rosterMutex);
+		for (const auto& allowed : match->allowedPlayers)
+		{
+			if (allowed.player_index != player->playerIndex)
+				continue;
+			std::error_code ec;
+			const auto rosterAddress = asio::ip::make_address(allowed.ip, ec);
+			return !ec && normalizeAddress(rosterAddress) == address;
+		}
+		return false;
+	}
+
+	asio::awaitable<void> RollbackServer::refetchRoster(std::shared_ptr<MatchState> match)
+	{
+		std::optional<MVSIMatchConfig> config;
+		for (uint32_t attempt = 0; attempt <= config_.rosterFetchRetries; ++attempt)
+		{
+			if (attempt > 0)
+			{
+				asio::steady_timer timer(io_context_);
+				timer.expires_after(std::chrono::milliseconds(config_.rosterRetryDelayMs));
+				co_await timer.async_wait(asio::use_awaitable);
+			}
+			if (!running_ || match->phase == MatchPhase::Ended)
+				break;
+
+			logInfo(logCtx(match), "Roster is empty, asking the backend again");
+			try
+			{
+				// The HTTP call blocks, so it runs on backendPool_ and this resumes on an io thread
+				config = co_await asio::co_spawn(backendPool_,
+					[this, match]() -> asio::awaitable<std::optional<MVSIMatchConfig>>
+					{
+						co_return backend_->registerMatch(match->matchId, match->key);
+					},
+					asio::use_awaitable);
+			}
+			catch (const std::exception& e)
+			{
+				logWarn(logCtx(match), "Roster refetch failed: ", e.what());
+				config.reset();
+			}
+			if (config && !config->players.empty())
+				break;
+		}
+		match->rosterRefetching = false;
+
+		if (!config || config->players.empty())
+		{
+			logWarn(logCtx(match), "Backend still has no roster after ", config_.rosterFetchRetries + 1, " attempts");
+			co_return;
+		}
+		if (config->max_players != match->max_players_)
+		{
+			logWarn(logCtx(match), "Ignoring refetched roster: max_players changed from ", match->max_players_, " to ", static_cast<int>(config->max_players));
+			co_return;
+		}
+		if (auto problem = rosterProblem(*config))
+		{
+			logWarn(logCtx(match), "Ignoring refetched roster: ", *problem);
+			co_return;
+		}
+		std::lock_guard lock(match->rosterMutex);
+		match->allowedPlayers = config->players;
+		logInfo(logCtx(match), "Refetched roster has ", match->allowedPlayers.size(), " player(s)");
+	}
+
+	void RollbackServer::addEndpoint(const std::string& key, const std::shared_ptr<PlayerInfo>& player)
+	{
+		players_.insert_or_assign(key, player);
+		std::lock_guard lock(endpointsByAddressMutex_);
+		endpointsByAddress_[keyAddress(key)].insert(key);
+	}
+
+	void RollbackServer::removeEndpoint(const std::string& key)
+	{
+		players_.erase(key);
+		std::lock_guard lock(endpointsByAddressMutex_);
+		const auto it = endpointsByAddress_.find(keyAddress(key));
+		if (it == endpointsByAddress_.end())
+			return;
+		it->second.erase(key);
+		if (it->second.empty())
+			endpointsByAddress_.erase(it);
+	}
+
+	void RollbackServer::rekeyPlayer(const std::shared_ptr<MatchState>& match, const std::shared_ptr<PlayerInfo>& player, const udp::endpoint& remote)
+	{
+		const std::string newKey = endpointString(remote.address(), remote.port());
+		std::string oldKey;
+		{
+			std::unique_lock lock(player->mutex);
+			oldKey = endpointString(player->address, player->port);
+			player->address = remote.address();
+			player->port = remote.port();
+		}
+		if (oldKey == newKey)
+			return;
+
+		match->players.erase(oldKey);
+		match->players.insert_or_assign(newKey, player);
+		removeEndpoint(oldKey);
+		addEndpoint(newKey, player);
+
+		std::lock_guard lock(match->disconnectMutex);
+		for (auto& notice : match->disconnectNotices)
+		{
+			if (notice.unacked.erase(oldKey))
+			{
+				notice.unacked.insert(newKey);
+			}
+		}
+	}
+
+	uint16_t RollbackServer::enabledCapabilities() const
+	{
+		uint16_t capabilities = 0;
+		if (config_.packetChecksums)
+			capabilities |= CAPABILITY_CHECKSUMS;
+		if (config_.uncompressedClients)
+			capabilities |= CAPABILITY_UNCOMPRESSED;
+		if (config_.sessionNonceClients)
+			capabilities |= CAPABILITY_SESSION_NONCE;
+		if (config_.rleInputClients)
+			capabilities |= CAPABILITY_RLE_INPUTS;
+		return capabilities;
+	}
+
+	bool RollbackServer::checkProtocolVersion(const NewConnectionPayload& payload, const udp::endpoint& remote)
+	{
+		if (payload.messageVersion & CAPABILITIES_VERSION_FLAG)
+		{
+			// Negotiated once, here: all of what the client asks for, or a kick that
+			// tells it what it may ask for instead
+			const uint16_t requested = protocolCapabilities(payload.messageVersion);
+			if ((requested & CAPABILITY_CHECKSUMS) && (requested & CAPABILITY_UNCOMPRESSED))
+			{
+				rejectConnection(payload, remote, KickReason::VersionMismatch, CAPABILITIES_VERSION_FLAG | enabledCapabilities(), ServerError::PlayerKicked,
+					"checksums and uncompressed packets asked for together");
+				return false;
+			}
+			const uint16_t refused = requested & ~enabledCapabilities();
+			if (refused == 0)
+				return true;
+			rejectConnection(payload, remote, KickReason::VersionMismatch, CAPABILITIES_VERSION_FLAG | enabledCapabilities(), ServerError::PlayerKicked,
+				"capabilities " + std::to_string(refused) + " not enabled (server grants " + std::to_string(enabledCapabilities()) + ")");
+			return false;
+		}
+		// Nothing yet confirms which version the game itself sends, so another one is
+		// only logged, and the client is spoken to in the plain protocol
+		if (payload.messageVersion != SUPPORTED_PROTOCOL_VERSION)
+		{
+			logWarn({ payload.matchData.matchId, payload.playerData.playerIndex, endpointString(remote.address(), remote.port()) },
+				"Client speaks protocol version ", payload.messageVersion, " (server speaks ", SUPPORTED_PROTOCOL_VERSION, "), accepting it anyway");
+		}
+		return true;
+	}
+
+	bool RollbackServer::checkMatchKey(const std::shared_ptr<MatchState>& match, const NewConnectionPayload& payload, const udp::endpoint& remote)
+	{
+		// Only the first connection's key went to the backend; everyone after must present the same one
+		if (payload.matchData.key == match->key)
+		{
+			return true;
+		}
+
+		rejectConnection(payload, remote, KickReason::InvalidKey, 0, ServerError::PlayerKicked, "wrong match key");
+		return false;
+	}
+
+	void RollbackServer::rejectConnection(const NewConnectionPayload& payload, const udp::endpoint& remote,
+		KickReason reason, uint32_t param1, ServerError code, const std::string& why)
+	{
+		const std::string key = endpointString(remote.address(), remote.port());
+		logWarn({ payload.matchData.matchId, payload.playerData.playerIndex, key }, "Rejecting connection: ", why);
+		reportError(code, key + " rejected from match " + payload.matchData.matchId + ": " + why);
+
+		KickPayload kick;
+		kick.reason = static_cast<uint16_t>(reason);
+		kick.param1 = param1;
+		// A client refused its version or capabilities gets the default framing; it can't read its own anyway
+		const uint16_t version = reason == KickReason::VersionMismatch ? SUPPORTED_PROTOCOL_VERSION : payload.messageVersion;
+		asio::co_spawn(io_context_,
+			sendUnregisteredMessage(remote, ServerMessageType::Kick, kick, version),
+			asio::detached);
+	}
+
+	std::shared_ptr<PlayerInfo> RollbackServer::handleNewSpectator(
+		const NewConnectionPayload& payload, const udp::endpoint& remote)
+	{
+		std::string key = endpointString(remote.address(), remote.port());
+		if (!checkProtocolVersion(payload, remote))
+		{
+			return nullptr;
+		}
+
+		// Spectators can only watch a match that the players have already set up
+		auto matchOpt = matches_.find(payload.matchData.matchId);
+		if (!matchOpt.has_value())
+		{
+			rejectConnection(payload, remote, KickReason::MatchUnavailable, 0, ServerError::ConnectionRejected,
+				"spectator tried to join an unknown match");
+			return nullptr;
+		}
+		auto match = matchOpt.value();
+		if (!checkMatchKey(match, payload, remote))
+		{
+			return nullptr;
+		}
+
+		auto existing = players_.find(key);
+		if (existing.has_value())
+		{
+			return existing.value();
+		}
+
+		auto spectator = std::make_shared<PlayerInfo>();
+		spectator->address = remote.address();
+		spectator->port = remote.port();
+		applyCapabilities(spectator, payload.messageVersion, match->sessionNonce);
+		spectator->matchId = match->matchId;
+		spectator->playerIndex = payload.playerData.playerIndex;
+		spectator->lastSeqRecv = 0;
+		spectator->lastSeqSent = 0;
+		spectator->ackedFrames.resize(match->max_players_, 0);
+		spectator->ready = true;
+		spectator->lastInputTime = clock_->now();
+		spectator->lastHeardFrom = spectator->lastInputTime.time_since_epoch().count();
+		spectator->emulated = false;
+		spectator->spectator = true;
+
+		match->spectators.insert_or_assign(key, spectator);
+		addEndpoint(key, spectator);
+		logInfo(logCtx(spectator), "Spectator joined");
+
+		NewConnectionReplyPayload replyPayload;
+		replyPayload.success = static_cast<uint8_t>(ConnectionResult::Success);
+		replyPayload.matchNumPlayers = static_cast<uint8_t>(match->players.size());
+		replyPayload.playerIndex = static_cast<uint8_t>(spectator->playerIndex);
+		replyPayload.matchDurationInFrames = match->durationInFrames;
+		replyPayload.unknown = 0;
+		replyPayload.isValidationServerDebugMode = 0;
+		replyPayload.sessionNonce = spectator->sessionNonce;
+
+		asio::co_spawn(io_context_,
+			sendServerMessage(match, spectator, ServerMessageType::NewConnectionReply, replyPayload),
+			asio::detached);
+
+		// A spectator arriving after kick-off still needs to be told the game started
+		if (match->phase == MatchPhase::InProgress)
+		{
+			asio::co_spawn(io_context_,
+				sendServerMessage(match, spectator, ServerMessageType::StartGame, std::monostate{}),
+				asio::detached);
+		}
+
+		return spectator;
+	}
+
+	void RollbackServer::startPingPhase(std::shared_ptr<MatchState> match)
+	{
+		// Create a shared_ptr to a struct that will own the match and remain alive
+		// through the entire asynchronous operation
+		struct PingContext
+		{
+			std::shared_ptr<MatchState> match;
+			std::chrono::milliseconds intervalMs;
+			steady_clock::time_point deadline;
+		};
+		logInfo(logCtx(match), "Starting ping phase");
+		auto context = std::make_shared<PingContext>();
+		context->match = match; // Store a copy of the match
+		context->intervalMs = std::chrono::milliseconds(config_.pingIntervalMs);
+		context->deadline = clock_->now() + std::chrono::milliseconds(config_.pingPhaseTimeoutMs);
+
+		// Everyone still connected has answered enough pings for a stable estimate
+		auto allSampled = [](const std::shared_ptr<MatchState>& match)
+		{
+			for (const auto& p : match->players.snapshot())
+			{
+				if (!p.second->disconnected && p.second->pingSamples < match->pingPhaseTotal)
+					return false;
+			}
+			return true;
+		};
+
+		// Create a self-contained coroutine that captures the context by value
+		// This ensures the context (and thus the match) stays alive until the coroutine completes
+		asio::co_spawn(io_context_, [this, context, allSampled]() -> asio::awaitable<void>
+			{
+				try {
+					// First broadcast immediately
+					co_await broadcastRequestQuality(context->match);
+					context->match->pingPhaseCount++;
+
+					// Then repeat at intervals until every player has answered enough of them
+					while (running_ && !allSampled(context->match)) {
+						if (clock_->now() >= context->deadline) {
+							logWarn(logCtx(context->match), "Ping phase timed out after ", context->match->pingPhaseCount,
+								" pings, configuring the match with the samples we have");
+							break;
+						}
+
+						// Create a timer for each iteration
+						asio::steady_timer timer(io_context_);
+						timer.expires_after(context->intervalMs);
+						co_await timer.async_wait(asio::use_awaitable);
+						// Ended meanwhile, or the server was stopped
+						if (context->match->phase == MatchPhase::Ended)
+							co_return;
+
+						// The context is captured by value and stays alive throughout,
+						// so the match pointer remains valid
+						co_await broadcastRequestQuality(context->match);
+						context->match->pingPhaseCount++;
+					}
+
+					if (running_ && context->match->phase != MatchPhase::Ended)
+						co_await broadcastPlayersConfiguration(context->match);
+				}
+				catch (const std::exception& e) {
+					logError(logCtx(context->match), "Exception in ping phase: ", e.what());
+				} }, asio::detached);
+	}
+
+	asio::awaitable<void> RollbackServer::broadcastRequestQuality(std::shared_ptr<MatchState> match)
+	{
+		auto ts = clock_->now();
+
+		for (const auto& p : match->players.snapshot())
+		{
+			auto player = p.second;
+			{
+				std::shared_lock lock(player->mutex);
+				if (player->disconnected)
+					continue;
+			}
+			RequestQualityDataPayload payload;
+			{
+				std::shared_lock lock(player->mutex);
+				payload.ping = player->ping;
+				payload.packetsLossPercent = player->packetsLossPercent;
+			}
+
+			// std::cout << "Sending Ping for " << player->playerIndex << ":" << player->address << std::endl;
+			co_await sendServerMessage(match, player, ServerMessageType::RequestQualityData, payload, ts);
+		}
+
+		co_return;
+	}
+
+	asio::awaitable<void> RollbackServer::broadcastPlayersConfiguration(std::shared_ptr<MatchState> match)
+	{
+		logInfo(logCtx(match), "Broadcasting players configuration");
+		auto playersSnapshot = match->players.snapshot();
+
+		// The backend roster decides teams. A slot it doesn't list takes the team the
+		// client asked for, and a slot nobody claimed alternates teams by index
+		std::vector<uint16_t> configValues(match->max_players_);
+		std::vector<bool> fromRoster(match->max_players_, false);
+		for (int i = 0; i < match->max_players_; i++)
+		{
+			configValues[i] = playerConfigValue(static_cast<uint16_t>(i), static_cast<uint16_t>(i % 2));
+		}
+		{
+			std::lock_guard rosterLock(match->rosterMutex);
+			for (const auto& allowed : match->allowedPlayers)
+			{
+				if (allowed.player_index < configValues.size())
+				{
+					configValues[allowed.player_index] = playerConfigValue(allowed.player_index, allowed.team_index);
+					fromRoster[allowed.player_index] = true;
+				}
+			}
+		}
+		for (const auto& p : playersSnapshot)
+		{
+			auto player = p.second;
+			if (player->playerIndex < configValues.size() && !fromRoster[player->playerIndex])
+			{
+				configValues[player->playerIndex] = playerConfigValue(player->playerIndex, player->teamId);
+			}
+		}
+
+		for (const auto& p : playersSnapshot)
+		{
+			auto player = p.second;
+			{
+				std::shared_lock lock(player->mutex);
+				if (player->disconnected)
+					continue;
+			}
+			PlayersConfigurationDataPayload payload;
+			{
+				payload.numPlayers = static_cast<uint8_t>(playersSnapshot.size());
+			}
+
+			payload.configValues = configValues;
+
+			co_await sendServerMessage(match, player, ServerMessageType::PlayersConfigurationData, payload);
+		}
+		match->configuredAt = clock_->now().time_since_epoch().count();
+
+		co_return;
+	}
+
+	void RollbackServer::handlePlayerInputAck(
+		std::shared_ptr<MatchState> match,
+		std::shared_ptr<PlayerInfo> player,
+		const PlayerInputAckPayload& payload)
+	{
+		std::unique_lock lock(player->mutex);
+		// An ack that echoes an older server message than one already handled arrived
+		// out of order: it holds nothing new and would only eat a pending ping
+		const uint32_t ackSeq = payload.serverMessageSequenceNumber;
+		if (player->hasAckSeq && isSequenceNewer(player->lastAckSeq, ackSeq))
+		{
+			return;
+		}
+		player->lastAckSeq = ackSeq;
+		player->hasAckSeq = true;
+
+		std::vector<uint32_t> prunedThrough;
+		{
+			std::shared_lock matchLock(match->mutex);
+			prunedThrough = match->prunedThrough;
+		}
+
+		// Update client's view of acked frames; one whose acks stop short of the pruned
+		// history can't go on simulating
+		const auto lostPlayer = applyInputAcks(player->ackedFrames, payload.ackFrame, prunedThrough);
+
+		recordRtt(player, payload.serverMessageSequenceNumber);
+		const bool alreadyGone = player->disconnected;
+		lock.unlock();
+
+		if (lostPlayer && !alreadyGone)
+		{
+			kickPlayer(match, player, KickReason::InputHistoryLost, static_cast<uint32_t>(*lostPlayer),
+				"acked player " + std::to_string(*lostPlayer) + " only to frame " + std::to_string(payload.ackFrame[*lostPlayer])
+				+ ", behind the input history (pruned through " + std::to_string(prunedThrough[*lostPlayer]) + ")");
+		}
+	}
+
+	bool RollbackServer::recordRtt(std::shared_ptr<PlayerInfo> player, uint32_t sequence)
+	{
+		const auto sample = takePingSample(player->pendingPings, sequence, clock_->now());
+		if (!sample.has_value())
+		{
+			return false;
+		}
+		const int16_t newPing = sample.value();
+
+		if (newPing > -1)
+		{
+			// === EWMA smoothing ===
+			player->smoothedPing = smoothPing(player->smoothedPing, player->pingInitialized, static_cast<float>(newPing));
+			player->pingInitialized = true;
+			// Store raw ping for backwards‐compat/logging if needed
+			player->ping = newPing;
+
+			// Flag that we have a truly new ping‐sample
+			player->hasNewPing = true;
+		}
+
+		player->pingSamples++;
+		return true;
+	}
+
+	void RollbackServer::prunePendingPings(std::shared_ptr<PlayerInfo> player, steady_clock::time_point now)
+	{
+		const auto timeout = std::chrono::milliseconds(config_.pendingPingTimeoutMs);
+		uint32_t lost = 0;
+		for (const auto& [sequence, sentAt] : player->pendingPings.snapshot())
+		{
+			if (now - sentAt > timeout && player->pendingPings.erase(sequence))
+			{
+				lost++;
+			}
+		}
+
+		std::unique_lock lock(player->mutex);
+		const uint32_t samples = player->pingSamples;
+		const uint32_t answered = samples - player->lossWindowSamples;
+		lost += player->corruptPackets.exchange(0); // arrived, but as good as lost
+		if (answered + lost > 0)
+		{
+			player->packetsLossPercent = static_cast<int16_t>(lost * 100 / (answered + lost));
+			player->lossWindowSamples = samples;
+		}
+	}
+
+	void RollbackServer::announceDisconnect(std::shared_ptr<MatchState> match, std::shared_ptr<PlayerInfo> player)
+	{
+		auto recipients = match->players.snapshot();
+		recipients.merge(match->spectators.snapshot());
+		if (recipients.size() == 1 && recipients.begin()->second == player)
+		{
+			// A solo match: nobody to hand the slot to an AI for, and a notice nobody
+			// will ever ack would only sit in the table
+			logInfo(logCtx(player), "Disconnected from a solo match, no one to notify");
+			return;
+		}
+
+		PlayerDisconnectedPayload payload;
+		payload.playerIndex = static_cast<uint8_t>(player->playerIndex);
+		payload.shouldAITakeControl = 1;
+		payload.AITakeControlFrame = aiTakeControlFrame(match, player);
+		{
+			std::lock_guard lock(match->disconnectMutex);
+			payload.playerDisconnectedArrayIndex = static_cast<uint16_t>(match->disconnectNotices.size());
+			DisconnectNotice notice{ payload, {} };
+			notice.lastSent = clock_->now();
+			for (const auto& r : recipients)
+			{
+				// Spectators get the notice too but, as with inputs, never ack it
+				if (r.second != player && !r.second->disconnected && !r.second->spectator)
+				{
+					notice.unacked.insert(r.first);
+				}
+			}
+			match->disconnectNotices.push_back(std::move(notice));
+		}
+
+		logInfo(logCtx(player), "Announcing disconnect (notice ", payload.playerDisconnectedArrayIndex, ")");
+		for (const auto& r : recipients)
+		{
+			if (r.second == player)
+				continue;
+			asio::co_spawn(io_context_,
+				sendServerMessage(match, r.second, ServerMessageType::PlayerDisconnected, payload),
+				asio::detached);
+		}
+	}
+
+	void RollbackServer::detectStall(const std::shared_ptr<MatchState>& match, const std::shared_ptr<PlayerInfo>& player, uint32_t serverFrame)
+	{
+		if (player->lastClientFrame != player->progressClientFrame || match->phase != MatchPhase::InProgress)
+		{
+			player->progressClientFrame = player->lastClientFrame;
+			player->progressServerFrame = serverFrame;
+			if (player->stalled)
+			{
+				player->stalled = false;
+				logInfo(logCtx(player), "Player resumed at frame ", player->lastClientFrame);
+			}
+			return;
+		}
+		if (config_.stallTicks == 0 || player->stalled || serverFrame - player->progressServerFrame <= config_.stallTicks)
+			return;
+
+		// Still sending (or we'd have timed them out), but the simulation isn't moving
+		player->stalled = true;
+		player->stallCount++;
+		logWarn(logCtx(player), "Player stalled at frame ", player->lastClientFrame, " for ", serverFrame - player->progressServerFrame, " ticks");
+		reportError(ServerError::PlayerStalled, endpointString(player->address, player->port) + " stalled at frame "
+			+ std::to_string(player->lastClientFrame) + " in match " + match->matchId);
+	}
+
+	void RollbackServer::markLagging(const std::shared_ptr<PlayerInfo>& peer, uint32_t missingFrame)
+	{
+		// Every recipient counts its own misses; the first to give up flags the peer
+		if (peer->lagging.exchange(true))
+			return;
+		peer->lagCount++;
+		logWarn(logCtx(peer), "Player lagging: frame ", missingFrame, " still missing after ", config_.laggyMissedInputs, " ticks, predicting its inputs");
+		if (config_.widenLaggyInputBuffer)
+		{
+			std::unique_lock lock(peer->mutex);
+			if (peer->inputBufferFrames < config_.maxInputBufferFrames)
+			{
+				peer->inputBufferFrames++;
+				logInfo(logCtx(peer), "Input buffer widened to ", peer->inputBufferFrames, " frames");
+			}
+		}
+	}
+
+	uint32_t RollbackServer::aiTakeControlFrame(const std::shared_ptr<MatchState>& match, const std::shared_ptr<PlayerInfo>& player) const
+	{
+		// Never before the departed player's own last input...
+		uint32_t frame = match->currentFrame;
+		const auto departedInputs = match->inputs[player->playerIndex].snapshot();
+		if (!departedInputs.empty())
+		{
+			frame = std::max(frame, departedInputs.rbegin()->first + 1);
+		}
+
+		// ...nor before any remaining player could have simulated by the time the notice
+		// reaches them: lastClientFrame is half a round trip old and the notice takes the
+		// other half, so a whole round trip of frames on top
+		for (const auto& p : match->players.snapshot())
+		{
+			const auto& other = p.second;
+			if (other == player)
+				continue;
+			std::shared_lock lock(other->mutex);
+			if (other->disconnected)
+				continue;
+			const auto rttFrames = static_cast<uint32_t>(std::ceil(other->smoothedPing / match->tickIntervalMs));
+			frame = std::max(frame, other->lastClientFrame + rttFrames);
+		}
+		return frame + AI_TAKEOVER_MARGIN;
+	}
+
+	void RollbackServer::retransmitDisconnectNotices(std::shared_ptr<MatchState> match, steady_clock::time_point now)
+	{
+		const auto interval = std::chrono::milliseconds(config_.disconnectRetransmitMs);
+		std::vector<std::pair<std::shared_ptr<PlayerInfo>, PlayerDisconnectedPayload>> resends;
+		{
+			std::lock_guard lock(match->disconnectMutex);
+			for (auto& notice : match->disconnectNotices)
+			{
+				if (notice.unacked.empty() || now - notice.lastSent < interval)
+					continue;
+
+				if (notice.attempts >= config_.disconnectMaxAttempts)
+				{
+					logWarn(logCtx(match), "Giving up on disconnect notice ", notice.payload.playerDisconnectedArrayIndex,
+						" after ", notice.attempts, " sends; ", notice.unacked.size(), " recipient(s) never acked");
+					notice.unacked.clear();
+					continue;
+				}
+
+				for (const auto& key : notice.unacked)
+				{
+					auto recipient = match->players.find(key);
+					if (recipient.has_value())
+					{
+						resends.emplace_back(recipient.value(), notice.payload);
+					}
+				}
+				notice.attempts++;
+				notice.lastSent = now;
+			}
+		}
+
+		for (const auto& [recipient, payload] : resends)
+		{
+			asio::co_spawn(io_context_,
+				sendServerMessage(match, recipient, ServerMessageType::PlayerDisconnected, payload),
+				asio::detached);
+		}
+	}
+
+	void RollbackServer::handleDisconnectAck(
+		std::shared_ptr<MatchState> match,
+		std::shared_ptr<PlayerInfo> player,
+		const PlayerDisconnectedAckPayload& payload)
+	{
+		const std::string key = endpointString(player->address, player->port);
+		std::lock_guard lock(match->disconnectMutex);
+		if (payload.playerDisconnectedArrayIndex >= match->disconnectNotices.size())
+		{
+			logWarn(logCtx(player), "Ack for unknown disconnect notice ", +payload.playerDisconnectedArrayIndex);
+			return;
+		}
+		// Acks repeat until the client stops hearing the notice; only the first one counts
+		if (match->disconnectNotices[payload.playerDisconnectedArrayIndex].unacked.erase(key))
+		{
+			logDebug(logCtx(player), "Acked disconnect notice ", +payload.playerDisconnectedArrayIndex);
+		}
+	}
+
+	void RollbackServer::handleReady(
+		std::shared_ptr<MatchState> match,
+		std::shared_ptr<PlayerInfo> player,
+		bool isReady)
+	{
+		{
+			std::shared_lock lock(player->mutex);
+			if (player->kickedFor)
+				return; // readied too late, the slot was already given up
+		}
 
-		auto existingPlayer = players_.find(key);
-		if (existingPlayer.has_value())
+		player->ready = isReady;
+		auto playersSnapshot = match->players.snapshot();
+		bool allReady = true;
+		for (const auto& p : playersSnapshot)
 		{
-			return existingPlayer.value();
+			auto player = p.second;
+			std::shared_lock lock(match->mutex);
+			// Someone who has left (or was kicked for not readying) can't hold the others up
+			if (!player->ready && !player->disconnected)
+			{
+				allReady = false;
+				break;
+			}
 		}
 
-		// Create new player
-		auto newPlayer = std::make_shared<PlayerInfo>();
-		newPlayer->address = remote.address();
-		newPlayer->port = remote.port();
-		newPlayer->matchId = matchData.matchId;
-		newPlayer->playerIndex = payload.playerData.playerIndex;
-		newPlayer->lastSeqRecv = 0;
-		newPlayer->lastSeqSent = 0;
-		newPlayer->ackedFrames.resize(match->max_players_, 0);
-		newPlayer->ping = 0;
-		newPlayer->ready = debug;
-		newPlayer->lastClientFrame = 0;
-		newPlayer->lastInputTime = std::chrono::steady_clock::now();
-		newPlayer->rift = 0;
-		newPlayer->emulated = debug;
-
-		// Add player to match and global list
+		if (allReady)
 		{
-
-			match->players.insert_or_assign(key, newPlayer);
-			players_.insert_or_assign(key, newPlayer);
+			startMatch(match);
 		}
-		std::cout << "Player index " << payload.playerData.playerIndex << " joined" << std::endl;
-
-		// Send connection reply
-		NewConnectionReplyPayload replyPayload;
-		replyPayload.success = 0;
-		replyPayload.matchNumPlayers = static_cast<uint8_t>(match->players.size());
-		replyPayload.playerIndex = newPlayer->playerIndex;
-		replyPayload.matchDurationInFrames = match->durationInFrames;
-		replyPayload.unknown = 0;
-		replyPayload.isValidationServerDebugMode = 0;
-
-		asio::co_spawn(io_context_,
-			sendServerMessage(match, newPlayer, ServerMessageType::NewConnectionReply, replyPayload),
-			asio::detached);
+	}
 
-		// Start ping phase if all players have connected
+	void RollbackServer::startMatch(std::shared_ptr<MatchState> match)
+	{
+		auto expectedPhase = MatchPhase::WaitingForPlayers;
+		if (match->phase.compare_exchange_strong(expectedPhase, MatchPhase::InProgress))
 		{
+			logInfo(logCtx(match), "All players ready, match in progress");
+			match->startedAt = clock_->now().time_since_epoch().count();
+		}
 
-			if (match->players.size() == static_cast<size_t>(match->max_players_))
-			{
-				startPingPhase(match);
-			}
+		// Broadcast StartGame to all players and spectators
+		auto recipients = match->players.snapshot();
+		recipients.merge(match->spectators.snapshot());
+		for (const auto& p : recipients)
+		{
+			auto player = p.second;
+			asio::co_spawn(io_context_,
+				sendServerMessage(match, player, ServerMessageType::StartGame, std::monostate{}),
+				asio::detached);
 		}
 
-		return newPlayer;
+		// Start tick loop if not already running
+		if (!match->tickRunning)
+		{
+			startTickLoop(match);
+		}
 	}
 
-	void RollbackServer::startPingPhase(std::shared_ptr<MatchState> match)
+	void RollbackServer::sweepIdlePlayers(std::shared_ptr<MatchState> match, steady_clock::time_point now)
 	{
-		// Create a shared_ptr to a struct that will own the match and remain alive
-		// through the entire asynchronous operation
-		struct PingContext
+		if (config_.idlePlayerTimeoutMs == 0 || match->phase == MatchPhase::Ended)
+			return;
+
+		const auto timeout = std::chrono::milliseconds(config_.idlePlayerTimeoutMs);
+		// Emulated (debug) players never send anything, so silence says nothing about them
+		auto isIdle = [&](const std::shared_ptr<PlayerInfo>& player)
 		{
-			std::shared_ptr<MatchState> match;
-			const std::chrono::milliseconds intervalMs{ 50 };
+			return !player->emulated
+				&& now - steady_clock::time_point(steady_clock::duration(player->lastHeardFrom.load())) > timeout;
 		};
-		std::cout << "Starting Ping Phase" << std::endl;
-		auto context = std::make_shared<PingContext>();
-		context->match = match; // Store a copy of the match
 
-		// Create a self-contained coroutine that captures the context by value
-		// This ensures the context (and thus the match) stays alive until the coroutine completes
-		asio::co_spawn(io_context_, [this, context]() -> asio::awaitable<void>
+		for (const auto& p : match->spectators.snapshot())
+		{
+			if (isIdle(p.second))
 			{
-				try {
-					// First broadcast immediately
-					co_await broadcastRequestQuality(context->match);
-					context->match->pingPhaseCount++;
+				match->spectators.erase(p.first);
+				removeEndpoint(p.first);
+				logInfo(logCtx(p.second), "Removed spectator idle for over ", config_.idlePlayerTimeoutMs, "ms");
+			}
+		}
 
-					// Then repeat at intervals
-					for (uint32_t i = 1; i < context->match->pingPhaseTotal && running_; ++i) {
-						// Create a timer for each iteration
-						asio::steady_timer timer(io_context_);
-						timer.expires_after(context->intervalMs);
-						co_await timer.async_wait(asio::use_awaitable);
+		if (match->tickRunning)
+			return;
 
-						// The context is captured by value and stays alive throughout,
-						// so the match pointer remains valid
-						co_await broadcastRequestQuality(context->match);
-						context->match->pingPhaseCount++;
-					}
+		// Once PlayersConfigurationData has gone out every client counts on this slot,
+		// so it stays: the player is disconnected and announced, and the AI takes over
+		if (match->configuredAt != 0)
+		{
+			for (const auto& p : match->players.snapshot())
+			{
+				if (!isIdle(p.second))
+					continue;
+				{
+					std::unique_lock lock(p.second->mutex);
+					if (p.second->disconnected)
+						continue;
+					p.second->disconnected = true;
+				}
+				logInfo(logCtx(p.second), "Player idle for over ", config_.idlePlayerTimeoutMs, "ms, handing its slot to the AI");
+				announceDisconnect(match, p.second);
+			}
+			return;
+		}
 
-					co_await broadcastPlayersConfiguration(context->match);
+		// Before that nothing is indexed by the player yet, so it can be dropped outright,
+		// freeing the slot for whoever connects to it next
+		bool removed = false;
+		for (const auto& p : match->players.snapshot())
+		{
+			if (!isIdle(p.second))
+				continue;
+			match->players.erase(p.first);
+			removeEndpoint(p.first);
+			{
+				std::lock_guard lock(match->disconnectMutex);
+				for (auto& notice : match->disconnectNotices)
+				{
+					notice.unacked.erase(p.first);
 				}
-				catch (const std::exception& e) {
-					std::cerr << "Exception in ping phase: " << e.what() << std::endl;
-				} }, asio::detached);
+			}
+			removed = true;
+			logInfo(logCtx(p.second), "Removed player idle for over ", config_.idlePlayerTimeoutMs, "ms, freeing its slot");
+		}
+
+		if (removed && match->players.size() == 0)
+		{
+			endMatch(match, "every player went idle before start");
+		}
 	}
 
-	asio::awaitable<void> RollbackServer::broadcastRequestQuality(std::shared_ptr<MatchState> match)
+	asio::awaitable<void> RollbackServer::retransmitStartGame(std::shared_ptr<MatchState> match, steady_clock::time_point now)
 	{
-		auto ts = steady_clock::now();
+		const auto startedAt = match->startedAt.load();
+		if (config_.startGameRetransmitTimeoutMs == 0 || startedAt == 0)
+			co_return;
+		if (now - steady_clock::time_point(steady_clock::duration(startedAt)) > std::chrono::milliseconds(config_.startGameRetransmitTimeoutMs))
+			co_return;
 
+		// A player whose StartGame was lost would never start while everyone else
+		// plays on; its first Input is the only sign it got one. Spectators send no
+		// input, so they rely on the copy sent when they joined or at kick-off.
+		const auto interval = std::chrono::milliseconds(config_.startGameRetransmitMs);
 		for (const auto& p : match->players.snapshot())
 		{
 			auto player = p.second;
 			{
-				std::shared_lock lock(player->mutex);
-				if (player->disconnected)
+				std::unique_lock lock(player->mutex);
+				if (player->disconnected || player->lastInputArrival)
 					continue;
+				if (player->lastStartGameSent && now - *player->lastStartGameSent < interval)
+					continue;
+				player->lastStartGameSent = now;
 			}
-			RequestQualityDataPayload payload;
-			{
-				std::shared_lock lock(player->mutex);
-				payload.ping = player->ping;
-			}
-
-			// std::cout << "Sending Ping for " << player->playerIndex << ":" << player->address << std::endl;
-			auto sequence = co_await sendServerMessage(match, player, ServerMessageType::RequestQualityData, payload);
-
-			// Record it per player
-			player->pendingPings.insert_or_assign(sequence, ts);
+			co_await sendServerMessage(match, player, ServerMessageType::StartGame, std::monostate{});
 		}
-
-		co_return;
 	}
 
-	asio::awaitable<void> RollbackServer::broadcastPlayersConfiguration(std::shared_ptr<MatchState> match)
+	void RollbackServer::checkReadyTimeout(std::shared_ptr<MatchState> match, steady_clock::time_point now)
 	{
-		std::cout << "broadcastPlayersConfiguration" << std::endl;
-		auto playersSnapshot = match->players.snapshot();
-		for (const auto& p : playersSnapshot)
+		const auto configuredAt = match->configuredAt.load();
+		if (config_.readyTimeoutMs == 0 || configuredAt == 0 || match->phase != MatchPhase::WaitingForPlayers)
+			return;
+		if (now - steady_clock::time_point(steady_clock::duration(configuredAt)) < std::chrono::milliseconds(config_.readyTimeoutMs))
+			return;
+
+		std::vector<std::shared_ptr<PlayerInfo>> stragglers;
+		for (const auto& p : match->players.snapshot())
 		{
-			auto player = p.second;
-			{
-				std::shared_lock lock(player->mutex);
-				if (player->disconnected)
-					continue;
-			}
-			PlayersConfigurationDataPayload payload;
+			std::shared_lock lock(p.second->mutex);
+			if (!p.second->ready && !p.second->disconnected)
 			{
-				payload.numPlayers = static_cast<uint8_t>(playersSnapshot.size());
+				stragglers.push_back(p.second);
 			}
+		}
+		if (stragglers.empty())
+			return;
 
-			payload.configValues.resize(match->max_players_);
+		KickPayload kick;
+		kick.reason = static_cast<uint16_t>(KickReason::ReadyTimeout);
+		kick.param1 = 0;
 
-			for (int i = 0; i < match->max_players_; i++)
+		if (config_.readyTimeoutPolicy == ReadyTimeoutPolicy::Abort)
+		{
+			logWarn(logCtx(match), stragglers.size(), " player(s) never readied, aborting the match");
+			// Spectators too: the match they were watching is gone
+			for (const auto* members : { &match->players, &match->spectators })
 			{
-				const std::array<uint16_t, 4> PlayerConfigValues = { 0, 257, 512, 769 };
-				payload.configValues[i] = PlayerConfigValues[i % PlayerConfigValues.size()];
+				for (const auto& p : members->snapshot())
+				{
+					asio::co_spawn(io_context_,
+						sendServerMessage(match, p.second, ServerMessageType::Kick, kick),
+						asio::detached);
+				}
 			}
+			endMatch(match, "ready timeout");
+			return;
+		}
 
-			co_await sendServerMessage(match, player, ServerMessageType::PlayersConfigurationData, payload);
+		for (const auto& player : stragglers)
+		{
+			kickPlayer(match, player, KickReason::ReadyTimeout, 0, "not ready after " + std::to_string(config_.readyTimeoutMs) + "ms");
 		}
 
-		co_return;
+		if (allPlayersDisconnected(match))
+		{
+			endMatch(match, "nobody readied");
+			return;
+		}
+		startMatch(match);
 	}
 
-	void RollbackServer::handlePlayerInputAck(
+	void RollbackServer::kickPlayer(std::shared_ptr<MatchState> match, std::shared_ptr<PlayerInfo> player, KickReason reason, uint32_t param1,
+		const std::string& why)
+	{
+		logWarn(logCtx(player), "Kicking player: ", why);
+		reportError(ServerError::PlayerKicked, endpointString(player->address, player->port) + " kicked from match " + match->matchId + ": " + why);
+
+		KickPayload kick;
+		kick.reason = static_cast<uint16_t>(reason);
+		kick.param1 = param1;
+		asio::co_spawn(io_context_,
+			sendServerMessage(match, player, ServerMessageType::Kick, kick),
+			asio::detached);
+		{
+			std::unique_lock lock(player->mutex);
+			player->disconnected = true;
+			player->kickedFor = reason;
+		}
+		announceDisconnect(match, player);
+	}
+
+	void RollbackServer::handleClientInput(
 		std::shared_ptr<MatchState> match,
 		std::shared_ptr<PlayerInfo> player,
-		const PlayerInputAckPayload& payload)
+		const InputPayload& payload)
 	{
-		std::shared_lock lock(player->mutex);
-		// Update client's view of acked frames
-		for (size_t i = 0; i < payload.ackFrame.size() && i < player->ackedFrames.size(); i++)
+		const auto& startFrame = payload.startFrame;
+		const auto& clientFrame = payload.clientFrame;
+		const auto& numFrames = payload.numFrames;
+		const auto& inputPerFrame = payload.inputPerFrame;
+
+		// A client can run a little ahead of the server (that's what rift corrects),
+		// never seconds ahead; such frames would poison the history and the rift
+		if (config_.maxFrameLead > 0)
 		{
-			const uint32_t playerAckedFrame = payload.ackFrame[i];
-			if (playerAckedFrame && player->ackedFrames[i] < playerAckedFrame)
+			uint32_t serverFrame;
+			{
+				std::shared_lock lock(match->mutex);
+				serverFrame = match->currentFrame;
+			}
+			const uint64_t limit = static_cast<uint64_t>(serverFrame) + config_.maxFrameLead;
+			const uint64_t lastFrame = static_cast<uint64_t>(startFrame) + (numFrames > 0 ? numFrames - 1 : 0);
+			if (clientFrame > limit || lastFrame > limit)
 			{
-				player->ackedFrames[i] = playerAckedFrame;
+				logWarn(logCtx(player), "Dropping input: client_frame=", clientFrame, " last_frame=", lastFrame,
+					" is more than ", config_.maxFrameLead, " frames past server frame ", serverFrame);
+				return;
 			}
 		}
 
-		// Compute raw ping (RTT)
-		// auto it = player->pendingPings.find(payload.serverMessageSequenceNumber);
-		auto pendingPingOpt = player->pendingPings.find(payload.serverMessageSequenceNumber);
-		if (pendingPingOpt.has_value())
 		{
-			int16_t newPing = static_cast<int16_t>(
-				duration_cast<milliseconds>(steady_clock::now() - pendingPingOpt.value()).count());
-
-			if (newPing > 255)
+			std::unique_lock lock(player->mutex);
+			if (player->kickedFor)
+				return; // its input would bring it back into the match
+			const auto now = clock_->now();
+			if (player->lastInputArrival)
 			{
-				newPing = 255; // Cap ping to 255ms;
+				const std::chrono::duration<float, std::milli> interval = now - *player->lastInputArrival;
+				player->jitter = updateJitter(player->jitter, interval.count());
+				if (config_.adaptiveInputBuffer)
+				{
+					player->inputBufferFrames = inputBufferFrames(player->jitter.jitterMs, config_.maxInputBufferFrames, match->tickIntervalMs);
+				}
 			}
+			player->lastInputArrival = now;
+			player->lastClientFrame = clientFrame;
+			player->hasNewFrame = true;
+			player->lastInputTime = now; // Update last input time
+			player->disconnected = false;                             // Mark as connected on input
+		}
 
-			if (newPing > -1)
+		// Store each new input in the map
+		{
+			auto& histMap = match->inputs[player->playerIndex];
+			for (uint8_t i = 0; i < numFrames && i < inputPerFrame.size(); i++)
 			{
-				// === EWMA smoothing ===
-				if (!player->pingInitialized)
-				{
-					// First measurement: initialize smoothedPing exactly to the first sample
-					player->smoothedPing = static_cast<float>(newPing);
-					player->pingInitialized = true;
-				}
-				else
+				const uint32_t f = startFrame + i;
+				auto stored = histMap.find(f);
+				if (stored.has_value())
 				{
-					// EWMA update:
-					player->smoothedPing =
-						PlayerInfo::clampFloat(PING_ALPHA * static_cast<float>(newPing) + (1.0f - PING_ALPHA) * player->smoothedPing, 255.0f);
+					// If we already have an input for this frame, keep the first one.
+					// Resends due to ping are expected and match; a server prediction may
+					// legitimately differ. A real input changing after the fact is a desync
+					// or a tampering client.
+					if (stored.value() != inputPerFrame[i] && !match->predictedFrames[player->playerIndex].contains(f))
+					{
+						logWarn(logCtx(player), "Conflicting input for frame ", f, ": kept ", stored.value(), ", ignored ", inputPerFrame[i]);
+					}
+					continue;
 				}
-				// Store raw ping for backwards‐compat/logging if needed
-				player->ping = newPing;
-
-				// Flag that we have a truly new ping‐sample
-				player->hasNewPing = true;
+				histMap.insert_or_assign(f, inputPerFrame[i]);
+				player->framesReceived++;
 			}
-
-			player->pendingPings.erase(payload.serverMessageSequenceNumber);
 		}
 	}
 
-	void RollbackServer::handleReady(
+	void RollbackServer::calcRiftVariableTick(
 		std::shared_ptr<MatchState> match,
 		std::shared_ptr<PlayerInfo> player,
-		bool isReady)
+		uint32_t serverFrame)
 	{
-
-		player->ready = isReady;
-		auto playersSnapshot = match->players.snapshot();
-		bool allReady = true;
-		for (const auto& p : playersSnapshot)
+		if (!isRiftUpdateFrame(serverFrame))
+			return;
+		// If we have a freshly smoothed ping AND a freshly received frame stamp:
+		if (player->hasNewPing && player->hasNewFrame)
 		{
-			auto player = p.second;
-			std::shared_lock lock(match->mutex);
-			if (!player->ready)
+			RiftState state{ player->riftInit, player->smoothRift, player->rift };
+			// Measuring against a server frame that far ahead makes the client run
+			// inputBufferFrames ahead, so its inputs are already here when needed
+			state = updateRift(state, player->smoothedPing, player->lastClientFrame, serverFrame + player->inputBufferFrames,
+				match->riftMin, match->riftMax, match->tickIntervalMs);
+			player->riftInit = state.initialized;
+			player->smoothRift = state.smoothRift;
+			player->rift = state.rawRift;
+
+			// Update the ping to the smoothed value
+			player->ping = player->smoothedPing;
+
+			// Reset the “new” flags after using them
+			player->hasNewPing = false;
+			player->hasNewFrame = false;
+			if (player->smoothRift > 1 || player->smoothRift < -1 || player->smoothedPing > 254)
 			{
-				allReady = false;
-				break;
+				logDebug(logCtx(player), "ping=", player->ping, " rift=", player->smoothRift, " raw_rift=", player->rift, " client_frame=", player->lastClientFrame, " server_frame=", serverFrame);
 			}
 		}
+	}
 
-		if (allReady)
+	bool RollbackServer::allPlayersDisconnected(const std::shared_ptr<MatchState>& match) const
+	{
+		for (const auto& p : match->players.snapshot())
 		{
-			// Broadcast StartGame to all players
-			for (const auto& p : playersSnapshot)
+			std::shared_lock plock(p.second->mutex);
+			if (!p.second->disconnected)
 			{
-				auto player = p.second;
-				asio::co_spawn(io_context_,
-					sendServerMessage(match, player, ServerMessageType::StartGame, std::monostate{}),
-					asio::detached);
+				return false;
 			}
+		}
+		return true;
+	}
+
+	bool RollbackServer::allPlayersFinished(const std::shared_ptr<MatchState>& match) const
+	{
+		if (match->durationInFrames == 0)
+			return false; // no fixed length; the match ends when the players leave
+		bool anyConnected = false;
+		for (const auto& p : match->players.snapshot())
+		{
+			std::shared_lock plock(p.second->mutex);
+			if (p.second->disconnected)
+				continue;
+			anyConnected = true;
+			if (p.second->lastClientFrame < match->durationInFrames)
+				return false;
+		}
+		return anyConnected;
+	}
 
-			// Start tick loop if not already running
-			if (!match->tickRunning)
+	bool RollbackServer::allInputsAcked(const std::shared_ptr<MatchState>& match) const
+	{
+		std::vector<uint32_t> lastFrames;
+		for (const auto& histMap : match->inputs)
+		{
+			const auto frames = histMap.snapshot();
+			lastFrames.push_back(frames.empty() ? 0 : frames.rbegin()->first);
+		}
+		for (const auto& p : match->players.snapshot())
+		{
+			std::shared_lock plock(p.second->mutex);
+			if (p.second->disconnected)
+				continue;
+			for (size_t idx = 0; idx < lastFrames.size() && idx < p.second->ackedFrames.size(); ++idx)
 			{
-				startTickLoop(match);
+				if (p.second->ackedFrames[idx] < lastFrames[idx])
+					return false;
 			}
 		}
+		return true;
 	}
 
-	void RollbackServer::handleClientInput(
-		std::shared_ptr<MatchState> match,
-		std::shared_ptr<PlayerInfo> player,
-		const InputPayload& payload)
+	MatchOutcome RollbackServer::settleMatchResult(const std::shared_ptr<MatchState>& match)
 	{
-		const auto& startFrame = payload.startFrame;
-		const auto& clientFrame = payload.clientFrame;
-		const auto& numFrames = payload.numFrames;
-		const auto& inputPerFrame = payload.inputPerFrame;
-
+		std::map<uint16_t, uint8_t> reportedWinners;
 		{
-			std::unique_lock lock(player->mutex);
-			player->lastClientFrame = clientFrame;
-			player->hasNewFrame = true;
-			player->lastInputTime = std::chrono::steady_clock::now(); // Update last input time
-			player->disconnected = false;                             // Mark as connected on input
+			std::lock_guard lock(match->resultMutex);
+			reportedWinners = match->reportedWinners;
 		}
+		const MatchOutcome outcome = tallyMatchResult(reportedWinners, match->max_players_);
+		if (outcome.status == MatchResultStatus::Unreported)
+			return outcome;
 
-		// Store each new input in the map
+		std::map<uint8_t, int> votes;
+		for (const auto& [playerIndex, team] : reportedWinners)
+		{
+			votes[team]++;
+		}
+		if (outcome.status == MatchResultStatus::Decided)
+		{
+			logInfo(logCtx(match), "Team ", +outcome.winning_team_index, " won (", votes[outcome.winning_team_index], " of ", +outcome.reports, " reports)");
+		}
+		else
 		{
-			auto& histMap = match->inputs[player->playerIndex];
-			for (uint8_t i = 0; i < numFrames && i < inputPerFrame.size(); i++)
+			std::string tally;
+			for (const auto& [team, count] : votes)
 			{
-				const uint32_t f = startFrame + i;
-				if (histMap.find(f).has_value())
-				{
-					// If we already have an input for this frame, skip it
-					// This happens when the server overwrites an input or player is sending previous input due to ping
-					continue;
-				}
-				histMap.insert_or_assign(f, inputPerFrame[i]);
+				tally += (tally.empty() ? "team " : ", team ") + std::to_string(team) + ": " + std::to_string(count);
 			}
+			logWarn(logCtx(match), "Match result disputed, no team has a majority of ", match->max_players_, " slots (", tally, ")");
+			reportError(ServerError::MatchDisputed, "match " + match->matchId + " result disputed (" + tally + ")");
 		}
+		return outcome;
 	}
 
-	void RollbackServer::calcRiftVariableTick(
-		std::shared_ptr<PlayerInfo> player,
-		uint32_t serverFrame)
+	void RollbackServer::endMatch(std::shared_ptr<MatchState> match, const char* reason)
 	{
-		if (serverFrame % 60 != 0 && serverFrame > 500)
-			return;
-		// If we have a freshly smoothed ping AND a freshly received frame stamp:
-		if (player->hasNewPing && player->hasNewFrame)
+		auto expected = match->phase.load();
+		do
 		{
-			// Convert half of smoothedPing from ms → frames
-			float halfPingFrames = (player->smoothedPing * 0.5f) / TARGET_FRAME_TIME;
-
-			// Predict where the client “must be” in terms of frames
-			float predictedClientFrame = static_cast<float>(player->lastClientFrame) + halfPingFrames;
-
-			// Compute raw rift (client vs. server):
-			if (!player->riftInit)
-			{
-				player->riftInit = true;
-				float rawRift = predictedClientFrame - static_cast<float>(serverFrame);
+			if (expected == MatchPhase::Ended)
+				return; // someone else is already tearing it down
+		} while (!match->phase.compare_exchange_weak(expected, MatchPhase::Ended));
 
-				player->smoothRift = rawRift;
-			}
-			else
+		// The HTTP call blocks, so it goes out from backendPool_ rather than holding up an io thread
+		asio::post(backendPool_,
+			[this, context = logCtx(match), matchId = match->matchId, key = match->key, outcome = settleMatchResult(match)]
 			{
-				float rawRift = predictedClientFrame - float(serverFrame);
-				float absR = fabs(rawRift);
-				player->rift = rawRift;
-
-				if (absR < 1.0f)
-				{
-					// blend toward zero instead of toward rawRift
-					// e.g. kill half of the remaining smoothed error every tick
-					player->smoothRift *= 0.5f;
-
-					// once it's tiny, zero it out completely:
-					if (fabs(player->smoothRift) < 0.01f)
-						player->smoothRift = 0.0f;
-				}
-				else
+				try
 				{
-					player->smoothRift = RIFT_ALPHA * rawRift + (1.0f - RIFT_ALPHA) * player->smoothRift;
+					backend_->endMatch(matchId, key, outcome);
 				}
-
-				if (fabs(rawRift) < fabs(player->smoothRift))
+				catch (const std::exception& e)
 				{
-					player->smoothRift = rawRift;
+					logWarn(context, "Reporting the match's end failed: ", e.what());
 				}
-			}
-
-			player->smoothRift = PlayerInfo::clampFloat(player->smoothRift, 20.0f);
-
-			// Update the ping to the smoothed value
-			player->ping = player->smoothedPing;
-
-			// Reset the “new” flags after using them
-			player->hasNewPing = false;
-			player->hasNewFrame = false;
-			if (player->smoothRift > 1 || player->smoothRift < -1 || player->smoothedPing > 254)
-			{
-				std::cout << "PIndex:" << player->playerIndex << " PING:" << player->ping << " RIFT:" << player->smoothRift << " RAWRIFT:" << player->rift << " clientFrame:" << predictedClientFrame << " serverFrame:" << serverFrame << std::endl;
-			}
+			});
+		match->tickRunning = false;
+		// Remove all players and spectators from global players_ map
+		for (const auto& p : match->players.snapshot())
+		{
+			removeEndpoint(p.first);
+		}
+		for (const auto& s : match->spectators.snapshot())
+		{
+			removeEndpoint(s.first);
+		}
+		// Remove all players from match
+		match->players.clear();
+		match->spectators.clear();
+		// Clear all input data
+		for (auto& inputMap : match->inputs)
+		{
+			inputMap.clear();
 		}
+		// Remove match from matches_ map
+		matches_.erase(match->matchId);
+		logInfo(logCtx(match), "Match cleaned up (", reason, ")");
 	}
 
 	void RollbackServer::startTickLoop(std::shared_ptr<MatchState> match)
@@ -673,7 +2901,7 @@ namespace rollback
 		if (!match->tickRunning.compare_exchange_strong(expected, true))
 			return;
 		// Just spawn the coroutine on io_context_
-		asio::co_spawn(io_context_, runTickLoop(match), asio::detached);
+		asio::co_spawn(io_context_, runTickLoop(match), supervise(logCtx(match), "Tick loop"));
 	}
 
 	// Now update the runTickLoop function to take advantage of this higher resolution:
@@ -683,151 +2911,83 @@ namespace rollback
 		const auto targetInterval = std::chrono::duration_cast<std::chrono::nanoseconds>(
 			std::chrono::duration<double, std::milli>(match->tickIntervalMs));
 
-		auto nextTickTime = std::chrono::steady_clock::now() + targetInterval;
-
-		// Track accumulated error for drift compensation
-		std::chrono::nanoseconds accumulatedError{ 0 };
+		TickScheduler scheduler(targetInterval, clock_);
 
 		// For performance monitoring
 		int tickCount = 0;
-		auto monitorStart = std::chrono::steady_clock::now();
-		std::chrono::nanoseconds maxDeviation{ 0 };
+		auto monitorStart = clock_->now();
 
-		const auto startTime = steady_clock::now();
+		const auto startTime = clock_->now();
+		const uint32_t resumeFrame = match->currentFrame; // non-zero only for a match restored from a snapshot
+		std::optional<steady_clock::time_point> drainDeadline;
 
 		while (match->tickRunning && running_)
 		{
 			// Process the current tick
 			co_await tick(match);
 
-			// --- CLEANUP LOGIC START ---
-			// Check if all players are disconnected
-			bool allDisconnected = true;
-			std::vector<std::string> playerKeys;
-			{
-				std::shared_lock lock(match->mutex);
-				for (const auto& p : match->players.snapshot())
-				{
-					auto player = p.second;
-					playerKeys.push_back(p.first);
-					std::shared_lock plock(player->mutex);
-					if (!player->disconnected)
-					{
-						allDisconnected = false;
-						break;
-					}
-				}
-			}
-			if (allDisconnected)
+			// Nobody left to play: stop ticking and free the match
+			if (allPlayersDisconnected(match))
 			{
-				sendEndMatch(match->matchId, match->key);
-				match->tickRunning = false;
-				// Remove all players from global players_ map
-				for (const auto& key : playerKeys)
-				{
-					players_.erase(key);
-				}
-				// Remove all players from match
-				match->players.clear();
-				// Clear all input data
-				for (auto& inputMap : match->inputs)
-				{
-					inputMap.clear();
-				}
-				// Remove match from matches_ map
-				matches_.erase(match->matchId);
-				std::cout << "Match " << match->matchId << " cleaned up (all players disconnected)" << std::endl;
-		
+				endMatch(match, "all players disconnected");
 				break; // Exit tick loop
 			}
-			// --- CLEANUP LOGIC END ---
-
-			// Calculate actual time spent in tick processing
-			auto now = std::chrono::steady_clock::now();
-			auto elapsed = now - startTime;
-			uint32_t absoluteFrame = static_cast<uint32_t>(elapsed / targetInterval);
-			match->currentFrame = absoluteFrame;
-
-			// Calculate the next tick time with drift compensation
-			nextTickTime += targetInterval;
 
-			// Apply drift compensation - correct the accumulated error
-			if (accumulatedError != std::chrono::nanoseconds::zero())
+			// Once everyone has played the last frame, keep ticking only until the
+			// final inputs are acked so no client is left without them
+			if (!drainDeadline && allPlayersFinished(match))
 			{
-				// Only correct a portion of the error each tick to avoid overcorrection
-				auto correction = accumulatedError / 4; // With timeBeginPeriod(1), we can be more aggressive
-				nextTickTime -= correction;
-				accumulatedError -= correction;
+				match->draining = true;
+				drainDeadline = clock_->now() + std::chrono::milliseconds(config_.matchDrainTimeoutMs);
+				logInfo(logCtx(match), "Match duration reached, draining remaining inputs");
 			}
-
-			// Calculate wait time, ensuring we don't wait a negative amount
-			auto waitTime = nextTickTime - now;
-			if (waitTime < std::chrono::nanoseconds::zero())
+			if (drainDeadline)
 			{
-				// We're behind schedule - update accumulatedError and reset wait time
-				accumulatedError += waitTime; // Negative value increases the error
-				nextTickTime = now;           // Don't wait, run immediately
-
-				// Limit maximum accumulated error to prevent extreme corrections
-				const auto maxError = targetInterval * 3; // Smaller limit with higher resolution
-				if (accumulatedError < -maxError)
+				if (allInputsAcked(match))
 				{
-					accumulatedError = -maxError;
+					endMatch(match, "match finished");
+					break;
 				}
-
-				// Log significant drift for debugging
-				if (waitTime < -std::chrono::milliseconds(2))
-				{ // Lower threshold for logging
-				  // std::cout << "Tick loop running behind schedule by "<< std::chrono::duration_cast<std::chrono::microseconds>(waitTime).count() << "μs" << std::endl;
+				if (clock_->now() >= *drainDeadline)
+				{
+					endMatch(match, "match finished, drain timed out");
+					break;
 				}
-
-				continue; // Skip waiting, run next tick immediately
 			}
 
-			// Wait until the next tick time using high-precision timer
-			asio::steady_timer timer(co_await asio::this_coro::executor);
-			timer.expires_at(nextTickTime);
+			// Calculate actual time spent in tick processing
+			auto now = clock_->now();
+			auto elapsed = now - startTime;
+			uint32_t absoluteFrame = resumeFrame + static_cast<uint32_t>(elapsed / targetInterval);
+			match->currentFrame = absoluteFrame;
 
 			try
 			{
-				co_await timer.async_wait(asio::use_awaitable);
+				co_await scheduler.waitForNextTick();
 			}
 			catch (const std::system_error& e)
 			{
 				// Handle timer cancellation or errors
-				std::cerr << "Timer error: " << e.what() << std::endl;
+				logError(logCtx(match), "Timer error: ", e.what());
 				break;
 			}
 
-			// After wait, check how accurate our timing was
-			auto afterWait = std::chrono::steady_clock::now();
-			auto actualWaitTime = afterWait - now;
-			auto timerError = actualWaitTime - waitTime;
-
-			// Update tracking of maximum deviation
-			if (std::abs(timerError.count()) > std::abs(maxDeviation.count()))
-			{
-				maxDeviation = timerError;
-			}
-
-			// Add to accumulated error for future compensation
-			accumulatedError += timerError;
-
 			// Performance monitoring
 			tickCount++;
 			if (tickCount >= 500)
-			{ // Report every 100 ticks
-				auto monitorEnd = std::chrono::steady_clock::now();
+			{ // Report every 500 ticks
+				auto monitorEnd = clock_->now();
 				auto monitorDuration = monitorEnd - monitorStart;
 				auto avgTickTime = monitorDuration / tickCount;
 
-				std::cout << "  Average tick interval: "
-					<< std::chrono::duration_cast<std::chrono::microseconds>(avgTickTime).count() << std::endl;
+				logInfo(logCtx(match), "Average tick interval us=",
+					std::chrono::duration_cast<std::chrono::microseconds>(avgTickTime).count(),
+					" max drift us=", std::chrono::duration_cast<std::chrono::microseconds>(scheduler.maxDrift()).count());
 
 				// Reset monitoring variables
 				tickCount = 0;
 				monitorStart = monitorEnd;
-				maxDeviation = std::chrono::nanoseconds{ 0 };
+				scheduler.resetStats();
 			}
 		}
 
@@ -837,8 +2997,10 @@ namespace rollback
 	asio::awaitable<void> RollbackServer::tick(std::shared_ptr<MatchState> match)
 	{
 		auto playersSnapshot = match->players.snapshot();
-		auto now = std::chrono::steady_clock::now();
+		auto now = clock_->now();
 		// For each player, recalc rift only if they have both new ping & new frame ===
+		// Rift is measured against the server's frame, not another player's, so a
+		// solo match needs no special case here
 		{
 			uint32_t serverFrame;
 			{
@@ -851,63 +3013,77 @@ namespace rollback
 
 				{
 					std::shared_lock lock(player->mutex);
-					calcRiftVariableTick(player, serverFrame);
+					calcRiftVariableTick(match, player, serverFrame);
+					if (!player->disconnected)
+					{
+						std::lock_guard telemetryLock(match->telemetryMutex);
+						match->telemetry.push_back({ serverFrame, player->playerIndex, player->lastClientFrame, player->ping, player->smoothRift });
+						if (match->telemetry.size() > MAX_TELEMETRY_SAMPLES)
+						{
+							match->telemetry.pop_front();
+						}
+					}
 					if (!player->disconnected && (now - player->lastInputTime > std::chrono::seconds(DISCONECT_TIMEOUT)))
 					{
 						player->disconnected = true;
-						std::cout << "Player index " << player->playerIndex << " timed out (no input > 20s)" << std::endl;
+						logInfo(logCtx(player), "Player timed out (no input > ", +DISCONECT_TIMEOUT, "s)");
+						announceDisconnect(match, player);
 						continue;
 					}
 					if (player->disconnected)
 						continue;
+					detectStall(match, player, serverFrame);
 				}
 			}
 		}
 
-		// Check if we have enough inputs
-		bool exit = false;
-		for (const auto& input : match->inputs)
-		{
-			if (input.size() < 10)
-			{
-				exit = true;
-				break;
-			}
-		}
+		// Spectators receive the same stream as players but are never a peer in it
+		auto recipients = playersSnapshot;
+		recipients.merge(match->spectators.snapshot());
 
-		if (exit)
-		{
-			// Let's build up some input first
-			for (const auto& r : playersSnapshot)
-			{
-				auto recipient = r.second;
-				co_await sendServerMessage(match, recipient, ServerMessageType::StartGame, std::monostate{});
-			}
-			co_return;
-		}
+		const uint32_t releaseFrame = match->netcodeMode == NetcodeMode::InputDelay
+			? advanceLockstepFrame(match, playersSnapshot)
+			: UINT32_MAX;
+
+		co_await retransmitStartGame(match, now);
 
 		// build per-client payload and send
-		for (const auto& r : playersSnapshot)
+		for (const auto& r : recipients)
 		{
 			auto recipient = r.second;
 
+			// Each player is held back only until it has built up some input of its
+			// own; a peer that is slow to start is predicted rather than stalling everyone
+			if (!recipient->spectator && recipient->framesReceived < config_.inputsReadyFrames)
+				continue;
+
 			std::vector<uint32_t> startFrame(match->max_players_, 0);
 			std::vector<uint8_t> numFrames(match->max_players_, 0);
-			std::vector<std::vector<uint32_t>> inputPerFrame(match->max_players_);
-			uint16_t numPredictedOverrides = 0;
+			std::vector<std::vector<FrameInput>> inputPerFrame(match->max_players_);
+			// Frames sent that are the server's guess rather than the peer's real input,
+			// summed over every peer: fresh predictions and earlier ones now in the history
+			uint32_t numPredictedOverrides = 0;
 
 			std::vector<uint32_t> ackedFrames;
 			uint32_t lastClientFrame;
 			int16_t ping;
+			int16_t packetsLossPercent;
 			float smoothRift;
 			{
 				std::shared_lock lock(recipient->mutex);
 				ackedFrames = recipient->ackedFrames;
 				lastClientFrame = recipient->lastClientFrame;
 				ping = recipient->ping;
+				packetsLossPercent = recipient->packetsLossPercent;
 				smoothRift = recipient->smoothRift;
 			}
 
+			// Per-peer frame budget, split evenly so the whole packet still fits, and never more
+			// than maxFramesPerPeer at any rate
+			const auto budget = playerInputFrameBudget(match->max_players_, config_.inputWidth, config_.maxFramesPerPeer);
+			const uint8_t catchUpFrames = budget.catchUpFrames;
+			const uint8_t normalFrames = budget.normalFrames;
+
 			// For each peer, decide what frames to send...
 			for (const auto& pair : playersSnapshot)
 			{
@@ -915,59 +3091,106 @@ namespace rollback
 				const auto peer = pair.second;
 				size_t idx = peer->playerIndex;
 
-				std::map<uint32_t, uint32_t> histMap;
+				std::map<uint32_t, FrameInput> histMap;
 				{
 					// grab the lock, copy the map, then immediately release
 					// histMap = match->inputs[idx];
 					histMap = match->inputs[idx].snapshot();
 				}
 				const uint32_t lastAck = ackedFrames[idx];
+				// Nothing after the last frame a uint32 can name; wrapping to 0 would resend the whole history
+				if (lastAck == UINT32_MAX)
+					continue;
 				const uint32_t nextFrame = lastAck + 1;
+				if (match->netcodeMode == NetcodeMode::InputDelay)
+				{
+					// Lockstep: only frames everyone has submitted, never a prediction
+					startFrame[idx] = nextFrame;
+					const uint8_t runLength = playerInputRunLength(histMap, lastAck, normalFrames, releaseFrame);
+					for (uint8_t i = 0; i < runLength; i++)
+					{
+						const uint32_t f = nextFrame + i;
+						if (match->predictedFrames[idx].contains(f))
+							numPredictedOverrides++;
+						inputPerFrame[idx].push_back(histMap.at(f));
+						numFrames[idx]++;
+					}
+					continue;
+				}
 				auto missedInputSnapshot = recipient->missedInputs.snapshot();
 				// If we have the next real input
 				if (histMap.find(nextFrame) != histMap.end())
 				{
-					uint8_t sentCount = 0;
 					startFrame[idx] = nextFrame;
+					// A recipient this far behind (lost acks, a stall) would take seconds to
+					// drain at the normal rate, so send as much as fits in one packet
+					const bool catchingUp = histMap.rbegin()->first - lastAck > CATCH_UP_LAG;
+					const uint8_t maxFrames = catchingUp ? catchUpFrames : normalFrames;
+					if (catchingUp)
+					{
+						logDebug(logCtx(recipient), "Catching up player ", idx, ": acked ", lastAck, ", have ", histMap.rbegin()->first);
+					}
 					// Send everything we actually have
-					uint32_t f = nextFrame;
-					while (histMap.count(f) && sentCount < MAX_INPUTS_PER_FRAME)
+					const uint8_t runLength = playerInputRunLength(histMap, lastAck, maxFrames);
+					bool sentReal = false;
+					for (uint8_t i = 0; i < runLength; i++)
 					{
+						const uint32_t f = nextFrame + i;
+						if (match->predictedFrames[idx].contains(f))
+							numPredictedOverrides++;
+						else
+							sentReal = true;
 						inputPerFrame[idx].push_back(histMap.at(f));
 						numFrames[idx]++;
-						f++;
-						sentCount++;
 					}
 
-					recipient->missedInputs.insert_or_assign(idx, 0); // Reset miss counter
+					// Frames the server predicted last tick are found here too; only the peer's
+					// own input means it has caught up
+					if (sentReal)
+					{
+						recipient->missedInputs.insert_or_assign(idx, 0); // Reset miss counter
+						if (peer->lagging.exchange(false))
+						{
+							logInfo(logCtx(peer), "Player caught up, no longer lagging");
+						}
+					}
 				}
-				else if (missedInputSnapshot[idx] < 10)
+				else if (recipient->spectator || match->draining)
+				{
+					// Spectators only ever get real inputs; predicting on their behalf would
+					// overwrite history the players still need. Nor is there anything left
+					// to predict once the match is over.
+					continue;
+				}
+				else if (missedInputSnapshot[idx] < config_.laggyMissedInputs)
 				{
 					startFrame[idx] = lastAck;
 					recipient->missedInputs.insert_or_assign(idx, ++missedInputSnapshot[idx]);
-					const uint32_t lastVal = histMap.find(lastAck) != histMap.end() ? histMap.at(lastAck) : 0;
+					const FrameInput lastVal = histMap.find(lastAck) != histMap.end() ? histMap.at(lastAck) : FrameInput{};
 					inputPerFrame[idx].push_back(lastVal);
 					numFrames[idx] = 1;
 				}
 				else
 				{
+					markLagging(peer, nextFrame);
 					startFrame[idx] = nextFrame;
 					uint32_t predictedCount = 0;
 					uint32_t f = nextFrame;
-					const uint32_t lastVal = histMap.find(lastAck) != histMap.end() ? histMap.at(lastAck) : 0;
+					const FrameInput lastVal = histMap.find(lastAck) != histMap.end() ? histMap.at(lastAck) : FrameInput{};
 					{
 
 						// while (f < match->currentFrame)
-						while (f < lastClientFrame && predictedCount < MAX_INPUTS_PER_FRAME)
+						while (f < lastClientFrame && predictedCount < normalFrames)
 						{
 							match->inputs[idx].insert_or_assign(f, lastVal);
+							match->predictedFrames[idx].insert_or_assign(f, true);
 							inputPerFrame[idx].push_back(lastVal);
 							predictedCount++;
 							f++;
 						}
 					}
 					numFrames[idx] = static_cast<uint8_t>(predictedCount);
-					numPredictedOverrides = static_cast<uint16_t>(predictedCount);
+					numPredictedOverrides += predictedCount;
 				}
 			}
 
@@ -976,42 +3199,60 @@ namespace rollback
 			playerInputPayload.numPlayers = static_cast<uint8_t>(match->players.size());
 			playerInputPayload.startFrame = startFrame;
 			playerInputPayload.numFrames = numFrames;
-			playerInputPayload.numPredictedOverrides = numPredictedOverrides;
+			playerInputPayload.numPredictedOverrides = static_cast<uint16_t>(std::min<uint32_t>(numPredictedOverrides, UINT16_MAX));
 			playerInputPayload.numZeroedOverrides = 0;
 			playerInputPayload.ping = ping;
-			playerInputPayload.packetsLossPercent = 0;
+			playerInputPayload.packetsLossPercent = packetsLossPercent;
 			playerInputPayload.rift = smoothRift;
 			playerInputPayload.checksumAckFrame = 0;
 			playerInputPayload.inputPerFrame = inputPerFrame;
 
 			// Fire off the personalized PlayerInput
-			auto ts = steady_clock::now();
-			co_await sendPlayerInput(match, recipient, playerInputPayload);
-			recipient->pendingPings.insert_or_assign(match->sequenceCounter, ts);
+			// Spectators never ack, so only players' PlayerInputs are timed
+			const auto ts = clock_->now();
+			co_await sendPlayerInput(match, recipient, playerInputPayload, ts);
+
+			if (recipient->spectator)
+			{
+				// Spectators don't ack, so treat everything we just sent as delivered
+				std::unique_lock lock(recipient->mutex);
+				for (size_t idx = 0; idx < recipient->ackedFrames.size(); ++idx)
+				{
+					if (numFrames[idx] > 0)
+					{
+						recipient->ackedFrames[idx] = startFrame[idx] + numFrames[idx] - 1;
+					}
+				}
+				continue;
+			}
 		}
 
 		// === Cleanup histMap every 200 frames ===
 		if (match->currentFrame % 200 == 0)
 		{
-			for (int idx = 0; idx < match->inputs.size(); ++idx)
+			for (size_t idx = 0; idx < match->inputs.size(); ++idx)
 			{
 				auto& histMap = match->inputs[idx];
-				if (histMap.size() > 150)
+				if (histMap.size() > INPUT_HISTORY_FRAMES)
 				{
-					// Remove all but the last 100 entries (by frame number)
+					// Remove all but the last INPUT_HISTORY_FRAMES entries (by frame number)
 					std::vector<uint32_t> frames;
 					for (const auto& kv : histMap.snapshot())
 					{
 						frames.push_back(kv.first);
 					}
-					if (frames.size() > 150)
+					if (const auto through = inputHistoryPruneThrough(frames))
 					{
-						std::sort(frames.begin(), frames.end());
-						size_t toRemove = frames.size() - 150;
-						for (size_t i = 0; i < toRemove; ++i)
+						for (const uint32_t frame : frames)
 						{
-							histMap.erase(frames[i]);
+							if (frame <= *through)
+							{
+								histMap.erase(frame);
+								match->predictedFrames[idx].erase(frame);
+							}
 						}
+						std::unique_lock lock(match->mutex);
+						match->prunedThrough[idx] = std::max(match->prunedThrough[idx], *through);
 					}
 				}
 			}
@@ -1021,24 +3262,66 @@ namespace rollback
 		co_return;
 	}
 
-	asio::awaitable<void> RollbackServer::sendPlayerInput(
+	uint32_t RollbackServer::advanceLockstepFrame(
 		std::shared_ptr<MatchState> match,
-		std::shared_ptr<PlayerInfo> player,
-		const PlayerInputPayload& payload)
+		const std::map<std::string, std::shared_ptr<PlayerInfo>>& playersSnapshot)
 	{
+		uint32_t serverFrame;
+		{
+			std::shared_lock lock(match->mutex);
+			serverFrame = match->currentFrame;
+		}
 
-		// Record when we sent, for RTT
-		player->lastSentTime = steady_clock::now();
-		co_await sendServerMessage(match, player, ServerMessageType::PlayerInput, payload);
+		// A frame is released once every connected player has sent it and it has sat
+		// for the configured delay. Players that dropped out repeat their last input
+		// so they can't stall the others.
+		while (match->releasedFrame + 1 + match->inputDelayFrames <= serverFrame)
+		{
+			const uint32_t f = match->releasedFrame + 1;
+			bool complete = true;
+			for (const auto& p : playersSnapshot)
+			{
+				const auto& peer = p.second;
+				if (peer->playerIndex >= match->inputs.size())
+					continue;
+				auto& histMap = match->inputs[peer->playerIndex];
+				if (histMap.contains(f))
+					continue;
+				if (!peer->disconnected)
+				{
+					complete = false;
+					break;
+				}
+				histMap.insert_or_assign(f, histMap.find(f - 1).value_or(FrameInput{}));
+				match->predictedFrames[peer->playerIndex].insert_or_assign(f, true);
+			}
+			if (!complete)
+				break;
+			match->releasedFrame = f;
+		}
+		return match->releasedFrame;
+	}
 
-		co_return;
+	asio::awaitable<uint32_t> RollbackServer::sendPlayerInput(
+		std::shared_ptr<MatchState> match,
+		std::shared_ptr<PlayerInfo> player,
+		const PlayerInputPayload& payload,
+		time_point<steady_clock> sentAt)
+	{
+		// Record when we sent, for RTT
+		player->lastSentTime = sentAt;
+		std::optional<time_point<steady_clock>> pingSentAt;
+		if (!player->spectator)
+			pingSentAt = sentAt;
+		co_return co_await sendServerMessage(match, player, ServerMessageType::PlayerInput, payload, pingSentAt);
 	}
 
 	asio::awaitable<uint32_t> RollbackServer::sendServerMessage(
 		std::shared_ptr<MatchState> match,
 		std::shared_ptr<PlayerInfo> player,
 		ServerMessageType type,
-		const ServerMessageVariant& payload)
+		const ServerMessageVariant& payload,
+		std::optional<time_point<steady_clock>> pingSentAt)
 	{
 		if (player->disconnected)
 		{
@@ -1052,12 +3335,91 @@ namespace rollback
 			std::unique_lock lock(match->mutex);
 			header.sequence = ++match->sequenceCounter;
 		}
+		if (pingSentAt)
+		{
+			player->pendingPings.insert_or_assign(header.sequence, *pingSentAt);
+		}
 
 		// Serialize the message
-		auto buf = serializeServerMessage(header, payload, match->max_players_);
+		auto buf = serializeServerMessage(header, payload, match->max_players_, config_.inputWidth);
+
+		if (config_.coalesceMessages)
+		{
+			// Whatever else gets queued for this player before the flush runs rides along
+			bool firstQueued;
+			{
+				std::lock_guard lock(player->outboxMutex);
+				firstQueued = player->outbox.empty();
+				player->outbox.push_back(std::move(buf));
+			}
+			if (firstQueued)
+			{
+				asio::post(io_context_, [this, match, player]()
+					{
+						asio::co_spawn(io_context_, flushOutbox(match, player), supervise(logCtx(player), "Outbox flush"));
+					});
+			}
+			co_return header.sequence;
+		}
+
+		if (!co_await transmit(match, player, buf))
+		{
+			// Never sent, so never lost either
+			if (pingSentAt)
+			{
+				player->pendingPings.erase(header.sequence);
+			}
+			co_return 0;
+		}
+		co_return header.sequence;
+	}
+
+	asio::awaitable<void> RollbackServer::flushOutbox(std::shared_ptr<MatchState> match, std::shared_ptr<PlayerInfo> player)
+	{
+		std::vector<std::vector<uint8_t>> queued;
+		{
+			std::lock_guard lock(player->outboxMutex);
+			queued.swap(player->outbox);
+		}
+
+		// Greedily fill each datagram up to the same budget a lone PlayerInput gets
+		std::vector<std::vector<uint8_t>> batch;
+		size_t batchSize = 1;
+		auto sendBatch = [&]() -> asio::awaitable<bool>
+			{
+				bool sent = co_await transmit(match, player, batch.size() == 1 ? batch.front() : serializeBatch(batch));
+				batch.clear();
+				batchSize = 1;
+				co_return sent;
+			};
+		for (auto& message : queued)
+		{
+			if (!batch.empty() && batchSize + 2 + message.size() > MAX_UNCOMPRESSED_PAYLOAD)
+			{
+				if (!co_await sendBatch())
+					co_return;
+			}
+			batchSize += 2 + message.size();
+			batch.push_back(std::move(message));
+		}
+		if (!batch.empty())
+		{
+			co_await sendBatch();
+		}
+	}
+
+	asio::awaitable<bool> RollbackServer::transmit(
+		std::shared_ptr<MatchState> match,
+		std::shared_ptr<PlayerInfo> player,
+		const std::vector<uint8_t>& buf)
+	{
+		if (player->disconnected)
+		{
+			co_return false;
+		}
 
 		// Compress the buffer
-		auto compressedBuf = compressPacket(buf);
+		auto compressedBuf = encodeDatagram(buf, player->compression, player->checksums);
 
 		asio::ip::address address;
 		uint16_t port;
@@ -1068,123 +3430,147 @@ namespace rollback
 
 		udp::endpoint remote(address, port);
 
-		try
+		if (match->recorder)
 		{
-			co_await socket_.async_send_to(asio::buffer(compressedBuf), remote, asio::use_awaitable);
+			match->recorder->record(ReplayDirection::ServerToClient, replayPlayerIndex(player), compressedBuf);
 		}
-		catch (const std::system_error& e)
+
+		const size_t compressedSize = compressedBuf.size();
+
+		if (config_.sendQueueLimit > 0)
 		{
-			std::cerr << "Send failed for player " << player->playerIndex << ": " << e.what() << std::endl;
-			player->disconnected = true;
-			co_return 0;
+			// Hand the datagram to the player's sender task so a congested socket
+			// stalls that player's sends only, never the caller
+			bool startDrain = false;
+			{
+				std::lock_guard lock(player->sendQueueMutex);
+				if (player->sendQueue.size() >= config_.sendQueueLimit)
+				{
+					player->sendQueueDrops++;
+					logDebug(logCtx(player), "Send queue full, dropping message type ", static_cast<int>(buf.front()));
+					co_return false;
+				}
+				player->sendQueue.push_back(std::move(compressedBuf));
+				if (!player->sendQueueDraining)
+				{
+					player->sendQueueDraining = true;
+					startDrain = true;
+				}
+			}
+			if (startDrain)
+			{
+				asio::co_spawn(io_context_, drainSendQueue(player), supervise(logCtx(player), "Send queue"));
+			}
+		}
+		else
+		{
+			try
+			{
+				co_await sendDatagram(compressedBuf, remote);
+			}
+			catch (const std::system_error& e)
+			{
+				logError(logCtx(player), "Send failed: ", e.what());
+				player->disconnected = true;
+				co_return false;
+			}
 		}
 
-		co_return header.sequence;
+		player->lastAnySendTime = clock_->now().time_since_epoch().count();
+		match->traffic.packetsSent++;
+		match->traffic.bytesSent += compressedSize;
+		match->traffic.uncompressedBytesSent += buf.size();
+
+		co_return true;
 	}
 
-	std::optional<MVSIMatchConfig> RollbackServer::fetchMatchConfigFromServer(const std::string& matchId, const std::string& key)
+	asio::awaitable<void> RollbackServer::drainSendQueue(std::shared_ptr<PlayerInfo> player)
 	{
-		std::string base_url;
-		if (const char* env_p = std::getenv("mvsi_server")) {
-			base_url = env_p;
-		}
-		else {
-			std::cerr << "mvsi_server environment variable not set!" << std::endl;
-			return std::nullopt;
-		}
-		std::string url = base_url + "/mvsi_register";
-
-		nlohmann::json req_json;
-		req_json["matchId"] = matchId;
-		req_json["key"] = key;
-		std::string req_body = req_json.dump();
+		while (true)
+		{
+			std::vector<uint8_t> datagram;
+			{
+				std::lock_guard lock(player->sendQueueMutex);
+				if (player->sendQueue.empty() || player->disconnected)
+				{
+					player->sendQueue.clear();
+					player->sendQueueDraining = false;
+					co_return;
+				}
+				datagram = std::move(player->sendQueue.front());
+				player->sendQueue.pop_front();
+			}
 
-		CURL* curl = curl_easy_init();
-		if (!curl) {
-			std::cerr << "Failed to init curl" << std::endl;
-			return std::nullopt;
-		}
-		struct curl_slist* headers = nullptr;
-		headers = curl_slist_append(headers, "Content-Type: application/json");
-		std::string response;
-		curl_easy_setopt(curl, CURLOPT_URL, url.c_str());
-		curl_easy_setopt(curl, CURLOPT_HTTPHEADER, headers);
-		curl_easy_setopt(curl, CURLOPT_POSTFIELDS, req_body.c_str());
-		curl_easy_setopt(curl, CURLOPT_WRITEFUNCTION, +[](char* ptr, size_t size, size_t nmemb, void* userdata) -> size_t {
-			std::string* resp = static_cast<std::string*>(userdata);
-			resp->append(ptr, size * nmemb);
-			return size * nmemb;
-			});
-		curl_easy_setopt(curl, CURLOPT_WRITEDATA, &response);
-		CURLcode res = curl_easy_perform(curl);
-		curl_slist_free_all(headers);
-		curl_easy_cleanup(curl);
-		if (res != CURLE_OK) {
-			std::cerr << "Failed to POST to " << url << ": " << curl_easy_strerror(res) << std::endl;
-			return std::nullopt;
-		}
-		nlohmann::json resp_json = nlohmann::json::parse(response, nullptr, false);
-		if (resp_json.is_discarded()) {
-			std::cerr << "Invalid JSON from mvsi_register" << std::endl;
-			return std::nullopt;
-		}
-		MVSIMatchConfig config;
-		config.max_players = resp_json.value("max_players", 2);
-		config.match_duration = resp_json.value("match_duration", 36000);
-		if (resp_json.contains("players")) {
-			for (const auto& p : resp_json["players"]) {
-				MVSIPlayer player;
-				player.player_index = p.value("player_index", 0);
-				player.ip = p.value("ip", "");
-				player.is_host = p.value("is_host", false);
-				config.players.push_back(player);
+			// Read the endpoint per datagram: the player may have moved since it was queued
+			udp::endpoint remote;
+			{
+				std::shared_lock lock(player->mutex);
+				remote = udp::endpoint(player->address, player->port);
+			}
+			try
+			{
+				co_await sendDatagram(std::move(datagram), remote);
+			}
+			catch (const std::system_error& e)
+			{
+				// One failed datagram (ICMP unreachable, a full buffer) says nothing about
+				// the player; the disconnect timeout decides whether it's gone
+				logWarn(logCtx(player), "Send failed, dropping datagram: ", e.what());
+				player->sendQueueDrops++;
 			}
 		}
-		return config;
 	}
 
-	void RollbackServer::sendEndMatch(const std::string& matchId, const std::string& key)
+	asio::awaitable<void> RollbackServer::sendUnregisteredMessage(
+		udp::endpoint remote,
+		ServerMessageType type,
+		ServerMessageVariant payload,
+		uint16_t messageVersion)
 	{
-		std::string base_url;
-		if (const char* env_p = std::getenv("mvsi_server")) {
-			base_url = env_p;
+		ServerHeader header;
+		header.type = type;
+		header.sequence = 0;
+
+		auto buf = serializeServerMessage(header, payload, config_.maxPlayers, config_.inputWidth);
+		const uint16_t capabilities = protocolCapabilities(messageVersion);
+		auto compressedBuf = encodeDatagram(buf, (capabilities & CAPABILITY_UNCOMPRESSED) == 0,
+			(capabilities & CAPABILITY_CHECKSUMS) != 0);
+
+		try
+		{
+			co_await sendDatagram(std::move(compressedBuf), remote);
 		}
-		else {
-			std::cerr << "mvsi_server environment variable not set!" << std::endl;
-			return;
+		catch (const std::system_error& e)
+		{
+			logError(logCtx(remote), "Send failed: ", e.what());
 		}
-		std::string url = base_url + "/mvsi_end_match";
 
-		nlohmann::json req_json;
-		req_json["matchId"] = matchId;
-		req_json["key"] = key;
-		std::string req_body = req_json.dump();
+		co_return;
+	}
 
-		CURL* curl = curl_easy_init();
-		if (!curl) {
-			std::cerr << "Failed to init curl" << std::endl;
-			return;
-		}
-		struct curl_slist* headers = nullptr;
-		headers = curl_slist_append(headers, "Content-Type: application/json");
-		std::string response;
-		curl_easy_setopt(curl, CURLOPT_URL, url.c_str());
-		curl_easy_setopt(curl, CURLOPT_HTTPHEADER, headers);
-		curl_easy_setopt(curl, CURLOPT_POSTFIELDS, req_body.c_str());
-		curl_easy_setopt(curl, CURLOPT_WRITEFUNCTION, +[](char* ptr, size_t size, size_t nmemb, void* userdata) -> size_t {
-			std::string* resp = static_cast<std::string*>(userdata);
-			resp->append(ptr, size * nmemb);
-			return size * nmemb;
-			});
-		curl_easy_setopt(curl, CURLOPT_WRITEDATA, &response);
-		CURLcode res = curl_easy_perform(curl);
-		curl_slist_free_all(headers);
-		curl_easy_cleanup(curl);
-		if (res != CURLE_OK) {
-			std::cerr << "Failed to POST to " << url << ": " << curl_easy_strerror(res) << std::endl;
-			return;
+	asio::awaitable<void> RollbackServer::sendDatagram(std::vector<uint8_t> datagram, udp::endpoint remote)
+	{
+		if (conditioner_.enabled())
+		{
+			auto delay = conditioner_.delayFor();
+			if (!delay)
+				co_return; // simulated loss
+			if (delay->count() > 0)
+			{
+				// Don't hold the caller up; the datagram goes out on its own later
+				asio::co_spawn(io_context_,
+					[this, datagram = std::move(datagram), remote, delay = *delay]() -> asio::awaitable<void>
+					{
+						asio::steady_timer timer(io_context_, delay);
+						co_await timer.async_wait(asio::use_awaitable);
+						co_await socket_.async_send_to(asio::buffer(datagram), remote, asio::use_awaitable);
+					},
+					supervise(logCtx(remote), "Delayed send"));
+				co_return;
+			}
 		}
-		return;
+
+		co_await socket_.async_send_to(asio::buffer(datagram), remote, asio::use_awaitable);
 	}
 
 } // namespace rollback
\ No newline at end of file
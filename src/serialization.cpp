@@ -1,102 +1,113 @@
 #include "serialization.h"
+#include "logging.h"
+#include "packet_io.h"
 #include <string.h>
+#include <algorithm>
 #include <array>
+#include <cassert>
+#include <stdexcept>
+#include <string>
 
 namespace rollback {
 
-// Little endian utility functions
-template<typename T>
-void writeLittleEndian(std::vector<uint8_t>& buffer, size_t offset, T value) {
-    for (size_t i = 0; i < sizeof(T); ++i) {
-        buffer[offset + i] = static_cast<uint8_t>((value >> (i * 8)) & 0xFF);
-    }
-}
-
-template<typename T>
-T readLittleEndian(std::span<const uint8_t> buffer, size_t offset) {
-    T value = 0;
-    for (size_t i = 0; i < sizeof(T); ++i) {
-        if (offset + i < buffer.size()) {
-            value |= static_cast<T>(buffer[offset + i]) << (i * 8);
-        }
-    }
-    return value;
-}
-
-// Constants for the PlayerConfig values
-constexpr std::array<uint16_t, 4> PlayerConfigValues = {0, 257, 512, 769};
-
-std::optional<ClientMessageComplete> parseClientMessage(std::span<const uint8_t> buffer) {
+std::optional<ClientMessageComplete> parseClientMessage(std::span<const uint8_t> buffer, size_t inputWidth,
+                                                        std::string* error, InputEncoding inputEncoding) {
     const size_t HEADER_SIZE = 5; // type:uint8 + sequence:uint32LE
     
-    if (buffer.size() < HEADER_SIZE) {
+    auto reject = [error](std::string reason) -> std::optional<ClientMessageComplete> {
+        if (error) {
+            *error = std::move(reason);
+        }
         return std::nullopt;
+    };
+
+    if (buffer.size() < HEADER_SIZE) {
+        return reject("packet shorter than the header");
+    }
+    if (inputWidth == 0 || inputWidth > MAX_INPUT_WIDTH) {
+        return reject("invalid input width");
     }
     
-    size_t offset = 0;
+    PacketReader reader(buffer);
     
     // Read header
     ClientHeader header;
-    header.type = static_cast<ClientMessageType>(buffer[offset++]);
-    header.sequence = readLittleEndian<uint32_t>(buffer, offset);
-    offset += 4;
+    header.type = static_cast<ClientMessageType>(reader.u8());
+    header.sequence = reader.u32le();
     
     ClientMessageComplete result;
     result.header = header;
 
     // Parse payload based on message type
     switch (header.type) {
-        case ClientMessageType::NewConnection: {
-            NewConnectionPayload payload;
-            payload.messageVersion = readLittleEndian<uint16_t>(buffer, offset);
-            offset += 2;
-            
-            payload.playerData.teamId = readLittleEndian<uint16_t>(buffer, offset);
-            offset += 2;
-            payload.playerData.playerIndex = readLittleEndian<uint16_t>(buffer, offset);
-            offset += 2;
-            
-            // Read strings
-            auto readString = [&buffer, &offset](size_t maxLen) -> std::string {
-                std::string result;
-                size_t end = offset;
-                while (end < buffer.size() && end < offset + maxLen && buffer[end] != 0) {
-                    end++;
-                }
-                result.assign(reinterpret_cast<const char*>(&buffer[offset]), end - offset);
-                offset += maxLen; // Skip to end of string field
-                return result;
+        case ClientMessageType::NewConnection:
+        case ClientMessageType::SpectatorConnection: {
+            // Fixed layout: version, team, index, then three zero-padded strings
+            struct Field {
+                const char* name;
+                size_t size;
             };
+            static constexpr std::array<Field, 6> fields{ {
+                { "messageVersion", 2 }, { "teamId", 2 }, { "playerIndex", 2 },
+                { "matchId", 25 }, { "key", 45 }, { "environmentId", 25 },
+            } };
+            size_t needed = 0;
+            for (const auto& field : fields) {
+                needed += field.size;
+                if (!reader.has(needed)) {
+                    return reject(std::string("connection packet truncated in ") + field.name);
+                }
+            }
+
+            NewConnectionPayload payload;
+            payload.messageVersion = reader.u16le();
+            payload.playerData.teamId = reader.u16le();
+            payload.playerData.playerIndex = reader.u16le();
             
-            payload.matchData.matchId = readString(25);
-            payload.matchData.key = readString(45);
-            payload.matchData.environmentId = readString(25);
+            // Anything but printable ASCII before the terminator is garbage, not text
+            if (!reader.stringFixed(25, payload.matchData.matchId)) {
+                return reject("non-printable character in matchId");
+            }
+            if (!reader.stringFixed(45, payload.matchData.key)) {
+                return reject("non-printable character in key");
+            }
+            if (!reader.stringFixed(25, payload.matchData.environmentId)) {
+                return reject("non-printable character in environmentId");
+            }
             
             result.payload = payload;
             break;
         }
         case ClientMessageType::Input: {
             InputPayload payload;
-            payload.startFrame = readLittleEndian<uint32_t>(buffer, offset);
-            offset += 4;
-            payload.clientFrame = readLittleEndian<uint32_t>(buffer, offset);
-            offset += 4;
-            payload.numFrames = buffer[offset++];
-            payload.numChecksums = buffer[offset++];
+            payload.startFrame = reader.u32le();
+            payload.clientFrame = reader.u32le();
+            payload.numFrames = reader.u8();
+            payload.numChecksums = reader.u8();
             
             // Read input data
-            for (uint8_t i = 0; i < payload.numFrames; ++i) {
-                if (offset + 4 <= buffer.size()) {
-                    payload.inputPerFrame.push_back(readLittleEndian<uint32_t>(buffer, offset));
-                    offset += 4;
+            if (inputEncoding == InputEncoding::RunLength) {
+                while (payload.inputPerFrame.size() < payload.numFrames && reader.has(1 + inputWidth)) {
+                    const uint8_t run = reader.u8();
+                    if (run == 0) {
+                        return reject("empty input run");
+                    }
+                    const FrameInput input = reader.input(inputWidth);
+                    const size_t count = std::min<size_t>(run, payload.numFrames - payload.inputPerFrame.size());
+                    payload.inputPerFrame.insert(payload.inputPerFrame.end(), count, input);
+                }
+            } else {
+                for (uint8_t i = 0; i < payload.numFrames; ++i) {
+                    if (reader.has(inputWidth)) {
+                        payload.inputPerFrame.push_back(reader.input(inputWidth));
+                    }
                 }
             }
             
             // Read checksum data
             for (uint8_t i = 0; i < payload.numChecksums; ++i) {
-                if (offset + 4 <= buffer.size()) {
-                    payload.checksumPerFrame.push_back(readLittleEndian<uint32_t>(buffer, offset));
-                    offset += 4;
+                if (reader.has(4)) {
+                    payload.checksumPerFrame.push_back(reader.u32le());
                 }
             }
             
@@ -105,259 +116,491 @@ std::optional<ClientMessageComplete> parseClientMessage(std::span<const uint8_t>
         }
         case ClientMessageType::PlayerInputAck: {
             PlayerInputAckPayload payload;
-            payload.numPlayers = buffer[offset++];
+            payload.numPlayers = reader.u8();
             
             // Read ack frames
             for (uint8_t i = 0; i < payload.numPlayers; ++i) {
-                if (offset + 4 <= buffer.size()) {
-                    payload.ackFrame.push_back(readLittleEndian<uint32_t>(buffer, offset));
-                    offset += 4;
+                if (reader.has(4)) {
+                    payload.ackFrame.push_back(reader.u32le());
                 }
             }
             
-            payload.serverMessageSequenceNumber = readLittleEndian<uint32_t>(buffer, offset);
+            payload.serverMessageSequenceNumber = reader.u32le();
             
             result.payload = payload;
             break;
         }
         case ClientMessageType::MatchResult: {
             MatchResultPayload payload;
-            payload.numPlayers = buffer[offset++];
-            payload.lastFrameChecksum = readLittleEndian<uint32_t>(buffer, offset);
-            offset += 4;
-            payload.winningTeamIndex = buffer[offset++];
+            payload.numPlayers = reader.u8();
+            payload.lastFrameChecksum = reader.u32le();
+            payload.winningTeamIndex = reader.u8();
             
             result.payload = payload;
             break;
         }
         case ClientMessageType::QualityData: {
             QualityDataPayload payload;
-            payload.serverMessageSequenceNumber = readLittleEndian<uint32_t>(buffer, offset);
+            payload.serverMessageSequenceNumber = reader.u32le();
             
             result.payload = payload;
             break;
         }
         case ClientMessageType::Disconnecting: {
             DisconnectingPayload payload;
-            payload.reason = buffer[offset++];
+            payload.reason = reader.u8();
             
             result.payload = payload;
             break;
         }
         case ClientMessageType::PlayerDisconnectedAck: {
             PlayerDisconnectedAckPayload payload;
-            payload.playerDisconnectedArrayIndex = buffer[offset++];
+            payload.playerDisconnectedArrayIndex = reader.u8();
             
             result.payload = payload;
             break;
         }
         case ClientMessageType::ReadyToStartMatch: {
             ReadyToStartMatchPayload payload;
-            payload.ready = buffer[offset++];
+            payload.ready = reader.u8();
             
             result.payload = payload;
             break;
         }
         default:
-            return std::nullopt; // Unknown message type
+            return reject("unknown message type " + std::to_string(static_cast<int>(header.type)));
     }
     
     return result;
 }
 
-std::vector<uint8_t> serializeServerMessage(const ServerHeader& header, 
-                                           const ServerMessageVariant& payload,
-                                           int maxPlayers) {
-    // First calculate the size
-    size_t size = 5; // Header size (1 byte type + 4 bytes sequence)
-    
-    // Calculate payload size
-    std::visit([&size, maxPlayers](auto&& arg) {
+const char* clientMessageTypeName(ClientMessageType type) {
+    switch (type) {
+        case ClientMessageType::NewConnection: return "NewConnection";
+        case ClientMessageType::Input: return "Input";
+        case ClientMessageType::PlayerInputAck: return "PlayerInputAck";
+        case ClientMessageType::MatchResult: return "MatchResult";
+        case ClientMessageType::QualityData: return "QualityData";
+        case ClientMessageType::Disconnecting: return "Disconnecting";
+        case ClientMessageType::PlayerDisconnectedAck: return "PlayerDisconnectedAck";
+        case ClientMessageType::ReadyToStartMatch: return "ReadyToStartMatch";
+        case ClientMessageType::SpectatorConnection: return "SpectatorConnection";
+    }
+    return "unknown";
+}
+
+// The message type each payload is sent as
+static ServerMessageType payloadMessageType(const ServerMessageVariant& payload) {
+    return std::visit([](auto&& arg) -> ServerMessageType {
         using T = std::decay_t<decltype(arg)>;
-        
-        if constexpr (std::is_same_v<T, NewConnectionReplyPayload>) {
-            size += 9; // uint8 + uint8 + uint8 + uint32 + uint8 + uint8
-        }
-        else if constexpr (std::is_same_v<T, InputAckPayload>) {
-            size += 4; // uint32
+        if constexpr (std::is_same_v<T, NewConnectionReplyPayload>) return ServerMessageType::NewConnectionReply;
+        else if constexpr (std::is_same_v<T, InputAckPayload>) return ServerMessageType::InputAck;
+        else if constexpr (std::is_same_v<T, PlayerInputPayload>) return ServerMessageType::PlayerInput;
+        else if constexpr (std::is_same_v<T, RequestQualityDataPayload>) return ServerMessageType::RequestQualityData;
+        else if constexpr (std::is_same_v<T, PlayersStatusPayload>) return ServerMessageType::PlayersStatus;
+        else if constexpr (std::is_same_v<T, KickPayload>) return ServerMessageType::Kick;
+        else if constexpr (std::is_same_v<T, ChecksumAckPayload>) return ServerMessageType::ChecksumAck;
+        else if constexpr (std::is_same_v<T, PlayersConfigurationDataPayload>) return ServerMessageType::PlayersConfigurationData;
+        else if constexpr (std::is_same_v<T, PlayerDisconnectedPayload>) return ServerMessageType::PlayerDisconnected;
+        else if constexpr (std::is_same_v<T, ChangePortPayload>) return ServerMessageType::ChangePort;
+        else return ServerMessageType::StartGame; // std::monostate
+    }, payload);
+}
+
+std::optional<size_t> expectedServerMessageSize(ServerMessageType type, int maxPlayers) {
+    const size_t HEADER_SIZE = 5; // type:uint8 + sequence:uint32LE
+    const size_t players = static_cast<size_t>(std::max(maxPlayers, 0));
+
+    switch (type) {
+        case ServerMessageType::NewConnectionReply: return HEADER_SIZE + 9;
+        case ServerMessageType::StartGame: return HEADER_SIZE;
+        case ServerMessageType::InputAck: return HEADER_SIZE + 4;
+        // numPlayers, startFrame[] and numFrames[], overrides, ping, loss, rift, checksumAckFrame
+        case ServerMessageType::PlayerInput: return HEADER_SIZE + 1 + 5 * players + 14;
+        case ServerMessageType::RequestQualityData: return HEADER_SIZE + 4;
+        case ServerMessageType::PlayersStatus: return HEADER_SIZE + 1 + 2 * players;
+        case ServerMessageType::Kick: return HEADER_SIZE + 6;
+        case ServerMessageType::ChecksumAck: return HEADER_SIZE + 4;
+        case ServerMessageType::PlayersConfigurationData: return HEADER_SIZE + 1 + 2 * players;
+        case ServerMessageType::PlayerDisconnected: return HEADER_SIZE + 8;
+        case ServerMessageType::ChangePort: return HEADER_SIZE + 2;
+        default: return std::nullopt;
+    }
+}
+
+PlayerInputFrameBudget playerInputFrameBudget(int maxPlayers, size_t inputWidth, size_t frameCap) {
+    const size_t players = static_cast<size_t>(std::max(maxPlayers, 1));
+    const size_t width = std::max<size_t>(inputWidth, 1);
+    const size_t fixedSize = *expectedServerMessageSize(ServerMessageType::PlayerInput, maxPlayers);
+    const size_t cap = frameCap != 0 ? std::min<size_t>(frameCap, 255) : 255;
+    const size_t fits = fixedSize < MAX_UNCOMPRESSED_PAYLOAD ? (MAX_UNCOMPRESSED_PAYLOAD - fixedSize) / width / players : 0;
+    const size_t catchUp = std::clamp<size_t>(std::min(fits, cap), 1, 255);
+    return { static_cast<uint8_t>(std::min<size_t>(catchUp, MAX_INPUTS_PER_FRAME)), static_cast<uint8_t>(catchUp) };
+}
+
+uint8_t playerInputRunLength(const std::map<uint32_t, FrameInput>& history, uint32_t lastAcked, uint8_t maxFrames,
+                             uint32_t lastFrame) {
+    if (lastAcked == UINT32_MAX) {
+        return 0;
+    }
+    uint8_t count = 0;
+    uint32_t expected = lastAcked + 1;
+    for (auto it = history.find(expected); it != history.end() && it->first == expected; ++it) {
+        if (count == maxFrames || expected > lastFrame) {
+            break;
         }
-        else if constexpr (std::is_same_v<T, RequestQualityDataPayload>) {
-            size += 4; // int16 + int16
+        ++count;
+        if (expected == UINT32_MAX) {
+            break;
         }
-        else if constexpr (std::is_same_v<T, PlayerInputPayload>) {
-            // numPlayers + startFrame[] + numFrames[] + numPredicted + numZeroed
-            // + ping + packetsLoss + rift + checksumAck + inputPerFrame[][]
-            const auto& p = arg;
-            size += 1; // numPlayers
-            size += maxPlayers * 4; // startFrame
-            size += maxPlayers; // numFrames
-            size += 2 + 2; // numPredicted + numZeroed
-            size += 2 + 2 + 2; // ping + packetsLoss + rift
-            size += 4; // checksumAck
-            
-            // inputPerFrame
-            for (int i = 0; i < maxPlayers && i < static_cast<int>(p.numFrames.size()); ++i) {
-                size += p.numFrames[i] * 4;
+        ++expected;
+    }
+    return count;
+}
+
+std::optional<uint32_t> inputHistoryPruneThrough(std::vector<uint32_t> frames, size_t keep) {
+    if (frames.size() <= keep) {
+        return std::nullopt;
+    }
+    const auto newestDropped = frames.begin() + (frames.size() - keep - 1);
+    std::nth_element(frames.begin(), newestDropped, frames.end());
+    return *newestDropped;
+}
+
+std::optional<size_t> applyInputAcks(std::vector<uint32_t>& acked, const std::vector<uint32_t>& ackFrame,
+                                     const std::vector<uint32_t>& prunedThrough) {
+    std::optional<size_t> lost;
+    for (size_t i = 0; i < ackFrame.size() && i < acked.size(); ++i) {
+        if (i < prunedThrough.size() && acked[i] < prunedThrough[i] && ackFrame[i] < prunedThrough[i]) {
+            if (!lost) {
+                lost = i;
             }
+            continue;
         }
-        else if constexpr (std::is_same_v<T, PlayersStatusPayload>) {
-            size += 1; // numPlayers
-            size += maxPlayers * 2; // averagePing[]
-        }
-        else if constexpr (std::is_same_v<T, KickPayload>) {
-            size += 2 + 4; // reason + param1
-        }
-        else if constexpr (std::is_same_v<T, ChecksumAckPayload>) {
-            size += 4; // ackFrame
-        }
-        else if constexpr (std::is_same_v<T, PlayersConfigurationDataPayload>) {
-            size += 1; // numPlayers
-            size += maxPlayers * 4; // config data
-        }
-        else if constexpr (std::is_same_v<T, PlayerDisconnectedPayload>) {
-            size += 1 + 1 + 4 + 2; // playerIndex + shouldAI + AIFrame + arrayIndex
-        }
-        else if constexpr (std::is_same_v<T, ChangePortPayload>) {
-            size += 2; // port
+        if (ackFrame[i] && acked[i] < ackFrame[i]) {
+            acked[i] = ackFrame[i];
         }
-        // else: std::monostate for StartGame, which has no payload
-    }, payload);
-    
-    // Now create the buffer and serialize
-    std::vector<uint8_t> buffer(size);
-    size_t offset = 0;
+    }
+    return lost;
+}
+
+std::vector<uint8_t> serializeServerMessage(const ServerHeader& header, 
+                                           const ServerMessageVariant& payload,
+                                           int maxPlayers,
+                                           size_t inputWidth) {
+    if (inputWidth == 0 || inputWidth > MAX_INPUT_WIDTH) {
+        throw std::invalid_argument("serializeServerMessage: unsupported input width " + std::to_string(inputWidth));
+    }
+    if (payloadMessageType(payload) != header.type) {
+        throw std::invalid_argument("serializeServerMessage: payload does not match message type " +
+                                    std::to_string(static_cast<int>(header.type)));
+    }
+
+    PacketWriter writer;
+    writer.reserve(64);
     
     // Write header
-    buffer[offset++] = static_cast<uint8_t>(header.type);
-    writeLittleEndian<uint32_t>(buffer, offset, header.sequence);
-    offset += 4;
+    writer.u8(static_cast<uint8_t>(header.type));
+    writer.u32le(header.sequence);
     
     // Write payload
-    std::visit([&buffer, &offset, maxPlayers](auto&& arg) {
+    std::visit([&writer, maxPlayers, inputWidth](auto&& arg) {
         using T = std::decay_t<decltype(arg)>;
         
         if constexpr (std::is_same_v<T, NewConnectionReplyPayload>) {
             const auto& p = arg;
-            buffer[offset++] = p.success;
-            buffer[offset++] = p.matchNumPlayers;
-            buffer[offset++] = p.playerIndex;
-            writeLittleEndian<uint32_t>(buffer, offset, p.matchDurationInFrames);
-            offset += 4;
-            buffer[offset++] = 0;
-            buffer[offset++] = p.isValidationServerDebugMode;
+            writer.u8(p.success);
+            writer.u8(p.matchNumPlayers);
+            writer.u8(p.playerIndex);
+            writer.u32le(p.matchDurationInFrames);
+            writer.u8(0);
+            writer.u8(p.isValidationServerDebugMode);
+            if (p.sessionNonce) {
+                writer.u32le(*p.sessionNonce);
+            }
         }
         else if constexpr (std::is_same_v<T, InputAckPayload>) {
-            writeLittleEndian<uint32_t>(buffer, offset, arg.ackFrame);
-            offset += 4;
+            writer.u32le(arg.ackFrame);
         }
         else if constexpr (std::is_same_v<T, PlayerInputPayload>) {
             const auto& p = arg;
-            buffer[offset++] = p.numPlayers;
+            writer.u8(p.numPlayers);
             
             // StartFrame[]
             for (int i = 0; i < maxPlayers; ++i) {
-                uint32_t sf = (i < static_cast<int>(p.startFrame.size())) ? p.startFrame[i] : 0;
-                writeLittleEndian<uint32_t>(buffer, offset, sf);
-                offset += 4;
+                writer.u32le((i < static_cast<int>(p.startFrame.size())) ? p.startFrame[i] : 0);
             }
             
             // NumFrames[]
             for (int i = 0; i < maxPlayers; ++i) {
-                uint8_t nf = (i < static_cast<int>(p.numFrames.size())) ? p.numFrames[i] : 0;
-                buffer[offset++] = nf;
+                writer.u8((i < static_cast<int>(p.numFrames.size())) ? p.numFrames[i] : 0);
             }
             
             // Overrides
-            writeLittleEndian<uint16_t>(buffer, offset, p.numPredictedOverrides);
-            offset += 2;
-            writeLittleEndian<uint16_t>(buffer, offset, p.numZeroedOverrides);
-            offset += 2;
+            writer.u16le(p.numPredictedOverrides);
+            writer.u16le(p.numZeroedOverrides);
             
             // Ping, PacketsLossPercent, Rift
-            writeLittleEndian<int16_t>(buffer, offset, p.ping);
-            offset += 2;
-            writeLittleEndian<int16_t>(buffer, offset, p.packetsLossPercent);
-            offset += 2;
+            writer.i16le(p.ping);
+            writer.i16le(p.packetsLossPercent);
             
-            // Convert rift to int16 with 2 decimal places of precision
-            int16_t riftInt = static_cast<int16_t>(p.rift * 100);
-            writeLittleEndian<int16_t>(buffer, offset, riftInt);
-            offset += 2;
+            // Rift as int16 hundredths of a frame, saturated so an out-of-range value
+            // can't wrap around and tell the client the opposite direction
+            const float rift = std::clamp(p.rift * 100.0f, static_cast<float>(INT16_MIN), static_cast<float>(INT16_MAX));
+            writer.i16le(static_cast<int16_t>(rift));
             
             // ChecksumAckFrame
-            writeLittleEndian<uint32_t>(buffer, offset, p.checksumAckFrame);
-            offset += 4;
+            writer.u32le(p.checksumAckFrame);
             
             // InputPerFrame[][]
             for (int pi = 0; pi < maxPlayers; ++pi) {
-                const auto& arr = (pi < static_cast<int>(p.inputPerFrame.size())) ? p.inputPerFrame[pi] : std::vector<uint32_t>{};
+                const auto& arr = (pi < static_cast<int>(p.inputPerFrame.size())) ? p.inputPerFrame[pi] : std::vector<FrameInput>{};
                 uint8_t numFrames = (pi < static_cast<int>(p.numFrames.size())) ? p.numFrames[pi] : 0;
                 
                 for (uint8_t f = 0; f < numFrames; ++f) {
-                    uint32_t v = (f < arr.size()) ? arr[f] : 0;
-                    writeLittleEndian<uint32_t>(buffer, offset, v);
-                    offset += 4;
+                    writer.input((f < arr.size()) ? arr[f] : FrameInput{}, inputWidth);
                 }
             }
         }
         else if constexpr (std::is_same_v<T, RequestQualityDataPayload>) {
-            writeLittleEndian<int16_t>(buffer, offset, arg.ping);
-            offset += 2;
-            writeLittleEndian<int16_t>(buffer, offset, arg.packetsLossPercent);
-            offset += 2;
+            writer.i16le(arg.ping);
+            writer.i16le(arg.packetsLossPercent);
         }
         else if constexpr (std::is_same_v<T, PlayersStatusPayload>) {
             const auto& p = arg;
-            buffer[offset++] = p.numPlayers;
+            writer.u8(p.numPlayers);
             
             for (int i = 0; i < maxPlayers; ++i) {
-                int16_t avg = (i < static_cast<int>(p.status.size())) ? p.status[i].averagePing : 0;
-                writeLittleEndian<int16_t>(buffer, offset, avg);
-                offset += 2;
+                writer.i16le((i < static_cast<int>(p.status.size())) ? p.status[i].averagePing : 0);
             }
         }
         else if constexpr (std::is_same_v<T, KickPayload>) {
-            writeLittleEndian<uint16_t>(buffer, offset, arg.reason);
-            offset += 2;
-            writeLittleEndian<uint32_t>(buffer, offset, arg.param1);
-            offset += 4;
+            writer.u16le(arg.reason);
+            writer.u32le(arg.param1);
         }
         else if constexpr (std::is_same_v<T, ChecksumAckPayload>) {
-            writeLittleEndian<uint32_t>(buffer, offset, arg.ackFrame);
-            offset += 4;
+            writer.u32le(arg.ackFrame);
         }
         else if constexpr (std::is_same_v<T, PlayersConfigurationDataPayload>) {
             const auto& p = arg;
-            buffer[offset++] = p.numPlayers;
+            writer.u8(p.numPlayers);
             
             for (int i = 0; i < maxPlayers; ++i) {
-                uint16_t value = PlayerConfigValues[i % PlayerConfigValues.size()];
-                writeLittleEndian<uint16_t>(buffer, offset, value);
-                offset += 2;
+                writer.u16le((i < static_cast<int>(p.configValues.size())) ? p.configValues[i] : 0);
             }
         }
         else if constexpr (std::is_same_v<T, PlayerDisconnectedPayload>) {
             const auto& p = arg;
-            buffer[offset++] = p.playerIndex;
-            buffer[offset++] = p.shouldAITakeControl;
-            writeLittleEndian<uint32_t>(buffer, offset, p.AITakeControlFrame);
-            offset += 4;
-            writeLittleEndian<uint16_t>(buffer, offset, p.playerDisconnectedArrayIndex);
-            offset += 2;
+            writer.u8(p.playerIndex);
+            writer.u8(p.shouldAITakeControl);
+            writer.u32le(p.AITakeControlFrame);
+            writer.u16le(p.playerDisconnectedArrayIndex);
         }
         else if constexpr (std::is_same_v<T, ChangePortPayload>) {
-            writeLittleEndian<uint16_t>(buffer, offset, arg.port);
-            offset += 2;
+            writer.u16le(arg.port);
         }
         // else: std::monostate for StartGame, which has no payload
     }, payload);
-    
-    // Resize if needed (shouldn't be, but just in case)
-    if (offset < buffer.size()) {
-        buffer.resize(offset);
+
+    // The client reads fixed offsets, so a wrong size is misparsed rather than rejected
+    size_t expected = expectedServerMessageSize(header.type, maxPlayers).value_or(0);
+    if (const auto* p = std::get_if<PlayerInputPayload>(&payload)) {
+        for (int i = 0; i < maxPlayers && i < static_cast<int>(p->numFrames.size()); ++i) {
+            expected += p->numFrames[i] * inputWidth;
+        }
+    } else if (const auto* reply = std::get_if<NewConnectionReplyPayload>(&payload); reply && reply->sessionNonce) {
+        expected += SESSION_NONCE_SIZE;
     }
-    
-    return buffer;
+    assert(writer.size() == expected);
+    if (writer.size() != expected) {
+        logWarn({}, "Serialized message type ", static_cast<int>(header.type), " is ", writer.size(),
+                " bytes, expected ", expected, " for ", maxPlayers, " players");
+    }
+
+    return writer.take();
+}
+
+std::optional<ServerMessageComplete> parseServerMessage(std::span<const uint8_t> buffer, int maxPlayers,
+                                                        size_t inputWidth, uint16_t capabilities) {
+    const size_t HEADER_SIZE = 5; // type:uint8 + sequence:uint32LE
+
+    if (buffer.size() < HEADER_SIZE || maxPlayers < 0 || inputWidth == 0 || inputWidth > MAX_INPUT_WIDTH) {
+        return std::nullopt;
+    }
+
+    PacketReader reader(buffer);
+
+    ServerHeader header;
+    header.type = static_cast<ServerMessageType>(reader.u8());
+    header.sequence = reader.u32le();
+
+    ServerMessageComplete result;
+    result.header = header;
+
+    switch (header.type) {
+        case ServerMessageType::NewConnectionReply: {
+            if (!reader.has(9)) return std::nullopt;
+            NewConnectionReplyPayload payload;
+            payload.success = reader.u8();
+            payload.matchNumPlayers = reader.u8();
+            payload.playerIndex = reader.u8();
+            payload.matchDurationInFrames = reader.u32le();
+            payload.unknown = reader.u8();
+            payload.isValidationServerDebugMode = reader.u8();
+            if (capabilities & CAPABILITY_SESSION_NONCE) {
+                if (!reader.has(SESSION_NONCE_SIZE)) return std::nullopt;
+                payload.sessionNonce = reader.u32le();
+            }
+            result.payload = payload;
+            break;
+        }
+        case ServerMessageType::StartGame: {
+            result.payload = std::monostate{};
+            break;
+        }
+        case ServerMessageType::InputAck: {
+            if (!reader.has(4)) return std::nullopt;
+            result.payload = InputAckPayload{ reader.u32le() };
+            break;
+        }
+        case ServerMessageType::PlayerInput: {
+            const size_t fixedSize = 1 + static_cast<size_t>(maxPlayers) * 5 + 2 + 2 + 2 + 2 + 2 + 4;
+            if (!reader.has(fixedSize)) return std::nullopt;
+            PlayerInputPayload payload;
+            payload.numPlayers = reader.u8();
+            for (int i = 0; i < maxPlayers; ++i) {
+                payload.startFrame.push_back(reader.u32le());
+            }
+            for (int i = 0; i < maxPlayers; ++i) {
+                payload.numFrames.push_back(reader.u8());
+            }
+            payload.numPredictedOverrides = reader.u16le();
+            payload.numZeroedOverrides = reader.u16le();
+            payload.ping = reader.i16le();
+            payload.packetsLossPercent = reader.i16le();
+            payload.rift = reader.i16le() / 100.0f;
+            payload.checksumAckFrame = reader.u32le();
+
+            payload.inputPerFrame.resize(maxPlayers);
+            for (int pi = 0; pi < maxPlayers; ++pi) {
+                if (!reader.has(payload.numFrames[pi] * inputWidth)) return std::nullopt;
+                for (uint8_t f = 0; f < payload.numFrames[pi]; ++f) {
+                    payload.inputPerFrame[pi].push_back(reader.input(inputWidth));
+                }
+            }
+            result.payload = payload;
+            break;
+        }
+        case ServerMessageType::RequestQualityData: {
+            if (!reader.has(4)) return std::nullopt;
+            RequestQualityDataPayload payload;
+            payload.ping = reader.i16le();
+            payload.packetsLossPercent = reader.i16le();
+            result.payload = payload;
+            break;
+        }
+        case ServerMessageType::PlayersStatus: {
+            if (!reader.has(1 + static_cast<size_t>(maxPlayers) * 2)) return std::nullopt;
+            PlayersStatusPayload payload;
+            payload.numPlayers = reader.u8();
+            for (int i = 0; i < maxPlayers; ++i) {
+                payload.status.push_back({ reader.i16le() });
+            }
+            result.payload = payload;
+            break;
+        }
+        case ServerMessageType::Kick: {
+            if (!reader.has(6)) return std::nullopt;
+            KickPayload payload;
+            payload.reason = reader.u16le();
+            payload.param1 = reader.u32le();
+            result.payload = payload;
+            break;
+        }
+        case ServerMessageType::ChecksumAck: {
+            if (!reader.has(4)) return std::nullopt;
+            result.payload = ChecksumAckPayload{ reader.u32le() };
+            break;
+        }
+        case ServerMessageType::PlayersConfigurationData: {
+            if (!reader.has(1 + static_cast<size_t>(maxPlayers) * 2)) return std::nullopt;
+            PlayersConfigurationDataPayload payload;
+            payload.numPlayers = reader.u8();
+            for (int i = 0; i < maxPlayers; ++i) {
+                payload.configValues.push_back(reader.u16le());
+            }
+            result.payload = payload;
+            break;
+        }
+        case ServerMessageType::PlayerDisconnected: {
+            if (!reader.has(8)) return std::nullopt;
+            PlayerDisconnectedPayload payload;
+            payload.playerIndex = reader.u8();
+            payload.shouldAITakeControl = reader.u8();
+            payload.AITakeControlFrame = reader.u32le();
+            payload.playerDisconnectedArrayIndex = reader.u16le();
+            result.payload = payload;
+            break;
+        }
+        case ServerMessageType::ChangePort: {
+            if (!reader.has(2)) return std::nullopt;
+            result.payload = ChangePortPayload{ reader.u16le() };
+            break;
+        }
+        default:
+            return std::nullopt; // Unknown message type
+    }
+
+    return result;
+}
+
+std::vector<uint8_t> serializeBatch(const std::vector<std::vector<uint8_t>>& messages) {
+    PacketWriter writer;
+    writer.u8(static_cast<uint8_t>(ServerMessageType::Batch));
+    for (const auto& message : messages) {
+        if (message.empty() || message.size() > UINT16_MAX) {
+            throw std::invalid_argument("serializeBatch: message size out of range");
+        }
+        writer.u16le(static_cast<uint16_t>(message.size()));
+        writer.bytes(message);
+    }
+    return writer.take();
+}
+
+std::optional<std::vector<ServerMessageComplete>> parseServerMessages(std::span<const uint8_t> buffer, int maxPlayers,
+                                                                      size_t inputWidth, uint16_t capabilities) {
+    std::vector<ServerMessageComplete> messages;
+    if (buffer.empty() || buffer[0] != static_cast<uint8_t>(ServerMessageType::Batch)) {
+        auto single = parseServerMessage(buffer, maxPlayers, inputWidth, capabilities);
+        if (!single) return std::nullopt;
+        messages.push_back(std::move(*single));
+        return messages;
+    }
+
+    PacketReader reader(buffer.subspan(1));
+    while (reader.remaining() > 0) {
+        const size_t length = reader.u16le(); // zero-filled past the end
+        if (length == 0) break; // zero padding out to the end of the last mask group
+        if (!reader.has(length)) return std::nullopt;
+        auto message = parseServerMessage(buffer.subspan(1 + reader.offset(), length), maxPlayers, inputWidth, capabilities);
+        if (!message) return std::nullopt;
+        messages.push_back(std::move(*message));
+        reader.skip(length);
+    }
+    return messages;
+}
+
+bool takeSessionNonce(std::vector<uint8_t>& packet, uint32_t nonce) {
+    const size_t HEADER_SIZE = 5; // type:uint8 + sequence:uint32LE
+    if (packet.size() < HEADER_SIZE + SESSION_NONCE_SIZE) {
+        return false;
+    }
+    PacketReader reader(std::span<const uint8_t>(packet).subspan(HEADER_SIZE));
+    if (reader.u32le() != nonce) {
+        return false;
+    }
+    packet.erase(packet.begin() + HEADER_SIZE, packet.begin() + HEADER_SIZE + SESSION_NONCE_SIZE);
+    return true;
 }
 
 } // namespace rollback
\ No newline at end of file
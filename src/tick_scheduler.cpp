@@ -0,0 +1,73 @@
+#include "tick_scheduler.h"
+#include <thread>
+
+namespace rollback {
+
+namespace {
+
+// How long before the deadline to stop sleeping and start spinning
+#ifdef _WIN32
+constexpr auto SPIN_MARGIN = std::chrono::milliseconds(1);
+#else
+constexpr auto SPIN_MARGIN = std::chrono::microseconds(0);
+#endif
+
+// How often a wait on a virtual clock checks whether it has been moved past the deadline
+constexpr auto VIRTUAL_POLL_INTERVAL = std::chrono::milliseconds(1);
+
+} // namespace
+
+TickScheduler::TickScheduler(std::chrono::nanoseconds interval, std::shared_ptr<const Clock> clock)
+    : interval_(interval),
+      clock_(clock ? std::move(clock) : std::make_shared<SteadyClock>()),
+      start_(clock_->now()) {
+}
+
+Clock::time_point TickScheduler::advance(Clock::time_point now) {
+    ++ticks_;
+    const auto elapsed = interval_ * static_cast<int64_t>(ticks_);
+    auto deadline = start_ + elapsed;
+    if (now - deadline > interval_ * MAX_LAG_TICKS) {
+        // Re-anchor so this tick is due now and the ones after keep their spacing
+        start_ = now - elapsed;
+        deadline = now;
+    }
+    return deadline;
+}
+
+asio::awaitable<void> TickScheduler::waitForNextTick() {
+    const auto now = clock_->now();
+    const auto deadline = advance(now);
+    if (deadline <= now) {
+        co_return; // behind schedule: run the tick straight away
+    }
+
+    if (!clock_->realTime()) {
+        // Real time says nothing about when a virtual clock gets there, and spinning
+        // on it would hog the thread its driver may need
+        asio::steady_timer timer(co_await asio::this_coro::executor);
+        while (clock_->now() < deadline) {
+            timer.expires_after(VIRTUAL_POLL_INTERVAL);
+            co_await timer.async_wait(asio::use_awaitable);
+        }
+        co_return;
+    }
+
+    const auto waitTime = deadline - now;
+    if (waitTime > SPIN_MARGIN) {
+        asio::steady_timer timer(co_await asio::this_coro::executor);
+        timer.expires_after(waitTime - SPIN_MARGIN);
+        co_await timer.async_wait(asio::use_awaitable);
+    }
+
+    while (clock_->now() < deadline) {
+        std::this_thread::yield();
+    }
+
+    const auto timerError = clock_->now() - deadline;
+    if (std::chrono::abs(timerError) > std::chrono::abs(maxDrift_)) {
+        maxDrift_ = timerError;
+    }
+}
+
+} // namespace rollback
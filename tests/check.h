@@ -0,0 +1,43 @@
+#pragma once
+
+// Just enough of a test framework for the server and its pure parts: each test file
+// is its own executable, registered with ctest, that fails if any CHECK does.
+#include <cstdlib>
+#include <iostream>
+
+namespace rollback::test {
+
+inline int& failures() {
+    static int count = 0;
+    return count;
+}
+
+inline int finish(const char* suite) {
+    if (failures() == 0) {
+        std::cout << suite << ": all checks passed" << std::endl;
+        return EXIT_SUCCESS;
+    }
+    std::cerr << suite << ": " << failures() << " check(s) failed" << std::endl;
+    return EXIT_FAILURE;
+}
+
+} // namespace rollback::test
+
+#define CHECK(cond)                                                                         \
+    do {                                                                                    \
+        if (!(cond)) {                                                                      \
+            ++rollback::test::failures();                                                   \
+            std::cerr << __FILE__ << ":" << __LINE__ << ": CHECK(" #cond ") failed" << std::endl; \
+        }                                                                                   \
+    } while (0)
+
+#define CHECK_EQ(a, b)                                                                      \
+    do {                                                                                    \
+        const auto& checkA_ = (a);                                                          \
+        const auto& checkB_ = (b);                                                          \
+        if (!(checkA_ == checkB_)) {                                                        \
+            ++rollback::test::failures();                                                   \
+            std::cerr << __FILE__ << ":" << __LINE__ << ": CHECK_EQ(" #a ", " #b ") failed: " \
+                      << checkA_ << " != " << checkB_ << std::endl;                         \
+        }                                                                                   \
+    } while (0)
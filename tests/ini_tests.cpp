@@ -0,0 +1,50 @@
+// settings.ini parsing
+#include "check.h"
+#include "ini.h"
+#include <sstream>
+
+using namespace rollback;
+
+namespace {
+
+void parsesKeysQuotesAndComments() {
+    std::istringstream in(
+        "; the game's own settings\n"
+        "[Server]\n"
+        "bDomain = \"https://mvsi.example.com\"\n"
+        "port=41300\n"
+        "# a comment\n"
+        "\n"
+        "log_level = 'debug'\n"
+        "no equals sign here\n"
+        " tick_rate =  60 \n"
+        "port=41301\n");
+    const auto values = parseIni(in);
+    CHECK_EQ(values.size(), size_t{ 4 });
+    CHECK_EQ(values.at("bDomain"), std::string("https://mvsi.example.com"));
+    CHECK_EQ(values.at("log_level"), std::string("debug"));
+    CHECK_EQ(values.at("tick_rate"), std::string("60"));
+    CHECK_EQ(values.at("port"), std::string("41301")); // later duplicates win
+    CHECK(!values.contains("no equals sign here"));
+}
+
+void keepsUnmatchedQuotes() {
+    std::istringstream in("a=\"open\nb='\n=orphan\n");
+    const auto values = parseIni(in);
+    CHECK_EQ(values.at("a"), std::string("\"open"));
+    CHECK_EQ(values.at("b"), std::string("'"));
+    CHECK_EQ(values.size(), size_t{ 2 });
+}
+
+void missingFile() {
+    CHECK(!loadIniFile("/nonexistent/settings.ini").has_value());
+}
+
+} // namespace
+
+int main() {
+    parsesKeysQuotesAndComments();
+    keepsUnmatchedQuotes();
+    missingFile();
+    return test::finish("ini_tests");
+}
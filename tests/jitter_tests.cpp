@@ -0,0 +1,49 @@
+// Jitter estimate and the adaptive input buffer depth derived from it
+#include "check.h"
+#include "jitter.h"
+#include <vector>
+
+using namespace rollback;
+
+namespace {
+
+JitterState feed(const std::vector<float>& intervalsMs) {
+    JitterState state;
+    for (float interval : intervalsMs) {
+        state = updateJitter(state, interval);
+    }
+    return state;
+}
+
+void steadyArrivalsNeedNoBuffer() {
+    const auto steady = feed(std::vector<float>(120, 16.7f));
+    CHECK(steady.jitterMs < 0.5f);
+    CHECK_EQ(inputBufferFrames(steady.jitterMs), uint32_t{ 0 });
+}
+
+void jitteryArrivalsGrowTheBuffer() {
+    // Same average rate, but packets bunch up and then arrive late
+    std::vector<float> bursty;
+    for (int i = 0; i < 60; ++i) {
+        bursty.push_back(2.0f);
+        bursty.push_back(31.4f);
+    }
+    const auto steady = feed(std::vector<float>(120, 16.7f));
+    const auto jittery = feed(bursty);
+    CHECK(jittery.jitterMs > steady.jitterMs);
+    CHECK(inputBufferFrames(jittery.jitterMs) > inputBufferFrames(steady.jitterMs));
+}
+
+void bufferIsClamped() {
+    CHECK_EQ(inputBufferFrames(1000.0f, 4), uint32_t{ 4 });
+    CHECK_EQ(inputBufferFrames(-5.0f), uint32_t{ 0 });
+}
+
+} // namespace
+
+int main() {
+    steadyArrivalsNeedNoBuffer();
+    jitteryArrivalsGrowTheBuffer();
+    bufferIsClamped();
+    return test::finish("jitter_tests");
+}
@@ -0,0 +1,60 @@
+// Logging setup: initLogging's file output, and what calling it again does
+#include "check.h"
+#include "logging.h"
+#include <filesystem>
+#include <fstream>
+#include <string>
+#include <vector>
+
+using namespace rollback;
+
+namespace {
+
+std::vector<std::string> linesOf(const std::string& path) {
+    std::ifstream in(path);
+    std::vector<std::string> lines;
+    for (std::string line; std::getline(in, line);) {
+        lines.push_back(line);
+    }
+    return lines;
+}
+
+void logsToFile() {
+    const auto path = (std::filesystem::temp_directory_path() / "logging_tests.log").string();
+    std::filesystem::remove(path);
+
+    CHECK(initLogging(LogLevel::Info, path));
+    logInfo({ "m1", 2, {} }, "first ", 1);
+    logDebug({}, "below the level");
+
+    // Again with the same settings: same file, nothing lost, nothing written twice
+    CHECK(initLogging(LogLevel::Info, path));
+    logWarn({}, "second");
+
+    auto lines = linesOf(path);
+    CHECK_EQ(lines.size(), size_t{ 2 });
+    if (lines.size() == 2) {
+        CHECK(lines[0].find("level=info match_id=\"m1\" player_index=2 msg=\"first 1\"") != std::string::npos);
+        CHECK(lines[1].find("level=warn msg=\"second\"") != std::string::npos);
+    }
+
+    // Back to the console: the file gets nothing more
+    CHECK(initLogging(LogLevel::Error, {}));
+    logError({}, "to stderr");
+    CHECK_EQ(linesOf(path).size(), size_t{ 2 });
+    std::filesystem::remove(path);
+}
+
+void unopenableFile() {
+    const auto missingDir = std::filesystem::temp_directory_path() / "logging_tests_missing" / "x.log";
+    CHECK(!initLogging(LogLevel::Info, missingDir.string()));
+    CHECK(isLogEnabled(LogLevel::Info));
+}
+
+} // namespace
+
+int main() {
+    logsToFile();
+    unopenableFile();
+    return test::finish("logging_tests");
+}
@@ -0,0 +1,43 @@
+// MatchResult tallying: which team the clients' reports settle on, if any
+#include "check.h"
+#include "match_result.h"
+
+using namespace rollback;
+
+namespace {
+
+void majorityOfThree() {
+    // Two clients agree, the third (cheating or desynced) names the other team
+    const auto outcome = tallyMatchResult({ { 0, 1 }, { 1, 1 }, { 2, 0 } }, 3);
+    CHECK(outcome.status == MatchResultStatus::Decided);
+    CHECK_EQ(outcome.winning_team_index, uint8_t{ 1 });
+    CHECK_EQ(outcome.reports, uint8_t{ 3 });
+}
+
+void evenSplitIsDisputed() {
+    const auto outcome = tallyMatchResult({ { 0, 0 }, { 1, 0 }, { 2, 1 }, { 3, 1 } }, 4);
+    CHECK(outcome.status == MatchResultStatus::Disputed);
+    CHECK_EQ(outcome.reports, uint8_t{ 4 });
+}
+
+void loneReportIsNoMajority() {
+    // One client of two can't settle the match on its own
+    const auto outcome = tallyMatchResult({ { 0, 1 } }, 2);
+    CHECK(outcome.status == MatchResultStatus::Disputed);
+}
+
+void noReports() {
+    const auto outcome = tallyMatchResult({}, 2);
+    CHECK(outcome.status == MatchResultStatus::Unreported);
+    CHECK_EQ(outcome.reports, uint8_t{ 0 });
+}
+
+} // namespace
+
+int main() {
+    majorityOfThree();
+    evenSplitIsDisputed();
+    loneReportIsNoMajority();
+    noReports();
+    return test::finish("match_result_tests");
+}
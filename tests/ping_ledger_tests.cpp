@@ -0,0 +1,81 @@
+// Pending pings: each one yields a single round-trip sample, however many replies echo it
+#include "check.h"
+#include "ping_ledger.h"
+#include <atomic>
+#include <chrono>
+#include <thread>
+#include <vector>
+
+using namespace rollback;
+
+namespace {
+
+const auto SENT = std::chrono::steady_clock::time_point{} + std::chrono::hours(1);
+
+// What recordRtt does with each reply: count a sample only when it claims one
+struct Samples {
+    PendingPings pending;
+    uint32_t pingSamples = 0;
+    std::optional<int16_t> last;
+
+    void reply(uint32_t sequence, std::chrono::steady_clock::time_point now) {
+        const auto sample = takePingSample(pending, sequence, now);
+        if (sample) {
+            ++pingSamples;
+            last = sample;
+        }
+    }
+};
+
+void qualityDataThenAck() {
+    Samples samples;
+    samples.pending.insert_or_assign(7, SENT);
+
+    samples.reply(7, SENT + std::chrono::milliseconds(40)); // QualityData
+    samples.reply(7, SENT + std::chrono::milliseconds(45)); // input ack echoing the same sequence
+    CHECK_EQ(samples.pingSamples, uint32_t{ 1 });
+    CHECK(samples.last == std::optional<int16_t>(40));
+    CHECK_EQ(samples.pending.size(), size_t{ 0 });
+
+    // Replies to a sequence never filed count for nothing
+    samples.reply(8, SENT);
+    CHECK_EQ(samples.pingSamples, uint32_t{ 1 });
+}
+
+void cappedRoundTrip() {
+    PendingPings pending;
+    pending.insert_or_assign(1, SENT);
+    CHECK(takePingSample(pending, 1, SENT + std::chrono::seconds(10)) == std::optional<int16_t>(255));
+    pending.insert_or_assign(2, SENT);
+    CHECK(takePingSample(pending, 2, SENT - std::chrono::milliseconds(5)) == std::optional<int16_t>(-1));
+}
+
+void racingRepliesClaimOnce() {
+    // The two replies arrive on different strands, so they can race for the same entry
+    for (int round = 0; round < 200; ++round) {
+        PendingPings pending;
+        pending.insert_or_assign(3, SENT);
+        std::atomic<int> claimed{ 0 };
+        std::vector<std::thread> replies;
+        for (int i = 0; i < 2; ++i) {
+            replies.emplace_back([&] {
+                if (takePingSample(pending, 3, SENT + std::chrono::milliseconds(10))) {
+                    ++claimed;
+                }
+            });
+        }
+        for (auto& reply : replies) {
+            reply.join();
+        }
+        CHECK_EQ(claimed.load(), 1);
+    }
+}
+
+} // namespace
+
+int main() {
+    qualityDataThenAck();
+    cappedRoundTrip();
+    racingRepliesClaimOnce();
+    return test::finish("ping_ledger_tests");
+}
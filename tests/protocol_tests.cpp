@@ -0,0 +1,523 @@
+// Wire format: packet field helpers, server message sizes and PlayerInput frame budgets,
+// client message parsing and the zero-suppression codec. Pure functions only, no sockets.
+#include "check.h"
+#include "compression.h"
+#include "packet_io.h"
+#include "serialization.h"
+#include <map>
+#include <string>
+#include <vector>
+
+using namespace rollback;
+
+namespace {
+
+// An input whose every byte is distinct, so a dropped or reordered byte shows up
+FrameInput wideInput(uint8_t seed, size_t width) {
+    FrameInput input;
+    for (size_t i = 0; i < width; ++i) {
+        input.bytes[i] = static_cast<uint8_t>(seed + i * 17);
+    }
+    return input;
+}
+
+PlayerInputPayload playerInputPayload(int maxPlayers, size_t inputWidth, uint8_t framesEach) {
+    PlayerInputPayload payload{};
+    payload.numPlayers = static_cast<uint8_t>(maxPlayers);
+    payload.startFrame.assign(maxPlayers, 100);
+    payload.numFrames.assign(maxPlayers, framesEach);
+    payload.inputPerFrame.resize(maxPlayers);
+    for (int p = 0; p < maxPlayers; ++p) {
+        for (uint8_t f = 0; f < framesEach; ++f) {
+            payload.inputPerFrame[p].push_back(wideInput(static_cast<uint8_t>(p * 50 + f), inputWidth));
+        }
+    }
+    payload.ping = 42;
+    payload.packetsLossPercent = 3;
+    payload.rift = -1.25f;
+    return payload;
+}
+
+std::vector<uint8_t> inputPacket(uint32_t startFrame, uint8_t numFrames, const std::vector<uint8_t>& body) {
+    PacketWriter writer;
+    writer.u8(static_cast<uint8_t>(ClientMessageType::Input));
+    writer.u32le(7);
+    writer.u32le(startFrame);
+    writer.u32le(startFrame + numFrames - 1);
+    writer.u8(numFrames);
+    writer.u8(0);
+    writer.bytes(body);
+    return writer.take();
+}
+
+void stringHelpers() {
+    // Shorter than the field: zero padded to exactly len
+    PacketWriter writer;
+    writer.stringFixed("abc", 5);
+    auto bytes = writer.take();
+    CHECK_EQ(bytes.size(), size_t{ 5 });
+    CHECK(bytes == (std::vector<uint8_t>{ 'a', 'b', 'c', 0, 0 }));
+
+    // Longer than the field: cut at len, no terminator
+    writer.stringFixed("abcdefgh", 5);
+    bytes = writer.take();
+    CHECK(bytes == (std::vector<uint8_t>{ 'a', 'b', 'c', 'd', 'e' }));
+
+    // Exactly len bytes reads back whole, and the reader consumes exactly len
+    writer.stringFixed("abcde", 5);
+    writer.u8(0xAA);
+    bytes = writer.take();
+    PacketReader reader(bytes);
+    std::string out;
+    CHECK(reader.stringFixed(5, out));
+    CHECK_EQ(out, std::string("abcde"));
+    CHECK_EQ(reader.u8(), uint8_t{ 0xAA });
+
+    // Text stops at the first zero, the rest of the field is still skipped
+    const std::vector<uint8_t> padded{ 'h', 'i', 0, 'x', 'y', 0x55 };
+    PacketReader paddedReader(padded);
+    CHECK(paddedReader.stringFixed(5, out));
+    CHECK_EQ(out, std::string("hi"));
+    CHECK_EQ(paddedReader.u8(), uint8_t{ 0x55 });
+
+    // A control character before the terminator is refused
+    const std::vector<uint8_t> control{ 'a', 0x07, 'b', 0, 0 };
+    PacketReader controlReader(control);
+    CHECK(!controlReader.stringFixed(5, out));
+
+    // So are bytes past ASCII, e.g. a stray UTF-8 lead byte that would break the JSON snapshot
+    const std::vector<uint8_t> highBit{ 'a', 0xC3, 'b', 0, 0 };
+    PacketReader highBitReader(highBit);
+    CHECK(!highBitReader.stringFixed(5, out));
+    const std::vector<uint8_t> del{ 'a', 0x7F, 0 };
+    PacketReader delReader(del);
+    CHECK(!delReader.stringFixed(3, out));
+    const std::vector<uint8_t> tilde{ 'a', '~', 0 };
+    PacketReader tildeReader(tilde);
+    CHECK(tildeReader.stringFixed(3, out));
+    CHECK_EQ(out, std::string("a~"));
+}
+
+void wideInputRoundTrip() {
+    // 8 bytes per frame, both directions
+    constexpr size_t width = 8;
+    const auto payload = playerInputPayload(2, width, 3);
+    const auto bytes = serializeServerMessage({ ServerMessageType::PlayerInput, 9 }, payload, 2, width);
+    const auto parsed = parseServerMessage(bytes, 2, width);
+    CHECK(parsed.has_value());
+    if (parsed) {
+        const auto& back = std::get<PlayerInputPayload>(parsed->payload);
+        CHECK(back.inputPerFrame == payload.inputPerFrame);
+        CHECK(back.startFrame == payload.startFrame);
+    }
+
+    std::vector<uint8_t> body;
+    for (uint8_t f = 0; f < 2; ++f) {
+        const auto input = wideInput(f, width);
+        body.insert(body.end(), input.bytes.begin(), input.bytes.begin() + width);
+    }
+    const auto message = parseClientMessage(inputPacket(10, 2, body), width);
+    CHECK(message.has_value());
+    if (message) {
+        const auto& input = std::get<InputPayload>(message->payload);
+        CHECK_EQ(input.inputPerFrame.size(), size_t{ 2 });
+        CHECK(input.inputPerFrame[1] == wideInput(1, width));
+    }
+}
+
+void serializedSizes() {
+    const int maxPlayers = 4;
+    const std::vector<std::pair<ServerMessageType, ServerMessageVariant>> messages{
+        { ServerMessageType::NewConnectionReply, NewConnectionReplyPayload{ 0, 2, 1, 3600, 0, 0, std::nullopt } },
+        { ServerMessageType::StartGame, std::monostate{} },
+        { ServerMessageType::InputAck, InputAckPayload{ 77 } },
+        { ServerMessageType::RequestQualityData, RequestQualityDataPayload{ 30, 1 } },
+        { ServerMessageType::PlayersStatus, PlayersStatusPayload{ maxPlayers, std::vector<PlayerStatusData>(maxPlayers) } },
+        { ServerMessageType::Kick, KickPayload{ 1, 1 } },
+        { ServerMessageType::ChecksumAck, ChecksumAckPayload{ 5 } },
+        { ServerMessageType::PlayersConfigurationData, PlayersConfigurationDataPayload{ maxPlayers, std::vector<uint16_t>(maxPlayers) } },
+        { ServerMessageType::PlayerDisconnected, PlayerDisconnectedPayload{ 1, 1, 500, 0 } },
+        { ServerMessageType::ChangePort, ChangePortPayload{ 41235 } },
+    };
+    for (const auto& [type, payload] : messages) {
+        const auto bytes = serializeServerMessage({ type, 1 }, payload, maxPlayers);
+        const auto expected = expectedServerMessageSize(type, maxPlayers);
+        CHECK(expected.has_value());
+        if (expected) {
+            CHECK_EQ(bytes.size(), *expected);
+        }
+    }
+
+    // PlayerInput adds its frames on top of the fixed part
+    const auto payload = playerInputPayload(maxPlayers, DEFAULT_INPUT_WIDTH, 2);
+    const auto bytes = serializeServerMessage({ ServerMessageType::PlayerInput, 1 }, payload, maxPlayers);
+    const auto fixed = expectedServerMessageSize(ServerMessageType::PlayerInput, maxPlayers);
+    CHECK(fixed.has_value());
+    if (fixed) {
+        CHECK_EQ(bytes.size(), *fixed + maxPlayers * 2 * DEFAULT_INPUT_WIDTH);
+    }
+
+    CHECK(!expectedServerMessageSize(static_cast<ServerMessageType>(5), maxPlayers).has_value());
+}
+
+// Serializes `payload`, parses it back and checks the header made it through
+template <typename Payload>
+std::optional<Payload> roundTrip(ServerMessageType type, const Payload& payload, int maxPlayers) {
+    const auto parsed = parseServerMessage(serializeServerMessage({ type, 12 }, payload, maxPlayers), maxPlayers);
+    CHECK(parsed.has_value());
+    if (!parsed) {
+        return std::nullopt;
+    }
+    CHECK(parsed->header.type == type);
+    CHECK_EQ(parsed->header.sequence, uint32_t{ 12 });
+    const auto* back = std::get_if<Payload>(&parsed->payload);
+    CHECK(back != nullptr);
+    return back ? std::optional<Payload>(*back) : std::nullopt;
+}
+
+void serverMessageRoundTrips() {
+    const int maxPlayers = 3;
+    if (const auto ack = roundTrip(ServerMessageType::InputAck, InputAckPayload{ 0xCAFEF00D }, maxPlayers)) {
+        CHECK_EQ(ack->ackFrame, uint32_t{ 0xCAFEF00D });
+    }
+    if (const auto ack = roundTrip(ServerMessageType::ChecksumAck, ChecksumAckPayload{ 123456 }, maxPlayers)) {
+        CHECK_EQ(ack->ackFrame, uint32_t{ 123456 });
+    }
+    if (const auto change = roundTrip(ServerMessageType::ChangePort, ChangePortPayload{ 41235 }, maxPlayers)) {
+        CHECK_EQ(change->port, uint16_t{ 41235 });
+    }
+
+    // Pings are signed: a negative one (no sample yet) survives the trip
+    const PlayersStatusPayload status{ maxPlayers, { { 42 }, { -1 }, { 255 } } };
+    if (const auto back = roundTrip(ServerMessageType::PlayersStatus, status, maxPlayers)) {
+        CHECK_EQ(back->numPlayers, uint8_t{ maxPlayers });
+        CHECK_EQ(back->status.size(), size_t{ maxPlayers });
+        for (size_t i = 0; i < back->status.size() && i < status.status.size(); ++i) {
+            CHECK_EQ(back->status[i].averagePing, status.status[i].averagePing);
+        }
+    }
+}
+
+void batchRoundTrip() {
+    const int maxPlayers = 2;
+    const auto batch = serializeBatch({
+        serializeServerMessage({ ServerMessageType::InputAck, 1 }, InputAckPayload{ 77 }, maxPlayers),
+        serializeServerMessage({ ServerMessageType::ChecksumAck, 2 }, ChecksumAckPayload{ 5 }, maxPlayers),
+    });
+
+    // Decompression pads out to the mask group; the zero length it reads there ends the batch
+    const auto received = decompressPacket(compressPacket(batch));
+    CHECK(received.size() > batch.size());
+    const auto parsed = parseServerMessages(received, maxPlayers);
+    CHECK(parsed.has_value());
+    if (parsed) {
+        CHECK_EQ(parsed->size(), size_t{ 2 });
+        if (parsed->size() == 2) {
+            CHECK((*parsed)[0].header.type == ServerMessageType::InputAck);
+            CHECK_EQ((*parsed)[0].header.sequence, uint32_t{ 1 });
+            CHECK_EQ(std::get<InputAckPayload>((*parsed)[0].payload).ackFrame, uint32_t{ 77 });
+            CHECK((*parsed)[1].header.type == ServerMessageType::ChecksumAck);
+            CHECK_EQ((*parsed)[1].header.sequence, uint32_t{ 2 });
+            CHECK_EQ(std::get<ChecksumAckPayload>((*parsed)[1].payload).ackFrame, uint32_t{ 5 });
+        }
+    }
+
+    // A last message cut short of its declared length spoils the whole batch
+    const std::vector<uint8_t> truncated(batch.begin(), batch.end() - 1);
+    CHECK(!parseServerMessages(truncated, maxPlayers).has_value());
+}
+
+void frameBudgetFits() {
+    // Incompressible data at the payload ceiling still makes one datagram, checksum included
+    const std::vector<uint8_t> worst(MAX_UNCOMPRESSED_PAYLOAD, 0xFF);
+    CHECK(compressPacket(worst, true).size() <= 1024);
+
+    // Every player at the catch-up budget fits that ceiling, whatever the match shape
+    for (int maxPlayers = 1; maxPlayers <= MAX_MATCH_PLAYERS; ++maxPlayers) {
+        for (size_t width = 1; width <= MAX_INPUT_WIDTH; ++width) {
+            const auto budget = playerInputFrameBudget(maxPlayers, width);
+            CHECK(budget.normalFrames >= 1);
+            CHECK(budget.normalFrames <= MAX_INPUTS_PER_FRAME);
+            CHECK(budget.catchUpFrames >= budget.normalFrames);
+            const auto payload = playerInputPayload(maxPlayers, width, budget.catchUpFrames);
+            const auto bytes = serializeServerMessage({ ServerMessageType::PlayerInput, 1 }, payload, maxPlayers, width);
+            CHECK(bytes.size() <= MAX_UNCOMPRESSED_PAYLOAD);
+        }
+    }
+}
+
+void runLengthCapped() {
+    // Thousands of contiguous frames still leave as one capped run
+    std::map<uint32_t, FrameInput> history;
+    for (uint32_t f = 1; f <= 5000; ++f) {
+        history.emplace(f, FrameInput(f & 0xFF));
+    }
+    CHECK_EQ(playerInputRunLength(history, 0, MAX_INPUTS_PER_FRAME), MAX_INPUTS_PER_FRAME);
+    CHECK_EQ(playerInputRunLength(history, 0, 255), uint8_t{ 255 });
+    const auto capped = playerInputFrameBudget(2, DEFAULT_INPUT_WIDTH, 8);
+    CHECK_EQ(playerInputRunLength(history, 0, capped.catchUpFrames), uint8_t{ 8 });
+    // Held back at lastFrame, and cut short by a gap
+    CHECK_EQ(playerInputRunLength(history, 0, 255, 10), uint8_t{ 10 });
+    history.erase(4003);
+    CHECK_EQ(playerInputRunLength(history, 3990, 255), uint8_t{ 12 });
+
+    // A run up to the last frame a uint32 names ends there instead of wrapping to 0
+    std::map<uint32_t, FrameInput> tail;
+    for (uint32_t f = UINT32_MAX - 9; f != 0; ++f) {
+        tail.emplace(f, FrameInput(1));
+    }
+    tail.emplace(0, FrameInput(1));
+    tail.emplace(1, FrameInput(1));
+    CHECK_EQ(playerInputRunLength(tail, UINT32_MAX - 10, 255), uint8_t{ 10 });
+    CHECK_EQ(playerInputRunLength(tail, UINT32_MAX, 255), uint8_t{ 0 });
+}
+
+void prunedHistoryThenStaleAck() {
+    // 200 frames of history, kept to the last INPUT_HISTORY_FRAMES: 1..50 go
+    std::vector<uint32_t> frames;
+    for (uint32_t frame = 200; frame >= 1; --frame) {
+        frames.push_back(frame);
+    }
+    const auto through = inputHistoryPruneThrough(frames);
+    CHECK(through == std::optional<uint32_t>(50));
+    CHECK(!inputHistoryPruneThrough(std::vector<uint32_t>(frames.begin(), frames.begin() + INPUT_HISTORY_FRAMES)).has_value());
+
+    // Player 0's history was pruned through 50, player 1's not at all
+    const std::vector<uint32_t> prunedThrough{ *through, 0 };
+
+    // A client that had acked past the pruned frames keeps going
+    std::vector<uint32_t> acked{ 60, 10 };
+    CHECK(!applyInputAcks(acked, { 70, 20 }, prunedThrough).has_value());
+    CHECK(acked == std::vector<uint32_t>({ 70, 20 }));
+
+    // One still short of them, and acking short of them again, has lost player 0's
+    // inputs for good; the acks it could use are still taken
+    acked = { 40, 10 };
+    CHECK(applyInputAcks(acked, { 45, 20 }, prunedThrough) == std::optional<size_t>(0));
+    CHECK(acked == std::vector<uint32_t>({ 40, 20 }));
+
+    // Catching up past the pruned frames in the same ack is fine
+    CHECK(!applyInputAcks(acked, { 55, 20 }, prunedThrough).has_value());
+    CHECK_EQ(acked[0], uint32_t{ 55 });
+
+    // An ack older than what's known never goes backwards, and 0 means no ack yet
+    CHECK(!applyInputAcks(acked, { 52, 0 }, prunedThrough).has_value());
+    CHECK(acked == std::vector<uint32_t>({ 55, 20 }));
+}
+
+void decodedLength() {
+    // Three masks decode to three groups of eight, not to the 1024-byte ceiling
+    const std::vector<uint8_t> short_{ 0x01, 0xAB, 0x00, 0x80, 0xCD };
+    const auto decoded = decompressPacket(short_);
+    CHECK_EQ(decoded.size(), size_t{ 24 });
+    CHECK_EQ(decoded[0], uint8_t{ 0xAB });
+    CHECK_EQ(decoded[23], uint8_t{ 0xCD });
+
+    // All-zero masks describing far more than a datagram stop at the limit
+    const std::vector<uint8_t> zeros(200, 0);
+    CHECK(decompressPacket(zeros).size() <= 1024);
+    CHECK_EQ(decompressPacket(zeros, 64).size(), size_t{ 64 });
+
+    // A round trip keeps the payload, padded with zeros to the group size
+    const std::vector<uint8_t> original{ 1, 0, 0, 2, 3, 0, 0, 0, 0, 4 };
+    const auto roundTrip = decompressPacket(compressPacket(original));
+    CHECK_EQ(roundTrip.size(), size_t{ 16 });
+    CHECK(std::equal(original.begin(), original.end(), roundTrip.begin()));
+}
+
+bool checksumRejected(std::span<const uint8_t> packet) {
+    try {
+        decompressPacket(packet, 1024, true);
+    } catch (const ChecksumMismatch&) {
+        return true;
+    }
+    return false;
+}
+
+void checksummedPackets() {
+    const std::vector<uint8_t> original{ 7, 0, 0, 0, 0, 0, 9, 1, 0, 0, 0, 0, 5, 0, 0, 0 };
+    const auto packet = compressPacket(original, true);
+    CHECK_EQ(packet.size(), compressPacket(original).size() + CHECKSUM_SIZE);
+    CHECK(hasValidChecksum(packet));
+    CHECK(decompressPacket(packet, 1024, true) == original);
+
+    // One flipped bit anywhere, payload or trailer, fails the check
+    auto payloadFlipped = packet;
+    payloadFlipped[1] ^= 0x01;
+    CHECK(!hasValidChecksum(payloadFlipped));
+    CHECK(checksumRejected(payloadFlipped));
+
+    auto trailerFlipped = packet;
+    trailerFlipped.back() ^= 0x80;
+    CHECK(!hasValidChecksum(trailerFlipped));
+    CHECK(checksumRejected(trailerFlipped));
+
+    // Too short to hold a trailer at all
+    CHECK(checksumRejected(std::span<const uint8_t>(packet.data(), CHECKSUM_SIZE - 1)));
+}
+
+void intoMatchesAllocating() {
+    // One buffer each way, reused and never emptied by the caller, as the server's are
+    std::vector<uint8_t> compressed(300, 0xEE);
+    std::vector<uint8_t> decompressed(300, 0xEE);
+    const std::vector<std::vector<uint8_t>> packets{
+        { 1, 0, 0, 2, 3, 0, 0, 0, 0, 4 },
+        std::vector<uint8_t>(64, 0),
+        std::vector<uint8_t>(200, 0xAB),
+        { 9 },
+    };
+    for (const auto& packet : packets) {
+        for (const bool checksum : { false, true }) {
+            compressPacketInto(packet, compressed, checksum);
+            CHECK(compressed == compressPacket(packet, checksum));
+
+            decompressPacketInto(compressed, decompressed, 1024, checksum);
+            CHECK(decompressed == decompressPacket(compressed, 1024, checksum));
+            decompressPacketInto(compressed, decompressed, 16, checksum);
+            CHECK(decompressed == decompressPacket(compressed, 16, checksum));
+        }
+    }
+}
+
+void runLengthInputs() {
+    // (3 x A) (2 x B) covers five frames
+    PacketWriter runs;
+    runs.u8(3);
+    runs.input(FrameInput(0xA), DEFAULT_INPUT_WIDTH);
+    runs.u8(2);
+    runs.input(FrameInput(0xB), DEFAULT_INPUT_WIDTH);
+    auto message = parseClientMessage(inputPacket(1, 5, runs.take()), DEFAULT_INPUT_WIDTH, nullptr, InputEncoding::RunLength);
+    CHECK(message.has_value());
+    if (message) {
+        const auto& input = std::get<InputPayload>(message->payload);
+        const std::vector<FrameInput> expected{ 0xA, 0xA, 0xA, 0xB, 0xB };
+        CHECK(input.inputPerFrame == expected);
+    }
+
+    // A run longer than the frames left is cut to numFrames
+    runs.u8(9);
+    runs.input(FrameInput(0xC), DEFAULT_INPUT_WIDTH);
+    message = parseClientMessage(inputPacket(1, 4, runs.take()), DEFAULT_INPUT_WIDTH, nullptr, InputEncoding::RunLength);
+    CHECK(message.has_value());
+    if (message) {
+        CHECK_EQ(std::get<InputPayload>(message->payload).inputPerFrame.size(), size_t{ 4 });
+    }
+
+    // An empty run is malformed
+    runs.u8(0);
+    runs.input(FrameInput(0xD), DEFAULT_INPUT_WIDTH);
+    std::string error;
+    message = parseClientMessage(inputPacket(1, 1, runs.take()), DEFAULT_INPUT_WIDTH, &error, InputEncoding::RunLength);
+    CHECK(!message.has_value());
+    CHECK_EQ(error, std::string("empty input run"));
+}
+
+void configWordsTwoTeams() {
+    CHECK_EQ(playerConfigValue(0, 0), uint16_t{ 0 });
+    CHECK_EQ(playerConfigValue(1, 1), uint16_t{ 257 });
+    CHECK_EQ(playerConfigValue(2, 1), uint16_t{ 0x0201 });
+    CHECK_EQ(playerConfigValue(3, 0x0101), uint16_t{ 0x0301 }); // team cut to its low byte
+
+    // Players 0 and 2 against 1: each slot carries its index above its team
+    const int maxPlayers = 3;
+    const PlayersConfigurationDataPayload payload{
+        maxPlayers, { playerConfigValue(0, 0), playerConfigValue(1, 1), playerConfigValue(2, 0) }
+    };
+    const auto parsed = parseServerMessage(
+        serializeServerMessage({ ServerMessageType::PlayersConfigurationData, 4 }, payload, maxPlayers), maxPlayers);
+    CHECK(parsed.has_value());
+    if (parsed) {
+        const auto& words = std::get<PlayersConfigurationDataPayload>(parsed->payload).configValues;
+        CHECK(words == std::vector<uint16_t>({ 0, 257, 512 }));
+        for (size_t i = 0; i < words.size(); ++i) {
+            CHECK_EQ(static_cast<size_t>(words[i] >> 8), i);
+        }
+        CHECK_EQ(words[0] & 0xFF, words[2] & 0xFF);
+        CHECK((words[0] & 0xFF) != (words[1] & 0xFF));
+    }
+}
+
+void configWordsTwoVersusTwo() {
+    // Players 0 and 1 against 2 and 3, laid out as message_types.h documents
+    const int maxPlayers = 4;
+    const PlayersConfigurationDataPayload payload{
+        maxPlayers,
+        { playerConfigValue(0, 0), playerConfigValue(1, 0), playerConfigValue(2, 1), playerConfigValue(3, 1) }
+    };
+    CHECK(payload.configValues == std::vector<uint16_t>({ 0, 256, 513, 769 }));
+    const auto bytes = serializeServerMessage({ ServerMessageType::PlayersConfigurationData, 9 }, payload, maxPlayers);
+    const std::vector<uint8_t> expected{
+        static_cast<uint8_t>(ServerMessageType::PlayersConfigurationData), 9, 0, 0, 0, // header
+        4,                                                                              // numPlayers
+        0x00, 0x00, 0x00, 0x01, 0x01, 0x02, 0x01, 0x03,                                 // u16le per slot
+    };
+    CHECK(bytes == expected);
+}
+
+void capabilityVersions() {
+    CHECK_EQ(protocolCapabilities(SUPPORTED_PROTOCOL_VERSION), uint16_t{ 0 });
+    const uint16_t both = CAPABILITY_SESSION_NONCE | CAPABILITY_RLE_INPUTS;
+    CHECK_EQ(protocolCapabilities(CAPABILITIES_VERSION_FLAG | both), both);
+}
+
+void sequenceWraparound() {
+    CHECK(isSequenceNewer(2, 1));
+    CHECK(!isSequenceNewer(1, 1));
+    CHECK(!isSequenceNewer(1, 2));
+
+    // Past 0xFFFFFFFF the count starts over, and the low numbers are still newer
+    CHECK(isSequenceNewer(0, UINT32_MAX));
+    CHECK(isSequenceNewer(3, UINT32_MAX - 1));
+    CHECK(isSequenceNewer(UINT32_MAX, UINT32_MAX - 1));
+
+    // ...while a straggler from just before the wrap is stale once it has happened
+    CHECK(!isSequenceNewer(UINT32_MAX, 0));
+    CHECK(!isSequenceNewer(UINT32_MAX - 1, 3));
+}
+
+void sessionNonceOnlyWhenNegotiated() {
+    // Zeros after the reply, as mask-group padding leaves them, are not a nonce
+    const NewConnectionReplyPayload reply{ 0, 2, 1, 3600, 0, 0, std::nullopt };
+    auto bytes = serializeServerMessage({ ServerMessageType::NewConnectionReply, 1 }, reply, 2);
+    bytes.resize(bytes.size() + 8, 0);
+    auto parsed = parseServerMessage(bytes, 2);
+    CHECK(parsed.has_value());
+    if (parsed) {
+        CHECK(!std::get<NewConnectionReplyPayload>(parsed->payload).sessionNonce.has_value());
+    }
+
+    // Negotiated, the nonce is read, and a reply without one is malformed
+    auto withNonce = reply;
+    withNonce.sessionNonce = 0xDEADBEEF;
+    parsed = parseServerMessage(serializeServerMessage({ ServerMessageType::NewConnectionReply, 1 }, withNonce, 2), 2,
+                                DEFAULT_INPUT_WIDTH, CAPABILITY_SESSION_NONCE);
+    CHECK(parsed.has_value());
+    if (parsed) {
+        CHECK(std::get<NewConnectionReplyPayload>(parsed->payload).sessionNonce == std::optional<uint32_t>(0xDEADBEEF));
+    }
+    const auto withoutNonce = serializeServerMessage({ ServerMessageType::NewConnectionReply, 1 }, reply, 2);
+    CHECK(!parseServerMessage(withoutNonce, 2, DEFAULT_INPUT_WIDTH, CAPABILITY_SESSION_NONCE).has_value());
+}
+
+} // namespace
+
+int main() {
+    stringHelpers();
+    wideInputRoundTrip();
+    serializedSizes();
+    serverMessageRoundTrips();
+    batchRoundTrip();
+    frameBudgetFits();
+    runLengthCapped();
+    prunedHistoryThenStaleAck();
+    decodedLength();
+    checksummedPackets();
+    intoMatchesAllocating();
+    runLengthInputs();
+    configWordsTwoTeams();
+    configWordsTwoVersusTwo();
+    capabilityVersions();
+    sequenceWraparound();
+    sessionNonceOnlyWhenNegotiated();
+    return test::finish("protocol_tests");
+}
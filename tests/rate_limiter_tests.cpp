@@ -0,0 +1,53 @@
+// RateLimiter: the per-address token bucket in front of connection packets
+#include "check.h"
+#include "rate_limiter.h"
+#include <chrono>
+
+using namespace rollback;
+
+namespace {
+
+constexpr double RATE = 2.0; // tokens per second
+constexpr double BURST = 5.0;
+
+int allowedOf(RateLimiter& limiter, const std::string& key, std::chrono::steady_clock::time_point now, int attempts) {
+    int allowed = 0;
+    for (int i = 0; i < attempts; ++i) {
+        if (limiter.allow(key, now)) {
+            ++allowed;
+        }
+    }
+    return allowed;
+}
+
+void burstAtOneInstant() {
+    RateLimiter limiter(RATE, BURST);
+    const auto now = std::chrono::steady_clock::time_point{} + std::chrono::hours(1);
+    CHECK_EQ(allowedOf(limiter, "1.2.3.4", now, 100), static_cast<int>(BURST));
+
+    // Buckets are per key: another address still has its whole burst
+    CHECK_EQ(allowedOf(limiter, "5.6.7.8", now, 100), static_cast<int>(BURST));
+}
+
+void refillsAtRate() {
+    RateLimiter limiter(RATE, BURST);
+    const auto start = std::chrono::steady_clock::time_point{} + std::chrono::hours(1);
+    CHECK_EQ(allowedOf(limiter, "1.2.3.4", start, 100), static_cast<int>(BURST));
+
+    // One token comes back every 1/RATE seconds, not before
+    const auto oneToken = std::chrono::duration_cast<std::chrono::steady_clock::duration>(std::chrono::duration<double>(1.0 / RATE));
+    CHECK(!limiter.allow("1.2.3.4", start + oneToken / 2));
+    CHECK(limiter.allow("1.2.3.4", start + oneToken));
+    CHECK(!limiter.allow("1.2.3.4", start + oneToken));
+
+    // However long the wait, the bucket holds no more than the burst
+    CHECK_EQ(allowedOf(limiter, "1.2.3.4", start + std::chrono::minutes(10), 100), static_cast<int>(BURST));
+}
+
+} // namespace
+
+int main() {
+    burstAtOneInstant();
+    refillsAtRate();
+    return test::finish("rate_limiter_tests");
+}
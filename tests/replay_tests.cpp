@@ -0,0 +1,81 @@
+// ReplayRecorder and ReplayReader: what's written comes back in order, intact
+#include "check.h"
+#include "replay.h"
+#include <chrono>
+#include <filesystem>
+#include <thread>
+
+using namespace rollback;
+
+namespace {
+
+std::string tempPath(const char* name) {
+    return (std::filesystem::temp_directory_path() / name).string();
+}
+
+void recordThenRead() {
+    const auto path = tempPath("replay_tests.mvsr");
+    const std::vector<uint8_t> hello{ 0x01, 0x02, 0x03 };
+    const std::vector<uint8_t> reply{ 0xAA };
+    const std::vector<uint8_t> broadcast{ 0x10, 0x20 };
+    {
+        ReplayRecorder recorder(path);
+        CHECK(recorder.isOpen());
+        recorder.record(ReplayDirection::ClientToServer, 0, hello);
+        std::this_thread::sleep_for(std::chrono::milliseconds(5));
+        recorder.record(ReplayDirection::ServerToClient, 1, reply);
+        recorder.record(ReplayDirection::ServerToClient, REPLAY_NO_PLAYER, broadcast);
+    }
+
+    ReplayReader reader(path);
+    CHECK(reader.isValid());
+    const auto first = reader.next();
+    const auto second = reader.next();
+    const auto third = reader.next();
+    CHECK(first.has_value() && second.has_value() && third.has_value());
+    if (first && second && third) {
+        CHECK(first->direction == ReplayDirection::ClientToServer);
+        CHECK_EQ(first->playerIndex, uint8_t{ 0 });
+        CHECK(first->datagram == hello);
+
+        CHECK(second->direction == ReplayDirection::ServerToClient);
+        CHECK_EQ(second->playerIndex, uint8_t{ 1 });
+        CHECK(second->datagram == reply);
+
+        CHECK(third->direction == ReplayDirection::ServerToClient);
+        CHECK_EQ(third->playerIndex, REPLAY_NO_PLAYER);
+        CHECK(third->datagram == broadcast);
+
+        // Offsets count from the start of the recording and never go backwards
+        CHECK(first->offset.count() >= 0);
+        CHECK(second->offset - first->offset >= std::chrono::milliseconds(5));
+        CHECK(third->offset >= second->offset);
+    }
+    CHECK(!reader.next().has_value());
+    std::filesystem::remove(path);
+}
+
+void truncatedRecordEndsReplay() {
+    const auto path = tempPath("replay_tests_truncated.mvsr");
+    {
+        ReplayRecorder recorder(path);
+        recorder.record(ReplayDirection::ClientToServer, 0, std::vector<uint8_t>{ 1, 2, 3, 4 });
+        recorder.record(ReplayDirection::ClientToServer, 0, std::vector<uint8_t>{ 5, 6, 7, 8 });
+    }
+    std::filesystem::resize_file(path, std::filesystem::file_size(path) - 1);
+
+    ReplayReader reader(path);
+    CHECK(reader.next().has_value());
+    CHECK(!reader.next().has_value());
+    std::filesystem::remove(path);
+
+    CHECK(!ReplayReader(tempPath("replay_tests_missing.mvsr")).isValid());
+}
+
+} // namespace
+
+int main() {
+    recordThenRead();
+    truncatedRecordEndsReplay();
+    return test::finish("replay_tests");
+}
@@ -0,0 +1,72 @@
+// Rift sign convention, end to end: a client's Input packet, the rift estimate it
+// produces, and the int16 the server puts on the wire. Positive means the client
+// runs ahead of the server and must slow down.
+#include "check.h"
+#include "packet_io.h"
+#include "rift.h"
+#include "serialization.h"
+#include <vector>
+
+using namespace rollback;
+
+namespace {
+
+constexpr int MAX_PLAYERS_IN_TEST = 2;
+constexpr float PING_MS = 50.0f;
+
+uint32_t clientFrameOf(uint32_t clientFrame) {
+    PacketWriter writer;
+    writer.u8(static_cast<uint8_t>(ClientMessageType::Input));
+    writer.u32le(1);
+    writer.u32le(clientFrame); // startFrame
+    writer.u32le(clientFrame); // clientFrame
+    writer.u8(1);
+    writer.u8(0);
+    writer.input(FrameInput(0x1), DEFAULT_INPUT_WIDTH);
+    const auto message = parseClientMessage(writer.take(), DEFAULT_INPUT_WIDTH);
+    CHECK(message.has_value());
+    return message ? std::get<InputPayload>(message->payload).clientFrame : 0;
+}
+
+// The rift field exactly as serialized: after the header, numPlayers, startFrame[],
+// numFrames[], both override counts, ping and loss
+int16_t wireRift(float rift) {
+    PlayerInputPayload payload{};
+    payload.numPlayers = MAX_PLAYERS_IN_TEST;
+    payload.startFrame.assign(MAX_PLAYERS_IN_TEST, 0);
+    payload.numFrames.assign(MAX_PLAYERS_IN_TEST, 0);
+    payload.inputPerFrame.resize(MAX_PLAYERS_IN_TEST);
+    payload.rift = rift;
+    const auto bytes = serializeServerMessage({ ServerMessageType::PlayerInput, 1 }, payload, MAX_PLAYERS_IN_TEST);
+    PacketReader reader(bytes);
+    reader.skip(5 + 1 + 5 * MAX_PLAYERS_IN_TEST + 2 + 2 + 2 + 2);
+    return reader.i16le();
+}
+
+void clientAhead() {
+    // Thirty frames past the server, before even counting the trip
+    const auto state = updateRift({}, PING_MS, clientFrameOf(130), 100);
+    CHECK(state.smoothRift > 0.0f);
+    CHECK(wireRift(state.smoothRift) > 0);
+}
+
+void clientBehind() {
+    // Thirty frames short, far more than half the round trip explains
+    const auto state = updateRift({}, PING_MS, clientFrameOf(70), 100);
+    CHECK(state.smoothRift < 0.0f);
+    CHECK(wireRift(state.smoothRift) < 0);
+}
+
+void saturatesWithoutFlipping() {
+    CHECK_EQ(wireRift(1000.0f), int16_t{ INT16_MAX });
+    CHECK_EQ(wireRift(-1000.0f), int16_t{ INT16_MIN });
+}
+
+} // namespace
+
+int main() {
+    clientAhead();
+    clientBehind();
+    saturatesWithoutFlipping();
+    return test::finish("rift_tests");
+}
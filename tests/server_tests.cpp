@@ -0,0 +1,245 @@
+// The whole server over real sockets on 127.0.0.1, driven by the scripted clients of
+// tools/loopback_client.h: the lobby and match lifecycle that pure functions can't
+// cover. Each test starts its own server on an ephemeral port.
+#include "check.h"
+#include "loopback_client.h"
+#include "rollback_server.h"
+#include <asio.hpp>
+#include <chrono>
+#include <memory>
+#include <thread>
+#include <vector>
+
+using namespace rollback;
+using namespace rollback::loopback;
+
+namespace {
+
+constexpr const char* MATCH_ID = "server-tests";
+constexpr const char* MATCH_KEY = "server-tests-key";
+constexpr auto WAIT = std::chrono::seconds(5);
+
+MVSIMatchConfig rosterOf(uint8_t numPlayers) {
+    MVSIMatchConfig config;
+    config.max_players = numPlayers;
+    config.match_duration = 36000;
+    for (uint16_t i = 0; i < numPlayers; ++i) {
+        config.players.push_back({ i, "127.0.0.1", i == 0, static_cast<uint16_t>(i % 2) });
+    }
+    return config;
+}
+
+// Short ping phase and generous connect limits, every client sharing 127.0.0.1
+ServerConfig testConfig(uint8_t numPlayers) {
+    ServerConfig config;
+    config.maxPlayers = numPlayers;
+    config.connectRatePerSecond = 100.0;
+    config.connectBurst = 10.0;
+    config.pingCount = 2;
+    config.pingIntervalMs = 20;
+    return config;
+}
+
+std::unique_ptr<RollbackServer> startServer(const ServerConfig& config, uint8_t numPlayers) {
+    auto server = std::make_unique<RollbackServer>(
+        config, std::make_unique<LoopbackBackend>(MATCH_ID, rosterOf(numPlayers)), nullptr,
+        [](asio::io_context& io) { return udp::socket(io, udp::endpoint(asio::ip::make_address("127.0.0.1"), 0)); });
+    server->start();
+    return server;
+}
+
+udp::endpoint endpointOf(const RollbackServer& server) {
+    return udp::endpoint(asio::ip::make_address("127.0.0.1"), server.localPort());
+}
+
+ClientIdentity slot(uint16_t playerIndex) {
+    return ClientIdentity{ playerIndex, static_cast<uint16_t>(playerIndex % 2), MATCH_ID, MATCH_KEY };
+}
+
+// Polls `clients` until `done()` holds; false if WAIT passes first
+template <typename Done>
+bool pumpUntil(const std::vector<LoopbackClient*>& clients, Done done) {
+    const auto deadline = std::chrono::steady_clock::now() + WAIT;
+    while (std::chrono::steady_clock::now() < deadline) {
+        for (auto* client : clients) {
+            client->poll();
+        }
+        if (done()) {
+            return true;
+        }
+        std::this_thread::sleep_for(std::chrono::milliseconds(1));
+    }
+    return false;
+}
+
+// Each client sends one frame of input per poll round until every one of them has the
+// others' frames 1..frames intact; false if WAIT passes first
+bool tradeInputs(const std::vector<LoopbackClient*>& clients, uint32_t frames) {
+    auto inputOf = [](uint16_t player, uint32_t frame) { return FrameInput((static_cast<uint32_t>(player + 1) << 24) | frame); };
+    uint32_t next = 1;
+    return pumpUntil(clients, [&] {
+        for (auto* client : clients) {
+            if (client->started() && next <= frames) {
+                client->sendInput(next, next, { inputOf(client->playerIndex(), next) });
+            }
+        }
+        if (next <= frames) {
+            ++next;
+        }
+        for (auto* client : clients) {
+            for (auto* peer : clients) {
+                if (peer == client) {
+                    continue;
+                }
+                const auto& received = client->receivedInputs(peer->playerIndex());
+                for (uint32_t frame = 1; frame <= frames; ++frame) {
+                    const auto it = received.find(frame);
+                    if (it == received.end() || it->second != inputOf(peer->playerIndex(), frame)) {
+                        return false;
+                    }
+                }
+            }
+        }
+        return true;
+    });
+}
+
+bool slotConnected(const RollbackServer& server, uint16_t playerIndex) {
+    const auto lobby = server.getLobbyState(MATCH_ID);
+    return lobby && playerIndex < lobby->size() && (*lobby)[playerIndex].connected;
+}
+
+void idlePlayerFreesSlotBeforeConfiguration() {
+    auto config = testConfig(3);
+    config.idlePlayerTimeoutMs = 300;
+    auto server = startServer(config, 3);
+
+    asio::io_context io;
+    LoopbackClient silent(io, slot(0), 3, endpointOf(*server));
+    LoopbackClient present(io, slot(1), 3, endpointOf(*server));
+    silent.connect();
+    present.connect();
+    CHECK(pumpUntil({ &silent, &present }, [&] { return silent.connected() && present.connected(); }));
+
+    // Only `present` answers from here on; slot 0 is dropped and free to take again
+    CHECK(pumpUntil({ &present }, [&] { return !slotConnected(*server, 0); }));
+    CHECK(slotConnected(*server, 1));
+    LoopbackClient newcomer(io, slot(0), 3, endpointOf(*server));
+    newcomer.connect();
+    CHECK(pumpUntil({ &newcomer, &present }, [&] { return newcomer.connected(); }));
+    server->stop();
+}
+
+void idlePlayerHandedToAiAfterConfiguration() {
+    auto config = testConfig(2);
+    config.idlePlayerTimeoutMs = 300;
+    config.readyTimeoutMs = 0;
+    auto server = startServer(config, 2);
+
+    asio::io_context io;
+    LoopbackClient present(io, slot(0), 2, endpointOf(*server));
+    LoopbackClient silent(io, slot(1), 2, endpointOf(*server));
+    silent.setAutoReady(false); // keeps the match from starting
+    present.connect();
+    silent.connect();
+    CHECK(pumpUntil({ &present, &silent }, [&] { return present.configured() && silent.configured(); }));
+
+    // Everyone was told about slot 1, so it stays in the match and the AI is told to take it
+    CHECK(pumpUntil({ &present }, [&] { return !present.disconnectNotices().empty(); }));
+    if (!present.disconnectNotices().empty()) {
+        CHECK_EQ(present.disconnectNotices().front().playerIndex, uint8_t{ 1 });
+        CHECK_EQ(present.disconnectNotices().front().shouldAITakeControl, uint8_t{ 1 });
+    }
+    const auto lobby = server->getLobbyState(MATCH_ID);
+    CHECK(lobby.has_value());
+    CHECK(!slotConnected(*server, 1));
+    server->stop();
+}
+
+void reconnectMovesPlayerAndCapabilities() {
+    auto config = testConfig(2);
+    config.packetChecksums = true;
+    auto server = startServer(config, 2);
+
+    asio::io_context io;
+    LoopbackClient first(io, slot(0), 2, endpointOf(*server));
+    LoopbackClient other(io, slot(1), 2, endpointOf(*server));
+    first.connect();
+    other.connect();
+    CHECK(pumpUntil({ &first, &other }, [&] { return first.connected() && other.connected(); }));
+
+    // Slot 0 comes back from a new port, now asking for checksums: unless the server
+    // switches format with it, the reply fails the check and never counts as connected
+    LoopbackClient again(io, slot(0), 2, endpointOf(*server));
+    again.setChecksums(true);
+    again.connect();
+    CHECK(pumpUntil({ &again, &other }, [&] { return again.connected(); }));
+    CHECK(slotConnected(*server, 0));
+
+    // Inputs from the new endpoint are slot 0's, and slot 0's inputs go to it
+    CHECK(pumpUntil({ &again, &other }, [&] { return again.started() && other.started(); }));
+    CHECK(tradeInputs({ &again, &other }, 10));
+    server->stop();
+}
+
+void readyTimeoutKicksStragglers() {
+    auto config = testConfig(2);
+    config.readyTimeoutMs = 300;
+    config.readyTimeoutPolicy = ReadyTimeoutPolicy::KickAndStart;
+    auto server = startServer(config, 2);
+
+    asio::io_context io;
+    LoopbackClient ready(io, slot(0), 2, endpointOf(*server));
+    LoopbackClient straggler(io, slot(1), 2, endpointOf(*server));
+    straggler.setAutoReady(false);
+    ready.connect();
+    straggler.connect();
+
+    // The straggler goes, and the match starts without it
+    CHECK(pumpUntil({ &ready, &straggler }, [&] { return straggler.kickReason().has_value() && ready.started(); }));
+    CHECK(straggler.kickReason() == std::optional<uint16_t>(static_cast<uint16_t>(KickReason::ReadyTimeout)));
+    CHECK(!ready.kickReason().has_value());
+    CHECK(!straggler.started());
+    server->stop();
+}
+
+void readyTimeoutAbortsMatch() {
+    auto config = testConfig(2);
+    config.readyTimeoutMs = 300;
+    config.readyTimeoutPolicy = ReadyTimeoutPolicy::Abort;
+    auto server = startServer(config, 2);
+
+    asio::io_context io;
+    LoopbackClient ready(io, slot(0), 2, endpointOf(*server));
+    LoopbackClient straggler(io, slot(1), 2, endpointOf(*server));
+    LoopbackClient watcher(io, slot(0), 2, endpointOf(*server));
+    straggler.setAutoReady(false);
+    watcher.setSpectator(true);
+    ready.connect();
+    straggler.connect();
+    CHECK(pumpUntil({ &ready, &straggler }, [&] { return ready.connected() && straggler.connected(); }));
+    watcher.connect();
+
+    // Everyone is kicked, spectators included, and the match is gone
+    const std::vector<LoopbackClient*> everyone{ &ready, &straggler, &watcher };
+    CHECK(pumpUntil(everyone, [&] {
+        return ready.kickReason().has_value() && straggler.kickReason().has_value() && watcher.kickReason().has_value();
+    }));
+    for (const auto* client : everyone) {
+        CHECK(client->kickReason() == std::optional<uint16_t>(static_cast<uint16_t>(KickReason::ReadyTimeout)));
+        CHECK(!client->started());
+    }
+    CHECK(pumpUntil({}, [&] { return !server->getLobbyState(MATCH_ID).has_value(); }));
+    server->stop();
+}
+
+} // namespace
+
+int main() {
+    idlePlayerFreesSlotBeforeConfiguration();
+    idlePlayerHandedToAiAfterConfiguration();
+    reconnectMovesPlayerAndCapabilities();
+    readyTimeoutKicksStragglers();
+    readyTimeoutAbortsMatch();
+    return test::finish("server_tests");
+}
@@ -0,0 +1,59 @@
+// TickScheduler's schedule on a ManualClock: absolute deadlines, and the re-anchor
+// after falling too far behind. Only advance(), no timers.
+#include "check.h"
+#include "tick_scheduler.h"
+
+using namespace rollback;
+
+namespace {
+
+constexpr std::chrono::nanoseconds PERIOD = std::chrono::microseconds(16667);
+
+void nthTickAtStartPlusNPeriods() {
+    auto clock = std::make_shared<ManualClock>(Clock::time_point(std::chrono::seconds(100)));
+    const auto start = clock->now();
+    TickScheduler scheduler(PERIOD, clock);
+
+    // Work between ticks, on time or a little late, never shifts the schedule
+    for (int n = 1; n <= 600; ++n) {
+        const auto deadline = scheduler.advance(clock->now());
+        CHECK(deadline == start + PERIOD * n);
+        clock->set(deadline + std::chrono::microseconds(n % 7 * 1000));
+    }
+    CHECK_EQ(scheduler.ticks(), uint64_t{ 600 });
+}
+
+void earlyCallKeepsDeadline() {
+    auto clock = std::make_shared<ManualClock>();
+    const auto start = clock->now();
+    TickScheduler scheduler(PERIOD, clock);
+
+    // Asking well before the tick is due still gets the tick's own deadline
+    CHECK(scheduler.advance(start) == start + PERIOD);
+    CHECK(scheduler.advance(start) == start + PERIOD * 2);
+}
+
+void reanchorsAfterFallingBehind() {
+    auto clock = std::make_shared<ManualClock>();
+    const auto start = clock->now();
+    TickScheduler scheduler(PERIOD, clock);
+
+    // Up to MAX_LAG_TICKS behind: the missed ticks are still owed, back to back
+    const auto slightlyLate = start + PERIOD * (1 + TickScheduler::MAX_LAG_TICKS);
+    CHECK(scheduler.advance(slightlyLate) == start + PERIOD);
+
+    // Further behind: this tick is due now and the next one a period later
+    const auto stalled = start + std::chrono::seconds(5);
+    CHECK(scheduler.advance(stalled) == stalled);
+    CHECK(scheduler.advance(stalled) == stalled + PERIOD);
+    CHECK(scheduler.advance(stalled + PERIOD) == stalled + PERIOD * 2);
+}
+
+} // namespace
+
+int main() {
+    nthTickAtStartPlusNPeriods();
+    earlyCallKeepsDeadline();
+    reanchorsAfterFallingBehind();
+    return test::finish("tick_scheduler_tests");
+}
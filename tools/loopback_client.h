@@ -0,0 +1,229 @@
+// Scripted clients for the tools that drive a real RollbackServer over 127.0.0.1
+// (loopback-match, replay-harness), and a backend that needs no HTTP.
+#pragma once
+
+#include "compression.h"
+#include "match_backend.h"
+#include "packet_io.h"
+#include "serialization.h"
+#include <asio.hpp>
+#include <map>
+#include <optional>
+#include <string>
+#include <vector>
+
+namespace rollback::loopback {
+
+using asio::ip::udp;
+
+// Hands out a fixed config for one match id
+class LoopbackBackend : public MatchBackend {
+public:
+    LoopbackBackend(std::string matchId, MVSIMatchConfig config)
+        : matchId_(std::move(matchId)), config_(std::move(config)) {}
+
+    std::optional<MVSIMatchConfig> registerMatch(const std::string& matchId, const std::string&) override {
+        if (matchId != matchId_) {
+            return std::nullopt;
+        }
+        return config_;
+    }
+
+    void endMatch(const std::string&, const std::string&, const MatchOutcome&) override {}
+
+private:
+    std::string matchId_;
+    MVSIMatchConfig config_;
+};
+
+// What a client puts in its NewConnection packet
+struct ClientIdentity {
+    uint16_t playerIndex = 0;
+    uint16_t teamId = 0;
+    std::string matchId;
+    std::string key;
+};
+
+/**
+ * One player's socket. poll() answers the handshake the way the game does (quality
+ * data, ready, input acks) and collects every input the server relays; what to send
+ * as input, and when, is up to the caller.
+ */
+class LoopbackClient {
+public:
+    LoopbackClient(asio::io_context& io, ClientIdentity identity, uint8_t numPlayers, udp::endpoint server,
+                   size_t inputWidth = DEFAULT_INPUT_WIDTH)
+        : identity_(std::move(identity)), numPlayers_(numPlayers), inputWidth_(inputWidth), server_(server),
+          socket_(io, udp::endpoint(asio::ip::make_address("127.0.0.1"), 0)), receivedInputs_(numPlayers) {
+        socket_.non_blocking(true);
+    }
+
+    // On, the client asks for CAPABILITY_CHECKSUMS and drops whatever arrives without one
+    void setChecksums(bool checksums) { checksums_ = checksums; }
+
+    // On, connect() joins as a spectator of the match rather than into the identity's slot
+    void setSpectator(bool spectator) { spectator_ = spectator; }
+
+    void connect() {
+        PacketWriter writer = header(spectator_ ? ClientMessageType::SpectatorConnection : ClientMessageType::NewConnection);
+        writer.u16le(checksums_ ? static_cast<uint16_t>(CAPABILITIES_VERSION_FLAG | CAPABILITY_CHECKSUMS)
+                                : SUPPORTED_PROTOCOL_VERSION);
+        writer.u16le(identity_.teamId);
+        writer.u16le(identity_.playerIndex);
+        writer.stringFixed(identity_.matchId, 25);
+        writer.stringFixed(identity_.key, 45);
+        writer.stringFixed("loopback", 25);
+        send(writer);
+    }
+
+    // Answers whatever the server sent since the last call
+    void poll() {
+        std::array<uint8_t, 2048> buffer;
+        udp::endpoint from;
+        std::error_code ec;
+        for (;;) {
+            const size_t n = socket_.receive_from(asio::buffer(buffer), from, 0, ec);
+            if (ec) {
+                break;
+            }
+            std::vector<uint8_t> packet;
+            try {
+                packet = decompressPacket(std::span<const uint8_t>(buffer.data(), n), 1024, checksums_);
+            } catch (const std::runtime_error&) {
+                continue;
+            }
+            if (const auto messages = parseServerMessages(packet, numPlayers_, inputWidth_)) {
+                for (const auto& message : *messages) {
+                    onMessage(message);
+                }
+            }
+        }
+    }
+
+    void sendInput(uint32_t startFrame, uint32_t clientFrame, const std::vector<FrameInput>& inputs) {
+        PacketWriter writer = header(ClientMessageType::Input);
+        writer.u32le(startFrame);
+        writer.u32le(clientFrame);
+        writer.u8(static_cast<uint8_t>(inputs.size()));
+        writer.u8(0);
+        for (const auto& input : inputs) {
+            writer.input(input, inputWidth_);
+        }
+        send(writer);
+    }
+
+    // Off, the client takes PlayersConfigurationData without ever readying up
+    void setAutoReady(bool autoReady) { autoReady_ = autoReady; }
+
+    uint16_t playerIndex() const { return identity_.playerIndex; }
+    bool connected() const { return connected_; }
+    bool configured() const { return configured_; }
+    bool started() const { return started_; }
+    const std::string& failure() const { return failure_; }
+    std::optional<uint16_t> kickReason() const { return kickReason_; }
+
+    // Every PlayerDisconnected the server sent (each is acked), in arrival order
+    const std::vector<PlayerDisconnectedPayload>& disconnectNotices() const { return disconnectNotices_; }
+
+    // Every input the server relayed for `player`, by frame (the latest value wins)
+    const std::map<uint32_t, FrameInput>& receivedInputs(uint16_t player) const { return receivedInputs_.at(player); }
+
+private:
+    void onMessage(const ServerMessageComplete& message) {
+        switch (message.header.type) {
+        case ServerMessageType::NewConnectionReply:
+            connected_ = std::get<NewConnectionReplyPayload>(message.payload).success ==
+                         static_cast<uint8_t>(ConnectionResult::Success);
+            if (!connected_) {
+                failure_ = "connection refused";
+            }
+            break;
+        case ServerMessageType::RequestQualityData: {
+            PacketWriter writer = header(ClientMessageType::QualityData);
+            writer.u32le(message.header.sequence);
+            send(writer);
+            break;
+        }
+        case ServerMessageType::PlayersConfigurationData: {
+            configured_ = true;
+            if (!autoReady_) {
+                break;
+            }
+            PacketWriter writer = header(ClientMessageType::ReadyToStartMatch);
+            writer.u8(1);
+            send(writer);
+            break;
+        }
+        case ServerMessageType::StartGame:
+            started_ = true;
+            break;
+        case ServerMessageType::PlayerInput:
+            onPlayerInput(message.header.sequence, std::get<PlayerInputPayload>(message.payload));
+            break;
+        case ServerMessageType::Kick:
+            kickReason_ = std::get<KickPayload>(message.payload).reason;
+            failure_ = "kicked, reason " + std::to_string(*kickReason_);
+            break;
+        case ServerMessageType::PlayerDisconnected: {
+            const auto& notice = std::get<PlayerDisconnectedPayload>(message.payload);
+            disconnectNotices_.push_back(notice);
+            PacketWriter writer = header(ClientMessageType::PlayerDisconnectedAck);
+            writer.u8(static_cast<uint8_t>(notice.playerDisconnectedArrayIndex));
+            send(writer);
+            break;
+        }
+        default:
+            break;
+        }
+    }
+
+    void onPlayerInput(uint32_t sequence, const PlayerInputPayload& payload) {
+        for (size_t player = 0; player < receivedInputs_.size() && player < payload.inputPerFrame.size(); ++player) {
+            const auto& inputs = payload.inputPerFrame[player];
+            for (size_t i = 0; i < inputs.size(); ++i) {
+                receivedInputs_[player].insert_or_assign(payload.startFrame[player] + static_cast<uint32_t>(i), inputs[i]);
+            }
+        }
+
+        PacketWriter writer = header(ClientMessageType::PlayerInputAck);
+        writer.u8(payload.numPlayers);
+        for (uint8_t i = 0; i < payload.numPlayers; ++i) {
+            const uint32_t count = i < payload.numFrames.size() ? payload.numFrames[i] : 0;
+            writer.u32le(count > 0 ? payload.startFrame[i] + count - 1 : 0);
+        }
+        writer.u32le(sequence);
+        send(writer);
+    }
+
+    PacketWriter header(ClientMessageType type) {
+        PacketWriter writer;
+        writer.u8(static_cast<uint8_t>(type));
+        writer.u32le(++sequence_);
+        return writer;
+    }
+
+    void send(PacketWriter& writer) {
+        const auto datagram = compressPacket(writer.take(), checksums_);
+        std::error_code ec;
+        socket_.send_to(asio::buffer(datagram), server_, 0, ec);
+    }
+
+    ClientIdentity identity_;
+    uint8_t numPlayers_;
+    size_t inputWidth_;
+    udp::endpoint server_;
+    udp::socket socket_;
+    uint32_t sequence_ = 0;
+    bool connected_ = false;
+    bool configured_ = false;
+    bool started_ = false;
+    bool autoReady_ = true;
+    bool checksums_ = false;
+    bool spectator_ = false;
+    std::string failure_;
+    std::optional<uint16_t> kickReason_;
+    std::vector<PlayerDisconnectedPayload> disconnectNotices_;
+    std::vector<std::map<uint32_t, FrameInput>> receivedInputs_;
+};
+
+} // namespace rollback::loopback
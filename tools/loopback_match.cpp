@@ -0,0 +1,146 @@
+// End-to-end check of a whole match over real sockets on 127.0.0.1: a server with
+// an in-process backend and two scripted clients that connect, answer the ping
+// phase, ready up, and trade inputs once StartGame arrives. Passes when each client
+// has received the other's inputs, frame for frame, for FRAMES_TO_CHECK frames.
+// With --solo the match has a single player, who must get its own inputs echoed back.
+// With --restart the server is started, stopped and started again before the match,
+// so the match runs on a restarted server.
+//
+// Build with -DROLLBACK_BUILD_TOOLS=ON and run ./loopback-match [--solo|--restart]; exits
+// non-zero, saying which step never happened, on failure.
+#include "loopback_client.h"
+#include "rollback_server.h"
+#include <asio.hpp>
+#include <chrono>
+#include <iostream>
+#include <memory>
+#include <string>
+#include <thread>
+#include <vector>
+
+using namespace rollback;
+using namespace rollback::loopback;
+
+namespace {
+
+constexpr const char* MATCH_ID = "loopback";
+constexpr const char* MATCH_KEY = "loopback-key";
+constexpr uint32_t FRAMES_TO_CHECK = 30;
+constexpr auto FRAME_INTERVAL = std::chrono::milliseconds(16);
+constexpr auto DEADLINE = std::chrono::seconds(15);
+
+MVSIMatchConfig loopbackConfig(uint8_t numPlayers) {
+    MVSIMatchConfig config;
+    config.max_players = numPlayers;
+    config.match_duration = 36000;
+    for (uint16_t i = 0; i < numPlayers; ++i) {
+        config.players.push_back({ i, "127.0.0.1", i == 0, static_cast<uint16_t>(i % 2) });
+    }
+    return config;
+}
+
+// What every client sends for `frame`, so the other side can check it arrived intact
+uint32_t expectedInput(uint16_t playerIndex, uint32_t frame) {
+    return (static_cast<uint32_t>(playerIndex + 1) << 24) | frame;
+}
+
+// The other player in a 1v1; alone, the player's own inputs are what comes back
+uint16_t watchedPlayer(uint16_t playerIndex, uint8_t numPlayers) {
+    return numPlayers == 1 ? playerIndex : static_cast<uint16_t>(playerIndex == 0 ? 1 : 0);
+}
+
+// Every frame up to `frames` of the watched player's input arrived, and with the right value
+bool hasPeerInputs(const LoopbackClient& client, uint8_t numPlayers, uint32_t frames) {
+    const uint16_t peer = watchedPlayer(client.playerIndex(), numPlayers);
+    const auto& received = client.receivedInputs(peer);
+    for (uint32_t frame = 1; frame <= frames; ++frame) {
+        const auto it = received.find(frame);
+        if (it == received.end() || it->second != FrameInput(expectedInput(peer, frame))) {
+            return false;
+        }
+    }
+    return true;
+}
+
+} // namespace
+
+int main(int argc, char* argv[]) {
+    const std::string mode = argc > 1 ? argv[1] : "";
+    const bool solo = mode == "--solo";
+    const uint8_t numPlayers = solo ? 1 : 2;
+
+    ServerConfig config;
+    config.maxPlayers = numPlayers;
+    config.connectRatePerSecond = 100.0; // both clients share 127.0.0.1
+    config.connectBurst = 10.0;
+
+    // The server's socket is handed in rather than bound from config: an ephemeral
+    // port on 127.0.0.1, a fresh one for each start()
+    auto loopbackSocket = [](asio::io_context& serverIo) {
+        return udp::socket(serverIo, udp::endpoint(asio::ip::make_address("127.0.0.1"), 0));
+    };
+    RollbackServer server(config, std::make_unique<LoopbackBackend>(MATCH_ID, loopbackConfig(numPlayers)), nullptr,
+                          loopbackSocket);
+    server.start();
+    if (mode == "--restart") {
+        server.stop();
+        server.start();
+    }
+
+    asio::io_context io;
+    const udp::endpoint serverEndpoint(asio::ip::make_address("127.0.0.1"), server.localPort());
+    std::vector<LoopbackClient> clients;
+    clients.reserve(numPlayers);
+    for (uint16_t i = 0; i < numPlayers; ++i) {
+        clients.emplace_back(io, ClientIdentity{ i, i, MATCH_ID, MATCH_KEY }, numPlayers, serverEndpoint);
+    }
+    for (auto& client : clients) {
+        client.connect();
+    }
+
+    const auto deadline = std::chrono::steady_clock::now() + DEADLINE;
+    std::vector<uint32_t> nextFrame(numPlayers, 1);
+    std::vector<std::chrono::steady_clock::time_point> lastInputSent(numPlayers);
+    bool passed = false;
+    while (!passed && std::chrono::steady_clock::now() < deadline) {
+        passed = true;
+        for (size_t i = 0; i < clients.size(); ++i) {
+            auto& client = clients[i];
+            client.poll();
+            if (!client.failure().empty()) {
+                std::cerr << "Client failed: " << client.failure() << std::endl;
+                server.stop();
+                return 1;
+            }
+            // One frame of input per FRAME_INTERVAL once the match has started
+            const auto now = std::chrono::steady_clock::now();
+            if (client.started() && now - lastInputSent[i] >= FRAME_INTERVAL) {
+                lastInputSent[i] = now;
+                const uint32_t frame = nextFrame[i]++;
+                client.sendInput(frame, frame, { FrameInput(expectedInput(client.playerIndex(), frame)) });
+            }
+            passed = passed && hasPeerInputs(client, numPlayers, FRAMES_TO_CHECK);
+        }
+        std::this_thread::sleep_for(std::chrono::milliseconds(1));
+    }
+    server.stop();
+
+    if (passed) {
+        if (solo) {
+            std::cout << "The solo client received " << FRAMES_TO_CHECK << " frames of its own input back" << std::endl;
+        } else {
+            std::cout << "Both clients received " << FRAMES_TO_CHECK << " frames of each other's input" << std::endl;
+        }
+        return 0;
+    }
+    for (size_t i = 0; i < clients.size(); ++i) {
+        const auto& client = clients[i];
+        const char* step = !client.connected()    ? "NewConnectionReply"
+                           : !client.configured() ? "PlayersConfigurationData"
+                           : !client.started()    ? "StartGame"
+                           : solo                 ? "its own inputs"
+                                                  : "the peer's inputs";
+        std::cerr << "Client " << i << " never got " << step << std::endl;
+    }
+    return 1;
+}
@@ -0,0 +1,228 @@
+// Regression harness for the input relay. Replays the client side of a match
+// recorded with ServerConfig::replayDirectory against the real RollbackServer over
+// 127.0.0.1: one scripted client per recorded player connects with the recorded
+// match id, key, slot and team, answers the handshake, and once StartGame arrives
+// resends its recorded Input packets at their recorded pace. Passes when every
+// client has received every other player's recorded inputs, frame for frame.
+//
+// The recording is its own golden file: tests/fixtures/two_player.mvsr is a 1v1 of
+// 120 frames, each Input packet carrying its frame and the one before.
+//
+// Build with -DROLLBACK_BUILD_TOOLS=ON and run
+//   ./replay-harness match.mvsr <max_players> [input_width]
+// Exits non-zero, naming the first input that never arrived intact, on failure.
+#include "loopback_client.h"
+#include "replay.h"
+#include "rollback_server.h"
+#include <algorithm>
+#include <chrono>
+#include <iostream>
+#include <map>
+#include <memory>
+#include <optional>
+#include <string>
+#include <thread>
+#include <vector>
+
+using namespace rollback;
+using namespace rollback::loopback;
+
+namespace {
+
+// Time allowed past the last recorded input for the relay to catch up
+constexpr auto SETTLE_TIME = std::chrono::seconds(10);
+
+struct RecordedInput {
+    std::chrono::microseconds offset; // since this player's first Input packet
+    InputPayload payload;
+};
+
+// One player's side of the recording
+struct RecordedPlayer {
+    std::optional<ClientIdentity> identity;
+    std::vector<RecordedInput> inputs;
+    std::map<uint32_t, FrameInput> frames; // what the server must relay for this player
+};
+
+std::map<uint8_t, RecordedPlayer> readRecording(ReplayReader& reader, size_t inputWidth) {
+    std::map<uint8_t, RecordedPlayer> players;
+    std::map<uint8_t, std::chrono::microseconds> firstInput;
+    while (auto record = reader.next()) {
+        if (record->direction != ReplayDirection::ClientToServer || record->playerIndex == REPLAY_NO_PLAYER) {
+            continue;
+        }
+        std::vector<uint8_t> packet;
+        try {
+            packet = decompressPacket(record->datagram, 1024, hasValidChecksum(record->datagram));
+        } catch (const std::runtime_error&) {
+            continue;
+        }
+        const auto message = parseClientMessage(packet, inputWidth);
+        if (!message) {
+            continue;
+        }
+
+        auto& player = players[record->playerIndex];
+        if (const auto* connection = std::get_if<NewConnectionPayload>(&message->payload)) {
+            if (!player.identity) {
+                player.identity = ClientIdentity{ connection->playerData.playerIndex, connection->playerData.teamId,
+                                                  connection->matchData.matchId, connection->matchData.key };
+            }
+        } else if (const auto* input = std::get_if<InputPayload>(&message->payload)) {
+            const auto first = firstInput.try_emplace(record->playerIndex, record->offset).first->second;
+            player.inputs.push_back({ record->offset - first, *input });
+            for (size_t i = 0; i < input->inputPerFrame.size(); ++i) {
+                player.frames.insert_or_assign(input->startFrame + static_cast<uint32_t>(i), input->inputPerFrame[i]);
+            }
+        }
+    }
+    return players;
+}
+
+// The first of `player`'s recorded frames that `client` hasn't received intact, if any
+std::optional<uint32_t> firstMissingFrame(const LoopbackClient& client, uint16_t player, const RecordedPlayer& recorded) {
+    const auto& received = client.receivedInputs(player);
+    for (const auto& [frame, input] : recorded.frames) {
+        const auto it = received.find(frame);
+        if (it == received.end() || it->second != input) {
+            return frame;
+        }
+    }
+    return std::nullopt;
+}
+
+} // namespace
+
+int main(int argc, char* argv[]) {
+    if (argc < 3) {
+        std::cerr << "Usage: " << argv[0] << " <replay.mvsr> <max_players> [input_width]" << std::endl;
+        return 2;
+    }
+
+    ReplayReader reader(argv[1]);
+    if (!reader.isValid()) {
+        std::cerr << argv[1] << " is not a replay file" << std::endl;
+        return 2;
+    }
+
+    int maxPlayers = 0;
+    size_t inputWidth = DEFAULT_INPUT_WIDTH;
+    try {
+        maxPlayers = std::stoi(argv[2]);
+        if (argc > 3) {
+            inputWidth = std::stoul(argv[3]);
+        }
+    } catch (const std::exception&) {
+        std::cerr << "Invalid max_players or input_width" << std::endl;
+        return 2;
+    }
+    if (maxPlayers < 1 || maxPlayers > MAX_MATCH_PLAYERS || inputWidth < 1 || inputWidth > MAX_INPUT_WIDTH) {
+        std::cerr << "max_players must be 1-" << MAX_MATCH_PLAYERS << " and input_width 1-" << MAX_INPUT_WIDTH << std::endl;
+        return 2;
+    }
+
+    // Every slot must have connected in the recording, or there's no roster to give the server
+    const auto recorded = readRecording(reader, inputWidth);
+    MVSIMatchConfig matchConfig;
+    matchConfig.max_players = static_cast<uint8_t>(maxPlayers);
+    matchConfig.match_duration = 36000;
+    std::string matchId;
+    for (const auto& [index, player] : recorded) {
+        if (!player.identity) {
+            std::cerr << "Player " << static_cast<int>(index) << " never connects in the recording" << std::endl;
+            return 2;
+        }
+        matchId = player.identity->matchId;
+        matchConfig.players.push_back({ player.identity->playerIndex, "127.0.0.1", index == 0, player.identity->teamId });
+    }
+    if (recorded.size() != static_cast<size_t>(maxPlayers)) {
+        std::cerr << "The recording has " << recorded.size() << " player(s), expected " << maxPlayers << std::endl;
+        return 2;
+    }
+
+    ServerConfig config;
+    config.port = 0;
+    config.maxPlayers = maxPlayers;
+    config.inputWidth = inputWidth;
+    config.bindAddress = "127.0.0.1";
+    config.connectRatePerSecond = 100.0; // every client shares 127.0.0.1
+    config.connectBurst = 10.0;
+
+    RollbackServer server(config, std::make_unique<LoopbackBackend>(matchId, matchConfig));
+    server.start();
+
+    asio::io_context io;
+    const udp::endpoint serverEndpoint(asio::ip::make_address("127.0.0.1"), server.localPort());
+    std::vector<LoopbackClient> clients;
+    std::vector<const RecordedPlayer*> scripts;
+    std::chrono::microseconds lastOffset{ 0 };
+    clients.reserve(recorded.size());
+    for (const auto& [index, player] : recorded) {
+        clients.emplace_back(io, *player.identity, static_cast<uint8_t>(maxPlayers), serverEndpoint, inputWidth);
+        scripts.push_back(&player);
+        if (!player.inputs.empty()) {
+            lastOffset = std::max(lastOffset, player.inputs.back().offset);
+        }
+    }
+    for (auto& client : clients) {
+        client.connect();
+    }
+
+    // Each client replays its inputs on its own timeline, starting when it sees StartGame
+    std::vector<std::optional<std::chrono::steady_clock::time_point>> startedAt(clients.size());
+    std::vector<size_t> nextInput(clients.size(), 0);
+    const auto deadline = std::chrono::steady_clock::now() + std::chrono::duration_cast<std::chrono::seconds>(lastOffset) + SETTLE_TIME;
+    bool passed = false;
+    while (!passed && std::chrono::steady_clock::now() < deadline) {
+        passed = true;
+        for (size_t i = 0; i < clients.size(); ++i) {
+            auto& client = clients[i];
+            client.poll();
+            if (!client.failure().empty()) {
+                std::cerr << "Client " << client.playerIndex() << " failed: " << client.failure() << std::endl;
+                server.stop();
+                return 1;
+            }
+            const auto now = std::chrono::steady_clock::now();
+            if (client.started() && !startedAt[i]) {
+                startedAt[i] = now;
+            }
+            const auto& inputs = scripts[i]->inputs;
+            while (startedAt[i] && nextInput[i] < inputs.size() && now - *startedAt[i] >= inputs[nextInput[i]].offset) {
+                const auto& payload = inputs[nextInput[i]++].payload;
+                client.sendInput(payload.startFrame, payload.clientFrame, payload.inputPerFrame);
+            }
+            for (size_t peer = 0; peer < scripts.size() && passed; ++peer) {
+                if (peer != i || scripts.size() == 1) {
+                    passed = !firstMissingFrame(client, clients[peer].playerIndex(), *scripts[peer]);
+                }
+            }
+        }
+        std::this_thread::sleep_for(std::chrono::milliseconds(1));
+    }
+    server.stop();
+
+    if (passed) {
+        std::cout << "Every client received every other player's recorded inputs from " << argv[1] << std::endl;
+        return 0;
+    }
+    for (size_t i = 0; i < clients.size(); ++i) {
+        const auto& client = clients[i];
+        if (!client.started()) {
+            std::cerr << "Client " << client.playerIndex() << " never got "
+                      << (!client.connected() ? "NewConnectionReply" : !client.configured() ? "PlayersConfigurationData" : "StartGame")
+                      << std::endl;
+            continue;
+        }
+        for (size_t peer = 0; peer < scripts.size(); ++peer) {
+            if (peer == i && scripts.size() != 1) {
+                continue;
+            }
+            if (const auto frame = firstMissingFrame(client, clients[peer].playerIndex(), *scripts[peer])) {
+                std::cerr << "Client " << client.playerIndex() << " never got player " << clients[peer].playerIndex()
+                          << "'s recorded input for frame " << *frame << std::endl;
+            }
+        }
+    }
+    return 1;
+}